@@ -1,15 +1,13 @@
-use std::path::PathBuf;
 use std::process::Stdio;
 
-use nix::unistd::User;
-use target_lexicon::OperatingSystem;
 use tokio::process::Command;
 use tracing::{span, Span};
 
+use crate::action::base::{UserGroupBackend, UserGroupOperation};
 use crate::action::{ActionError, ActionErrorKind};
 use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, StatefulAction};
+use crate::action::{Action, ActionDescription, StatefulAction, Verification};
 
 /**
 Create an operating system level user in the given group
@@ -18,9 +16,11 @@ Create an operating system level user in the given group
 #[serde(tag = "action_name", rename = "add_user_to_group")]
 pub struct AddUserToGroup {
     name: String,
-    uid: u32,
+    pub(crate) uid: u32,
     groupname: String,
-    gid: u32,
+    pub(crate) gid: u32,
+    #[serde(default = "UserGroupBackend::detect")]
+    backend: UserGroupBackend,
 }
 
 impl AddUserToGroup {
@@ -30,65 +30,56 @@ impl AddUserToGroup {
         uid: u32,
         groupname: String,
         gid: u32,
+        backend: UserGroupBackend,
     ) -> Result<StatefulAction<Self>, ActionError> {
-        let this = Self {
+        let mut this = Self {
             name: name.clone(),
             uid,
             groupname: groupname.clone(),
             gid,
+            backend,
         };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => (),
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if !which::which("synogroup").is_ok() {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyUserCreationCommand,
-                        ));
-                    }
-                } else {
-                    if !(which::which("addgroup").is_ok() || which::which("gpasswd").is_ok()) {
-                        return Err(Self::error(ActionErrorKind::MissingAddUserToGroupCommand));
-                    }
-                    if !(which::which("delgroup").is_ok() || which::which("gpasswd").is_ok()) {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingRemoveUserFromGroupCommand,
-                        ));
-                    }
-                }
-            },
-        }
+        this.backend
+            .ensure_available(&UserGroupOperation::AddUserToGroup {
+                name: &name,
+                groupname: &groupname,
+            })
+            .map_err(Self::error)?;
+        this.backend
+            .ensure_available(&UserGroupOperation::RemoveUserFromGroup {
+                name: &name,
+                groupname: &groupname,
+            })
+            .map_err(Self::error)?;
 
         // Ensure user does not exists
-        if let Some(user) = User::from_name(name.as_str())
-            .map_err(|e| ActionErrorKind::GettingUserId(name.clone(), e))
-            .map_err(Self::error)?
-        {
-            if user.uid.as_raw() != uid {
+        if let Some(user) = this.backend.user(&name).map_err(Self::error)? {
+            if !this.backend.honours_requested_ids() {
+                // The IDs were allocated by the backend when the user was created, they are authoritative
+                this.uid = user.uid;
+                this.gid = user.gid;
+            }
+
+            if user.uid != this.uid {
                 return Err(Self::error(ActionErrorKind::UserUidMismatch(
                     name.clone(),
-                    user.uid.as_raw(),
+                    user.uid,
                     uid,
                 )));
             }
 
-            if user.gid.as_raw() != gid {
+            if user.gid != this.gid {
                 return Err(Self::error(ActionErrorKind::UserGidMismatch(
                     name.clone(),
-                    user.gid.as_raw(),
+                    user.gid,
                     gid,
                 )));
             }
 
             // See if group membership needs to be done
-            match OperatingSystem::host() {
-                OperatingSystem::MacOSX {
-                    major: _,
-                    minor: _,
-                    patch: _,
-                }
-                | OperatingSystem::Darwin => {
+            match this.backend {
+                UserGroupBackend::Dscl => {
                     let mut command = Command::new("/usr/sbin/dseditgroup");
                     command.process_group(0);
                     command.args(["-o", "checkmember", "-m"]);
@@ -130,6 +121,23 @@ impl AddUserToGroup {
                         },
                     };
                 },
+                UserGroupBackend::Files { .. } => {
+                    // `groups` would ask the host, not the root being installed into
+                    let user_in_group = this
+                        .backend
+                        .group(&this.groupname)
+                        .map_err(Self::error)?
+                        .is_some_and(|group| group.members.contains(&this.name));
+
+                    if user_in_group {
+                        tracing::debug!(
+                            "Adding user `{}` to group `{}` already complete",
+                            this.name,
+                            this.groupname
+                        );
+                        return Ok(StatefulAction::completed(this));
+                    }
+                },
                 _ => {
                     let output = execute_command(
                         Command::new("groups")
@@ -198,88 +206,13 @@ impl Action for AddUserToGroup {
             uid: _,
             groupname,
             gid: _,
+            backend,
         } = self;
 
-        use target_lexicon::OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .process_group(0)
-                        .args([
-                            ".",
-                            "-append",
-                            &format!("/Groups/{groupname}"),
-                            "GroupMembership",
-                        ])
-                        .arg(&name)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-                execute_command(
-                    Command::new("/usr/sbin/dseditgroup")
-                        .process_group(0)
-                        .args(["-o", "edit"])
-                        .arg("-a")
-                        .arg(&name)
-                        .arg("-t")
-                        .arg(&name)
-                        .arg(groupname)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if which::which("synogroup").is_ok() {
-                        execute_command(
-                            Command::new("synogroup")
-                                .process_group(0)
-                                .args(["--member", groupname, &name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyUserCreationCommand,
-                        ));
-                    }
-                } else {
-                    if which::which("gpasswd").is_ok() {
-                        execute_command(
-                            Command::new("gpasswd")
-                                .process_group(0)
-                                .args(["-a"])
-                                .args([name, groupname])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else if which::which("addgroup").is_ok() {
-                        execute_command(
-                            Command::new("addgroup")
-                                .process_group(0)
-                                .args([name, groupname])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(Self::error(
-                            ActionErrorKind::MissingAddUserToGroupCommand,
-                        )));
-                    }
-                }
-            },
-        }
+        backend
+            .execute(&UserGroupOperation::AddUserToGroup { name, groupname })
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
@@ -303,71 +236,55 @@ impl Action for AddUserToGroup {
             uid: _,
             groupname,
             gid: _,
+            backend,
         } = self;
 
-        use target_lexicon::OperatingSystem;
-        match target_lexicon::OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .process_group(0)
-                        .args([".", "-delete", &format!("/Groups/{groupname}"), "users"])
-                        .arg(&name)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if which::which("synogroup").is_ok() {
-                        execute_command(
-                            Command::new("synogroup")
-                                .process_group(0)
-                                .args(["--del", &self.name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyUserDeletionCommand,
-                        ));
-                    }
-                } else {
-                    if which::which("gpasswd").is_ok() {
-                        execute_command(
-                            Command::new("gpasswd")
-                                .process_group(0)
-                                .args(["-d"])
-                                .args([&name.to_string(), &groupname.to_string()])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else if which::which("delgroup").is_ok() {
-                        execute_command(
-                            Command::new("delgroup")
-                                .process_group(0)
-                                .args([name, groupname])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingRemoveUserFromGroupCommand,
-                        ));
-                    }
-                }
-            },
-        };
+        backend
+            .execute(&UserGroupOperation::RemoveUserFromGroup { name, groupname })
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let group = self
+            .backend
+            .group(&self.groupname)
+            .map_err(Self::error)?
+            .ok_or_else(|| ActionErrorKind::NoGroup(self.groupname.clone()))
+            .map_err(Self::error)?;
+        let user = self
+            .backend
+            .user(&self.name)
+            .map_err(Self::error)?
+            .ok_or_else(|| ActionErrorKind::NoUser(self.name.clone()))
+            .map_err(Self::error)?;
+        if user.gid == group.gid || group.members.contains(&self.name) {
+            return Ok(vec![]);
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let group = self.backend.group(&self.groupname).map_err(Self::error)?;
+        let user = self.backend.user(&self.name).map_err(Self::error)?;
+        match (user, group) {
+            (Some(user), Some(group))
+                if user.gid == group.gid || group.members.contains(&self.name) =>
+            {
+                Ok(Verification::Pass)
+            },
+            // A missing user or group is reported by the action which created it
+            (None, _) | (_, None) => Ok(Verification::Unverified),
+            (Some(_), Some(_)) => Ok(Verification::Drift(vec![format!(
+                "User `{}` is not a member of group `{}`",
+                self.name, self.groupname
+            )])),
+        }
+    }
 }
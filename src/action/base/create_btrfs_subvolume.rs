@@ -0,0 +1,446 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{create_dir_all, remove_dir_all};
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::action::base::create_directory::{path_is_mountpoint, remove_contents};
+use crate::action::base::create_file::ownership_and_mode_drift;
+use crate::action::{Action, ActionDescription, ActionErrorKind, ActionState};
+use crate::action::{ActionError, StatefulAction, Verification};
+use crate::execute_command;
+
+/// The inode number of the root directory of every btrfs subvolume
+const BTRFS_SUBVOLUME_ROOT_INODE: u64 = 256;
+
+/** Create a btrfs subvolume at the given location, optionally with a qgroup size limit, and mode.
+
+If the location is not on btrfs (or `btrfs` is not in `PATH`) a plain directory is created instead. An existing location
+is left as it is, and a quota given for it is not applied.
+Like [`CreateDirectory`](crate::action::base::CreateDirectory), the subvolume or directory is deleted on
+[`revert`](CreateBtrfsSubvolume::revert) if it is empty, or always if `force_prune_on_revert` is set. Only the contents
+of a mountpoint are deleted, and only if `force_prune_on_revert` is set.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_btrfs_subvolume")]
+pub struct CreateBtrfsSubvolume {
+    path: PathBuf,
+    mode: Option<u32>,
+    quota: Option<String>,
+    is_subvolume: bool,
+    #[serde(default)]
+    is_mountpoint: bool,
+    #[serde(default = "default_force_prune_on_revert")]
+    force_prune_on_revert: bool,
+}
+
+/// Receipts from before `force_prune_on_revert` was recorded always deleted everything
+fn default_force_prune_on_revert() -> bool {
+    true
+}
+
+impl CreateBtrfsSubvolume {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        path: impl AsRef<Path>,
+        mode: impl Into<Option<u32>>,
+        quota: Option<String>,
+        force_prune_on_revert: bool,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let path = path.as_ref().to_path_buf();
+        let mode = mode.into();
+        let mut is_mountpoint = false;
+
+        let (is_subvolume, action_state) = if path.exists() {
+            let metadata = tokio::fs::metadata(&path)
+                .await
+                .map_err(|e| ActionErrorKind::GettingMetadata(path.clone(), e))
+                .map_err(Self::error)?;
+            if !metadata.is_dir() {
+                return Err(Self::error(ActionErrorKind::PathWasNotDirectory(
+                    path.to_owned(),
+                )));
+            }
+
+            let is_subvolume = is_on_btrfs(&path) && metadata.ino() == BTRFS_SUBVOLUME_ROOT_INODE;
+            is_mountpoint = path_is_mountpoint(&path).await.map_err(Self::error)?;
+            if let Some(quota) = &quota {
+                tracing::warn!(
+                    "`{}` already exists, not limiting it to {quota}, run `btrfs qgroup limit {quota} {}` to do so",
+                    path.display(),
+                    path.display(),
+                );
+            }
+            tracing::debug!(
+                is_subvolume,
+                is_mountpoint,
+                "Creating subvolume `{}` already complete",
+                path.display(),
+            );
+            (is_subvolume, ActionState::Completed)
+        } else {
+            let existing_ancestor = path
+                .ancestors()
+                .find(|ancestor| ancestor.exists())
+                .unwrap_or(Path::new("/"));
+            let is_subvolume = is_on_btrfs(existing_ancestor) && which::which("btrfs").is_ok();
+            if !is_subvolume && quota.is_some() {
+                tracing::warn!(
+                    "`{}` is not on btrfs, creating a plain directory without the quota",
+                    existing_ancestor.display()
+                );
+            }
+            (is_subvolume, ActionState::Uncompleted)
+        };
+
+        Ok(StatefulAction {
+            action: Self {
+                path,
+                mode,
+                quota,
+                is_subvolume,
+                is_mountpoint,
+                force_prune_on_revert,
+            },
+            state: action_state,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "create_btrfs_subvolume")]
+impl Action for CreateBtrfsSubvolume {
+    fn action_tag() -> crate::action::ActionTag {
+        crate::action::ActionTag("create_btrfs_subvolume")
+    }
+    fn tracing_synopsis(&self) -> String {
+        if self.is_subvolume {
+            format!("Create btrfs subvolume `{}`", self.path.display())
+        } else {
+            format!("Create directory `{}`", self.path.display())
+        }
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_btrfs_subvolume",
+            path = tracing::field::display(self.path.display()),
+            mode = self
+                .mode
+                .map(|v| tracing::field::display(format!("{:#o}", v))),
+            quota = self.quota,
+            is_subvolume = self.is_subvolume,
+            is_mountpoint = self.is_mountpoint,
+            force_prune_on_revert = self.force_prune_on_revert,
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        match (self.is_subvolume, &self.quota) {
+            (true, Some(quota)) => {
+                explanation.push(format!(
+                    "Run `btrfs subvolume create {}`",
+                    self.path.display()
+                ));
+                explanation.push(format!("Run `btrfs quota enable {}`", self.path.display()));
+                explanation.push(format!(
+                    "Run `btrfs qgroup limit {quota} {}`",
+                    self.path.display()
+                ));
+            },
+            (true, None) => explanation.push(format!(
+                "Run `btrfs subvolume create {}`",
+                self.path.display()
+            )),
+            (false, _) => explanation
+                .push("The location is not on btrfs, so a plain directory is used".to_string()),
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        let Self {
+            path,
+            mode,
+            quota,
+            is_subvolume,
+            is_mountpoint: _,
+            force_prune_on_revert: _,
+        } = self;
+
+        if *is_subvolume {
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)
+                    .await
+                    .map_err(|e| ActionErrorKind::CreateDirectory(parent.to_path_buf(), e))
+                    .map_err(Self::error)?;
+            }
+            execute_command(
+                Command::new("btrfs")
+                    .process_group(0)
+                    .args(["subvolume", "create"])
+                    .arg(&path)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .map_err(Self::error)?;
+
+            if let Some(quota) = quota {
+                // Enabling quotas is filesystem wide, and does nothing if they already are
+                execute_command(
+                    Command::new("btrfs")
+                        .process_group(0)
+                        .args(["quota", "enable"])
+                        .arg(&path)
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)?;
+                execute_command(
+                    Command::new("btrfs")
+                        .process_group(0)
+                        .args(["qgroup", "limit"])
+                        .arg(quota.as_str())
+                        .arg(&path)
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)?;
+            }
+        } else {
+            create_dir_all(&path)
+                .await
+                .map_err(|e| ActionErrorKind::CreateDirectory(path.clone(), e))
+                .map_err(Self::error)?;
+        }
+
+        if let Some(mode) = mode {
+            tokio::fs::set_permissions(&path, PermissionsExt::from_mode(*mode))
+                .await
+                .map_err(|e| ActionErrorKind::SetPermissions(*mode, path.to_owned(), e))
+                .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        let unless_contents = if self.force_prune_on_revert {
+            ""
+        } else {
+            " if no other contents exists"
+        };
+        match (
+            self.is_mountpoint,
+            self.force_prune_on_revert,
+            self.is_subvolume,
+        ) {
+            (true, true, _) => vec![ActionDescription::new(
+                format!("Clean contents of mountpoint `{}`", self.path.display()),
+                vec![],
+            )],
+            (true, false, _) => vec![],
+            (false, _, true) => vec![ActionDescription::new(
+                format!(
+                    "Delete the btrfs subvolume `{}`{unless_contents}",
+                    self.path.display()
+                ),
+                vec![format!(
+                    "Run `btrfs subvolume delete {}`",
+                    self.path.display()
+                )],
+            )],
+            (false, _, false) => vec![ActionDescription::new(
+                format!(
+                    "Remove the directory `{}`{unless_contents}",
+                    self.path.display()
+                ),
+                vec![],
+            )],
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        let Self {
+            path,
+            is_subvolume,
+            is_mountpoint,
+            force_prune_on_revert,
+            ..
+        } = self;
+        if !path.exists() {
+            tracing::debug!("`{}` is already gone", path.display());
+            return Ok(());
+        }
+
+        let contents = path
+            .read_dir()
+            .map_err(|e| ActionErrorKind::Read(path.clone(), e))
+            .map_err(Self::error)?
+            .collect::<Vec<_>>();
+        let is_empty = contents.is_empty();
+
+        match (is_mountpoint, is_empty, force_prune_on_revert) {
+            (true, _, true) => {
+                tracing::debug!("Cleaning mountpoint `{}`", path.display());
+                remove_contents(path, contents).await.map_err(Self::error)?;
+            },
+            (true, _, false) => {
+                tracing::debug!("Not cleaning mountpoint `{}`", path.display());
+            },
+            (false, true, _) | (false, false, true) if *is_subvolume => {
+                execute_command(
+                    Command::new("btrfs")
+                        .process_group(0)
+                        .args(["subvolume", "delete"])
+                        .arg(&path)
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)?;
+            },
+            (false, true, _) | (false, false, true) => remove_dir_all(&path)
+                .await
+                .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
+                .map_err(Self::error)?,
+            (false, false, false) => {
+                tracing::debug!("Not removing `{}`, the folder is not empty", path.display());
+            },
+        };
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        if self.path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let metadata = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Verification::Drift(vec![format!(
+                    "`{}` is missing",
+                    self.path.display()
+                )]))
+            },
+            Err(e) => {
+                return Err(Self::error(ActionErrorKind::GettingMetadata(
+                    self.path.clone(),
+                    e,
+                )))
+            },
+        };
+        if !metadata.is_dir() {
+            return Ok(Verification::Drift(vec![
+                ActionErrorKind::PathWasNotDirectory(self.path.clone()).to_string(),
+            ]));
+        }
+
+        ownership_and_mode_drift(&self.path, &metadata, None, None, self.mode)
+            .map(Verification::from_drift)
+            .map_err(Self::error)
+    }
+}
+
+/// Whether `path` is on a btrfs filesystem
+#[cfg(target_os = "linux")]
+fn is_on_btrfs(path: &Path) -> bool {
+    nix::sys::statfs::statfs(path)
+        .is_ok_and(|stat| stat.filesystem_type() == nix::sys::statfs::BTRFS_SUPER_MAGIC)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_on_btrfs(_path: &Path) -> bool {
+    false
+}
+
+/// Parse a `btrfs qgroup limit` size, a number of bytes with an optional `K`, `M`, `G` or `T` suffix
+pub fn parse_qgroup_limit(input: &str) -> Result<String, String> {
+    let digits = input
+        .strip_suffix(['K', 'M', 'G', 'T', 'k', 'm', 'g', 't'])
+        .unwrap_or(input);
+    match digits.parse::<u64>() {
+        Ok(size) if size > 0 => Ok(input.to_string()),
+        _ => Err(format!("`{input}` is not a size, such as `500G` or `2T`")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_qgroup_limit() {
+        assert_eq!(parse_qgroup_limit("500G"), Ok("500G".to_string()));
+        assert_eq!(
+            parse_qgroup_limit("1073741824"),
+            Ok("1073741824".to_string())
+        );
+        assert!(parse_qgroup_limit("0").is_err());
+        assert!(parse_qgroup_limit("lots").is_err());
+        assert!(parse_qgroup_limit("G").is_err());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_directory_off_btrfs() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        if is_on_btrfs(temp_dir.path()) {
+            // Creating a subvolume needs root, this test covers the fallback
+            return Ok(());
+        }
+        let test_dir = temp_dir.path().join("falls_back_to_directory_off_btrfs");
+        let mut action =
+            CreateBtrfsSubvolume::plan(test_dir.clone(), 0o755, Some("1G".into()), true).await?;
+
+        action.try_execute().await?;
+
+        assert!(test_dir.is_dir(), "Directory should have been created");
+        tokio::fs::write(test_dir.join("stub"), "More content").await?;
+
+        action.try_revert().await?;
+
+        assert!(!test_dir.exists(), "Directory should have been deleted");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keeps_other_contents_without_force_prune() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        if is_on_btrfs(temp_dir.path()) {
+            return Ok(());
+        }
+        let test_dir = temp_dir
+            .path()
+            .join("keeps_other_contents_without_force_prune");
+        let mut action = CreateBtrfsSubvolume::plan(test_dir.clone(), 0o755, None, false).await?;
+
+        action.try_execute().await?;
+        tokio::fs::write(test_dir.join("stub"), "More content").await?;
+        action.try_revert().await?;
+        assert!(test_dir.join("stub").exists(), "Contents should be kept");
+
+        tokio::fs::remove_file(test_dir.join("stub")).await?;
+        let mut action = CreateBtrfsSubvolume::plan(test_dir.clone(), 0o755, None, false).await?;
+        action.try_revert().await?;
+        assert!(
+            !test_dir.exists(),
+            "Empty directory should have been deleted"
+        );
+
+        Ok(())
+    }
+}
@@ -7,8 +7,9 @@ use tokio::fs::{create_dir_all, remove_dir_all, remove_file};
 use tokio::process::Command;
 use tracing::{span, Span};
 
+use crate::action::base::create_file::ownership_and_mode_drift;
 use crate::action::{Action, ActionDescription, ActionErrorKind, ActionState};
-use crate::action::{ActionError, StatefulAction};
+use crate::action::{ActionError, StatefulAction, Verification};
 use crate::execute_command;
 
 /** Create a directory at the given location, optionally with an owning user, group, and mode.
@@ -252,27 +253,7 @@ impl Action for CreateDirectory {
         match (is_mountpoint, is_empty, force_prune_on_revert) {
             (true, _, true) => {
                 tracing::debug!("Cleaning mountpoint `{}`", path.display());
-                for child_path in contents {
-                    let child_path = child_path
-                        .map_err(|e| ActionErrorKind::ReadDir(path.clone(), e))
-                        .map_err(Self::error)?;
-                    let child_path_path = child_path.path();
-                    let child_path_type = child_path
-                        .file_type()
-                        .map_err(|e| ActionErrorKind::GettingMetadata(child_path_path.clone(), e))
-                        .map_err(Self::error)?;
-                    if child_path_type.is_dir() {
-                        remove_dir_all(child_path_path.clone())
-                            .await
-                            .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
-                            .map_err(Self::error)?
-                    } else {
-                        remove_file(child_path_path)
-                            .await
-                            .map_err(|e| ActionErrorKind::Remove(path.clone(), e))
-                            .map_err(Self::error)?
-                    }
-                }
+                remove_contents(path, contents).await.map_err(Self::error)?;
             },
             (true, _, false) => {
                 tracing::debug!("Not cleaning mountpoint `{}`", path.display());
@@ -288,10 +269,82 @@ impl Action for CreateDirectory {
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        if self.is_mountpoint || self.path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let metadata = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Verification::Drift(vec![format!(
+                    "`{}` is missing",
+                    self.path.display()
+                )]))
+            },
+            Err(e) => {
+                return Err(Self::error(ActionErrorKind::GettingMetadata(
+                    self.path.clone(),
+                    e,
+                )))
+            },
+        };
+        if !metadata.is_dir() {
+            return Ok(Verification::Drift(vec![
+                ActionErrorKind::PathWasNotDirectory(self.path.clone()).to_string(),
+            ]));
+        }
+        // A mountpoint was never created nor changed by the installer
+        if self.is_mountpoint {
+            return Ok(Verification::Pass);
+        }
+
+        ownership_and_mode_drift(
+            &self.path,
+            &metadata,
+            self.user.as_deref(),
+            self.group.as_deref(),
+            self.mode,
+        )
+        .map(Verification::from_drift)
+        .map_err(Self::error)
+    }
+}
+
+/// Remove the `contents` of the directory `path`, but not `path` itself, such as when it is a mountpoint
+pub(crate) async fn remove_contents(
+    path: &Path,
+    contents: Vec<std::io::Result<std::fs::DirEntry>>,
+) -> Result<(), ActionErrorKind> {
+    for child_path in contents {
+        let child_path = child_path.map_err(|e| ActionErrorKind::ReadDir(path.to_path_buf(), e))?;
+        let child_path_path = child_path.path();
+        let child_path_type = child_path
+            .file_type()
+            .map_err(|e| ActionErrorKind::GettingMetadata(child_path_path.clone(), e))?;
+        if child_path_type.is_dir() {
+            remove_dir_all(child_path_path.clone())
+                .await
+                .map_err(|e| ActionErrorKind::Remove(path.to_path_buf(), e))?
+        } else {
+            remove_file(child_path_path)
+                .await
+                .map_err(|e| ActionErrorKind::Remove(path.to_path_buf(), e))?
+        }
+    }
+    Ok(())
 }
 
 // There are cleaner ways of doing this (eg `systemctl status $PATH`) however we need a widely supported way.
-async fn path_is_mountpoint(path: &Path) -> Result<bool, ActionErrorKind> {
+pub(crate) async fn path_is_mountpoint(path: &Path) -> Result<bool, ActionErrorKind> {
     let path_str = match path.to_str() {
         Some(path_str) => path_str,
         None => return Err(ActionErrorKind::PathNoneString(path.to_path_buf())),
@@ -10,15 +10,17 @@ use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
+use crate::action::base::file_backup::{default_backup_dir, FileBackup, DRIFT_SUFFIX};
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
 };
 
 /** Create a file at the given location with the provided `buf`,
 optionally with an owning user, group, and mode.
 
-If `force` is set, the file will always be overwritten (and deleted)
-regardless of its presence prior to install.
+If `force` is set, the file will always be overwritten regardless of its presence prior
+to install. An existing file is backed up first and restored on revert, otherwise it is deleted.
  */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_file")]
@@ -27,8 +29,13 @@ pub struct CreateFile {
     user: Option<String>,
     group: Option<String>,
     mode: Option<u32>,
-    buf: String,
+    pub(crate) buf: String,
     force: bool,
+    #[serde(default)]
+    backup: Option<FileBackup>,
+    /// Where a file overwritten with `force` is backed up to, see [`BACKUP_DIR`](crate::action::base::file_backup::BACKUP_DIR)
+    #[serde(default = "default_backup_dir")]
+    backup_dir: PathBuf,
 }
 
 impl CreateFile {
@@ -40,6 +47,7 @@ impl CreateFile {
         mode: impl Into<Option<u32>>,
         buf: String,
         force: bool,
+        backup_dir: impl AsRef<Path>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let path = path.as_ref().to_path_buf();
         let mode = mode.into();
@@ -52,6 +60,8 @@ impl CreateFile {
             mode,
             buf,
             force,
+            backup: None,
+            backup_dir: backup_dir.as_ref().to_path_buf(),
         };
 
         if this.path.exists() {
@@ -71,6 +81,11 @@ impl CreateFile {
                 return Err(Self::error(ActionErrorKind::PathWasNotFile(this.path)));
             }
 
+            // It is backed up and overwritten, whatever it contains
+            if this.force {
+                return Ok(StatefulAction::uncompleted(this));
+            }
+
             if let Some(mode) = mode {
                 // Does the file have the right permissions?
                 let discovered_mode = metadata.permissions().mode();
@@ -184,7 +199,9 @@ impl Action for CreateFile {
             group,
             mode,
             buf,
-            force: _,
+            force,
+            backup,
+            backup_dir,
         } = self;
 
         if tracing::enabled!(tracing::Level::TRACE) {
@@ -192,8 +209,19 @@ impl Action for CreateFile {
             span.record("buf", &buf);
         }
 
+        // Keep the first backup, a repair recreating a removed file must not replace it
+        if *force && backup.is_none() {
+            *backup = FileBackup::create(path, backup_dir)
+                .await
+                .map_err(Self::error)?;
+        }
+
         let mut options = OpenOptions::new();
-        options.create_new(true).write(true).read(true);
+        if *force {
+            options.create(true).truncate(true).write(true).read(true);
+        } else {
+            options.create_new(true).write(true).read(true);
+        }
 
         if let Some(mode) = mode {
             options.mode(*mode);
@@ -238,6 +266,17 @@ impl Action for CreateFile {
             .map_err(|e| ActionErrorKind::Chown(path.clone(), e))
             .map_err(Self::error)?;
 
+        if let Some(backup) = backup {
+            // Opening an existing file does not change its mode
+            if let Some(mode) = mode {
+                tokio::fs::set_permissions(&path, PermissionsExt::from_mode(*mode))
+                    .await
+                    .map_err(|e| ActionErrorKind::SetPermissions(*mode, path.to_owned(), e))
+                    .map_err(Self::error)?;
+            }
+            backup.record_installed(path).await.map_err(Self::error)?;
+        }
+
         Ok(())
     }
 
@@ -249,8 +288,21 @@ impl Action for CreateFile {
             mode: _,
             buf: _,
             force: _,
+            backup,
+            backup_dir: _,
         } = &self;
 
+        if let Some(backup) = backup {
+            return vec![ActionDescription::new(
+                format!("Restore the original `{}`", path.display()),
+                vec![format!(
+                    "Restore `{}` from `{}`, keeping any changes made since Nix was installed in `{}{DRIFT_SUFFIX}`",
+                    path.display(),
+                    backup.original.display(),
+                    path.display(),
+                )],
+            )];
+        }
         vec![ActionDescription::new(
             format!("Delete file `{}`", path.display()),
             vec![format!("Delete file `{}`", path.display())],
@@ -266,7 +318,15 @@ impl Action for CreateFile {
             mode: _,
             buf: _,
             force: _,
+            backup,
+            backup_dir: _,
         } = self;
+
+        if let Some(backup) = backup {
+            backup.restore(path).await.map_err(Self::error)?;
+            return Ok(());
+        }
+
         // The user already deleted it
         if !path.exists() {
             return Ok(());
@@ -279,6 +339,117 @@ impl Action for CreateFile {
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        // A file which was since edited is left alone, only a missing one is restored
+        if self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let metadata = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Verification::Drift(vec![format!(
+                    "`{}` is missing",
+                    self.path.display()
+                )]))
+            },
+            Err(e) => {
+                return Err(Self::error(ActionErrorKind::GettingMetadata(
+                    self.path.clone(),
+                    e,
+                )))
+            },
+        };
+        if !metadata.is_file() {
+            return Ok(Verification::Drift(vec![ActionErrorKind::PathWasNotFile(
+                self.path.clone(),
+            )
+            .to_string()]));
+        }
+
+        let mut drift = ownership_and_mode_drift(
+            &self.path,
+            &metadata,
+            self.user.as_deref(),
+            self.group.as_deref(),
+            self.mode,
+        )
+        .map_err(Self::error)?;
+        let buf = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| ActionErrorKind::Read(self.path.clone(), e))
+            .map_err(Self::error)?;
+        if buf != self.buf {
+            drift.push(ActionErrorKind::DifferentContent(self.path.clone()).to_string());
+        }
+
+        Ok(Verification::from_drift(drift))
+    }
+}
+
+/// Describe how the owner, group and mode of `path` differ from those planned
+pub(crate) fn ownership_and_mode_drift(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    user: Option<&str>,
+    group: Option<&str>,
+    mode: Option<u32>,
+) -> Result<Vec<String>, ActionErrorKind> {
+    let mut drift = vec![];
+    if let Some(user) = user {
+        match User::from_name(user).map_err(|e| ActionErrorKind::GettingUserId(user.into(), e))? {
+            Some(user) if user.uid.as_raw() == metadata.uid() => (),
+            Some(user) => drift.push(
+                ActionErrorKind::PathUserMismatch(
+                    path.to_path_buf(),
+                    metadata.uid(),
+                    user.uid.as_raw(),
+                )
+                .to_string(),
+            ),
+            None => drift.push(format!(
+                "`{}` should be owned by user `{user}`, which is missing",
+                path.display()
+            )),
+        }
+    }
+    if let Some(group) = group {
+        match Group::from_name(group)
+            .map_err(|e| ActionErrorKind::GettingGroupId(group.into(), e))?
+        {
+            Some(group) if group.gid.as_raw() == metadata.gid() => (),
+            Some(group) => drift.push(
+                ActionErrorKind::PathGroupMismatch(
+                    path.to_path_buf(),
+                    metadata.gid(),
+                    group.gid.as_raw(),
+                )
+                .to_string(),
+            ),
+            None => drift.push(format!(
+                "`{}` should be owned by group `{group}`, which is missing",
+                path.display()
+            )),
+        }
+    }
+    if let Some(mode) = mode {
+        let discovered_mode = metadata.permissions().mode() & 0o777;
+        if discovered_mode != mode {
+            drift.push(
+                ActionErrorKind::PathModeMismatch(path.to_path_buf(), discovered_mode, mode)
+                    .to_string(),
+            );
+        }
+    }
+    Ok(drift)
 }
 
 #[cfg(test)]
@@ -291,8 +462,16 @@ mod test {
     async fn creates_and_deletes_file() -> eyre::Result<()> {
         let temp_dir = tempfile::tempdir()?;
         let test_file = temp_dir.path().join("creates_and_deletes_file");
-        let mut action =
-            CreateFile::plan(test_file.clone(), None, None, None, "Test".into(), false).await?;
+        let mut action = CreateFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            None,
+            "Test".into(),
+            false,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
 
         action.try_execute().await?;
 
@@ -309,8 +488,16 @@ mod test {
         let test_file = temp_dir
             .path()
             .join("creates_and_deletes_file_even_if_edited");
-        let mut action =
-            CreateFile::plan(test_file.clone(), None, None, None, "Test".into(), false).await?;
+        let mut action = CreateFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            None,
+            "Test".into(),
+            false,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
 
         action.try_execute().await?;
 
@@ -340,6 +527,7 @@ mod test {
             None,
             test_content.into(),
             false,
+            temp_dir.path().join("backups"),
         )
         .await?;
 
@@ -368,6 +556,7 @@ mod test {
             None,
             "Some different content".into(),
             false,
+            temp_dir.path().join("backups"),
         )
         .await
         {
@@ -409,6 +598,7 @@ mod test {
             Some(expected_mode),
             "Some different content".into(),
             false,
+            temp_dir.path().join("backups"),
         )
         .await
         {
@@ -453,6 +643,7 @@ mod test {
             Some(initial_mode),
             "Some content".into(),
             false,
+            temp_dir.path().join("backups"),
         )
         .await?;
 
@@ -476,6 +667,7 @@ mod test {
             None,
             "Some different content".into(),
             false,
+            temp_dir.path().join("backups"),
         )
         .await
         {
@@ -496,4 +688,117 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn repairs_only_missing_file() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("repairs_only_missing_file");
+        let mut action = CreateFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            None,
+            "Test".into(),
+            false,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+
+        action.try_execute().await?;
+        assert!(action.try_repair().await?.is_empty());
+
+        remove_file(&test_file).await?;
+        assert_eq!(action.try_repair().await?.len(), 1);
+        assert_eq!(tokio::fs::read_to_string(&test_file).await?, "Test");
+
+        write(&test_file, "Edited").await?;
+        assert!(action.try_repair().await?.is_empty());
+        assert_eq!(tokio::fs::read_to_string(&test_file).await?, "Edited");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verifies_content_and_mode() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("verifies_content_and_mode");
+        let mut action = CreateFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            0o644,
+            "Test".into(),
+            false,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+
+        assert_eq!(action.try_verify().await?, Verification::Unverified);
+        action.try_execute().await?;
+        assert_eq!(action.try_verify().await?, Verification::Pass);
+
+        tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(0o600)).await?;
+        write(&test_file, "Edited").await?;
+        match action.try_verify().await? {
+            Verification::Drift(drift) => assert_eq!(drift.len(), 2, "{drift:?}"),
+            verification => return Err(eyre!("Expected drift, got {verification:?}")),
+        }
+
+        remove_file(&test_file).await?;
+        assert_eq!(
+            action.try_verify().await?,
+            Verification::Drift(vec![format!("`{}` is missing", test_file.display())])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_exact_original() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("restores_exact_original");
+        // No trailing newline, and a different mode than the one planned
+        let original_content = "Some content";
+        write(&test_file, original_content).await?;
+        tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(0o640)).await?;
+
+        let mut action = CreateFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            0o644,
+            "Test\n".into(),
+            true,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+
+        action.try_execute().await?;
+
+        assert_eq!(tokio::fs::read_to_string(&test_file).await?, "Test\n");
+        assert_eq!(
+            tokio::fs::metadata(&test_file).await?.permissions().mode() & 0o777,
+            0o644
+        );
+
+        // Edits after install are kept aside, not merged
+        write(&test_file, "Test\nEdited\n").await?;
+
+        action.try_revert().await?;
+
+        assert_eq!(
+            tokio::fs::read_to_string(&test_file).await?,
+            original_content
+        );
+        assert_eq!(
+            tokio::fs::metadata(&test_file).await?.permissions().mode() & 0o777,
+            0o640
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(format!("{}{DRIFT_SUFFIX}", test_file.display())).await?,
+            "Test\nEdited\n"
+        );
+
+        Ok(())
+    }
 }
@@ -1,13 +1,9 @@
-use nix::unistd::Group;
-use std::path::PathBuf;
-use target_lexicon::OperatingSystem;
-use tokio::process::Command;
 use tracing::{span, Span};
 
+use crate::action::base::{UserGroupBackend, UserGroupOperation};
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
-use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, StatefulAction};
+use crate::action::{Action, ActionDescription, StatefulAction, Verification};
 
 /**
 Create an operating system level user group
@@ -16,46 +12,42 @@ Create an operating system level user group
 #[serde(tag = "action_name", rename = "create_group")]
 pub struct CreateGroup {
     name: String,
-    gid: u32,
+    pub(crate) gid: u32,
+    #[serde(default = "UserGroupBackend::detect")]
+    backend: UserGroupBackend,
 }
 
 impl CreateGroup {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub fn plan(name: String, gid: u32) -> Result<StatefulAction<Self>, ActionError> {
-        let this = Self {
+    pub fn plan(
+        name: String,
+        gid: u32,
+        backend: UserGroupBackend,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let mut this = Self {
             name: name.clone(),
             gid,
+            backend,
         };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => (),
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if !which::which("synogroup").is_ok() {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyGroupCreationCommand,
-                        ));
-                    }
-                } else {
-                    if !(which::which("groupadd").is_ok() || which::which("addgroup").is_ok()) {
-                        return Err(Self::error(ActionErrorKind::MissingGroupCreationCommand));
-                    }
-                    if !(which::which("groupdel").is_ok() || which::which("delgroup").is_ok()) {
-                        return Err(Self::error(ActionErrorKind::MissingGroupDeletionCommand));
-                    }
-                }
-            },
-        }
+        this.backend
+            .ensure_available(&UserGroupOperation::CreateGroup { name: &name, gid })
+            .map_err(Self::error)?;
+        this.backend
+            .ensure_available(&UserGroupOperation::DeleteGroup { name: &name })
+            .map_err(Self::error)?;
 
         // Ensure group does not exists
-        if let Some(group) = Group::from_name(name.as_str())
-            .map_err(|e| ActionErrorKind::GettingGroupId(name.clone(), e))
-            .map_err(Self::error)?
-        {
-            if group.gid.as_raw() != gid {
+        if let Some(group) = this.backend.group(&name).map_err(Self::error)? {
+            if !this.backend.honours_requested_ids() {
+                // The GID was allocated by the backend when the group was created, it is authoritative
+                this.gid = group.gid;
+            }
+
+            if group.gid != this.gid {
                 return Err(Self::error(ActionErrorKind::GroupGidMismatch(
                     name.clone(),
-                    group.gid.as_raw(),
+                    group.gid,
                     gid,
                 )));
             }
@@ -77,7 +69,11 @@ impl Action for CreateGroup {
         format!("Create group `{}` (GID {})", self.name, self.gid)
     }
     fn execute_description(&self) -> Vec<ActionDescription> {
-        let Self { name: _, gid: _ } = &self;
+        let Self {
+            name: _,
+            gid: _,
+            backend: _,
+        } = &self;
         vec![ActionDescription::new(
             self.tracing_synopsis(),
             vec![format!(
@@ -97,80 +93,39 @@ impl Action for CreateGroup {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self { name, gid } = self;
-
-        use OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/sbin/dseditgroup")
-                        .process_group(0)
-                        .args([
-                            "-o",
-                            "create",
-                            "-r",
-                            "Nix build group for nix-daemon",
-                            "-i",
-                            &format!("{gid}"),
-                            name,
-                        ])
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
+        let Self { name, gid, backend } = self;
+
+        backend
+            .execute(&UserGroupOperation::CreateGroup { name, gid: *gid })
+            .await
+            .map_err(Self::error)?;
+
+        if !backend.honours_requested_ids() {
+            // Record the GID which was actually allocated, so the receipt reflects the system
+            let group = backend
+                .group(name)
+                .map_err(Self::error)?
+                .ok_or_else(|| ActionErrorKind::NoGroup(name.clone()))
                 .map_err(Self::error)?;
-            },
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if which::which("synogroup").is_ok() {
-                        execute_command(
-                            Command::new("synogroup")
-                                .process_group(0)
-                                .args(["--add", name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyGroupCreationCommand,
-                        ));
-                    }
-                } else {
-                    if which::which("groupadd").is_ok() {
-                        execute_command(
-                            Command::new("groupadd")
-                                .process_group(0)
-                                .args(["-g", &gid.to_string(), "--system", name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else if which::which("addgroup").is_ok() {
-                        execute_command(
-                            Command::new("addgroup")
-                                .process_group(0)
-                                .args(["-g", &gid.to_string(), "--system", name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(ActionErrorKind::MissingGroupCreationCommand));
-                    }
-                }
-            },
-        };
+            if group.gid != *gid {
+                tracing::debug!(
+                    requested_gid = *gid,
+                    gid = group.gid,
+                    "Group `{name}` was not created with the requested GID, recording the allocated GID",
+                );
+            }
+            *gid = group.gid;
+        }
 
         Ok(())
     }
 
     fn revert_description(&self) -> Vec<ActionDescription> {
-        let Self { name, gid } = &self;
+        let Self {
+            name,
+            gid,
+            backend: _,
+        } = &self;
         vec![ActionDescription::new(
             format!("Delete group `{name}` (GID {gid})"),
             vec![format!(
@@ -181,66 +136,47 @@ impl Action for CreateGroup {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
-        let Self { name, gid: _ } = self;
-
-        use OperatingSystem;
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX {
-                major: _,
-                minor: _,
-                patch: _,
-            }
-            | OperatingSystem::Darwin => {
-                execute_command(
-                    Command::new("/usr/bin/dscl")
-                        .args([".", "-delete", &format!("/Groups/{name}")])
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if which::which("synogroup").is_ok() {
-                        execute_command(
-                            Command::new("synogroup")
-                                .process_group(0)
-                                .args(["--del", name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyGroupDeletionCommand,
-                        ));
-                    }
-                } else {
-                    if which::which("groupdel").is_ok() {
-                        execute_command(
-                            Command::new("groupdel")
-                                .process_group(0)
-                                .arg(name)
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else if which::which("delgroup").is_ok() {
-                        execute_command(
-                            Command::new("delgroup")
-                                .process_group(0)
-                                .arg(name)
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(ActionErrorKind::MissingGroupDeletionCommand));
-                    }
-                }
-            },
-        };
+        let Self {
+            name,
+            gid: _,
+            backend,
+        } = self;
+
+        backend
+            .execute(&UserGroupOperation::DeleteGroup { name })
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        if self
+            .backend
+            .group(&self.name)
+            .map_err(Self::error)?
+            .is_some()
+        {
+            return Ok(vec![]);
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        match self.backend.group(&self.name).map_err(Self::error)? {
+            Some(group) if group.gid == self.gid => Ok(Verification::Pass),
+            Some(group) => Ok(Verification::Drift(vec![
+                ActionErrorKind::GroupGidMismatch(self.name.clone(), group.gid, self.gid)
+                    .to_string(),
+            ])),
+            None => Ok(Verification::Drift(vec![format!(
+                "Group `{}` is missing",
+                self.name
+            )])),
+        }
+    }
 }
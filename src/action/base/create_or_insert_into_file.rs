@@ -1,7 +1,9 @@
 use nix::unistd::{chown, Group, User};
 
+use crate::action::base::file_backup::{default_backup_dir, FileBackup};
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
 };
 use rand::Rng;
 use std::{
@@ -25,17 +27,23 @@ pub enum Position {
 contents, optionally with an owning user, group, and mode.
 
 If the file exists, the provided `buf` will be inserted at its
-beginning or end, depending on the position field.
+beginning or end, depending on the position field, and the original
+is backed up to be restored on [`revert`](CreateOrInsertIntoFile::revert).
  */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_or_insert_into_file")]
 pub struct CreateOrInsertIntoFile {
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     user: Option<String>,
     group: Option<String>,
     mode: Option<u32>,
     buf: String,
     position: Position,
+    #[serde(default)]
+    backup: Option<FileBackup>,
+    /// Where the original is backed up to, see [`BACKUP_DIR`](crate::action::base::file_backup::BACKUP_DIR)
+    #[serde(default = "default_backup_dir")]
+    backup_dir: PathBuf,
 }
 
 impl CreateOrInsertIntoFile {
@@ -47,6 +55,7 @@ impl CreateOrInsertIntoFile {
         mode: impl Into<Option<u32>>,
         buf: String,
         position: Position,
+        backup_dir: impl AsRef<Path>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let path = path.as_ref().to_path_buf();
         let mode = mode.into();
@@ -59,6 +68,8 @@ impl CreateOrInsertIntoFile {
             mode,
             buf,
             position,
+            backup: None,
+            backup_dir: backup_dir.as_ref().to_path_buf(),
         };
         if this.path.exists() {
             // If the path exists, perhaps we can just skip this
@@ -190,8 +201,17 @@ impl Action for CreateOrInsertIntoFile {
             mode,
             buf,
             position,
+            backup,
+            backup_dir,
         } = self;
 
+        // Keep the first backup, a repair re-creating a removed file must not replace it
+        if backup.is_none() {
+            *backup = FileBackup::create(path, backup_dir)
+                .await
+                .map_err(Self::error)?;
+        }
+
         let mut orig_file = match OpenOptions::new().read(true).open(&path).await {
             Ok(f) => Some(f),
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
@@ -310,6 +330,10 @@ impl Action for CreateOrInsertIntoFile {
             .map_err(|e| ActionErrorKind::Rename(path.to_owned(), temp_file_path.to_owned(), e))
             .map_err(Self::error)?;
 
+        if let Some(backup) = backup {
+            backup.record_installed(path).await.map_err(Self::error)?;
+        }
+
         Ok(())
     }
 
@@ -321,7 +345,21 @@ impl Action for CreateOrInsertIntoFile {
             mode: _,
             buf,
             position: _,
+            backup,
+            backup_dir: _,
         } = &self;
+        if let Some(backup) = backup {
+            return vec![ActionDescription::new(
+                format!("Restore the original `{}`", path.display()),
+                vec![format!(
+                    "Restore `{}` from `{}`, keeping any changes made since Nix was installed in `{}{}`",
+                    path.display(),
+                    backup.original.display(),
+                    path.display(),
+                    crate::action::base::file_backup::DRIFT_SUFFIX,
+                )],
+            )];
+        }
         vec![ActionDescription::new(
             format!("Delete Nix related fragment from file `{}`", path.display()),
             vec![format!(
@@ -340,7 +378,15 @@ impl Action for CreateOrInsertIntoFile {
             mode: _,
             buf,
             position: _,
+            backup,
+            backup_dir: _,
         } = self;
+
+        if let Some(backup) = backup {
+            backup.restore(path).await.map_err(Self::error)?;
+            return Ok(());
+        }
+
         // The user already deleted it
         if !path.exists() {
             return Ok(());
@@ -391,6 +437,38 @@ impl Action for CreateOrInsertIntoFile {
         }
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) if contents.contains(&self.buf) => return Ok(vec![]),
+            // Most likely an OS update replaced the file, so its current version is what uninstalling restores
+            Ok(_) => self.backup = None,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(Self::error(ActionErrorKind::Read(self.path.clone(), e))),
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) if contents.contains(&self.buf) => Ok(Verification::Pass),
+            Ok(_) => Ok(Verification::Drift(vec![format!(
+                "`{}` no longer contains the lines added by the installer",
+                self.path.display()
+            )])),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Verification::Drift(vec![format!(
+                    "`{}` is missing",
+                    self.path.display()
+                )]))
+            },
+            Err(e) => Err(Self::error(ActionErrorKind::Read(self.path.clone(), e))),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -410,6 +488,7 @@ mod test {
             None,
             "Test".into(),
             Position::Beginning,
+            temp_dir.path().join("backups"),
         )
         .await?;
 
@@ -439,6 +518,7 @@ mod test {
             None,
             "Test".into(),
             Position::Beginning,
+            temp_dir.path().join("backups"),
         )
         .await?;
 
@@ -480,6 +560,7 @@ mod test {
                 None,
                 expected_content.into(),
                 position,
+                temp_dir.path().join("backups"),
             )
             .await?;
 
@@ -515,6 +596,7 @@ mod test {
             Some(expected_mode),
             "Some different content".into(),
             Position::End,
+            temp_dir.path().join("backups"),
         )
         .await?;
 
@@ -544,6 +626,7 @@ mod test {
             Some(initial_mode),
             "Some content".into(),
             Position::End,
+            temp_dir.path().join("backups"),
         )
         .await?;
 
@@ -567,6 +650,7 @@ mod test {
             None,
             "Some different content".into(),
             Position::End,
+            temp_dir.path().join("backups"),
         )
         .await
         {
@@ -587,4 +671,77 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn restores_exact_original() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("restores_exact_original");
+        // No trailing newline, which removing the fragment alone would not preserve
+        let original_content = "Some content";
+        write(&test_file, original_content).await?;
+        tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(0o640)).await?;
+
+        let mut action = CreateOrInsertIntoFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            0o644,
+            "\nTest\n".into(),
+            Position::End,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+
+        action.try_execute().await?;
+
+        // Edits after install are kept aside, not merged
+        let edited_content = format!("{original_content}\nTest\nEdited\n");
+        write(&test_file, &edited_content).await?;
+
+        action.try_revert().await?;
+
+        assert_eq!(read_to_string(&test_file).await?, original_content);
+        assert_eq!(
+            tokio::fs::metadata(&test_file).await?.permissions().mode() & 0o777,
+            0o640
+        );
+        assert_eq!(
+            read_to_string(format!("{}.nix-installer-drift", test_file.display())).await?,
+            edited_content
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repairs_removed_insertion() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("repairs_removed_insertion");
+        write(&test_file, "Some other content\n").await?;
+
+        let mut action = CreateOrInsertIntoFile::plan(
+            test_file.clone(),
+            None,
+            None,
+            None,
+            "Test\n".into(),
+            Position::End,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+
+        action.try_execute().await?;
+        assert!(action.try_repair().await?.is_empty());
+
+        // An OS update resets the file
+        write(&test_file, "Updated content\n").await?;
+        assert_eq!(action.try_repair().await?.len(), 1);
+        assert_eq!(read_to_string(&test_file).await?, "Updated content\nTest\n");
+
+        // Uninstalling must not bring back the file from before the update
+        action.try_revert().await?;
+        assert_eq!(read_to_string(&test_file).await?, "Updated content\n");
+
+        Ok(())
+    }
 }
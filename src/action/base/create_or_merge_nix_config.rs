@@ -11,8 +11,10 @@ use tokio::{
 };
 use tracing::{span, Span};
 
+use crate::action::base::file_backup::{default_backup_dir, FileBackup, DRIFT_SUFFIX};
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
 };
 
 /// The `nix.conf` configuration names that are safe to merge.
@@ -42,11 +44,18 @@ impl From<CreateOrMergeNixConfigError> for ActionErrorKind {
 }
 
 /// Create or merge an existing `nix.conf` at the specified path.
+///
+/// An existing `nix.conf` is backed up before merging, and restored on revert.
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_or_merge_nix_config")]
 pub struct CreateOrMergeNixConfig {
     pub(crate) path: PathBuf,
     pending_nix_config: NixConfig,
+    #[serde(default)]
+    backup: Option<FileBackup>,
+    /// Where an existing `nix.conf` is backed up to, see [`BACKUP_DIR`](crate::action::base::file_backup::BACKUP_DIR)
+    #[serde(default = "default_backup_dir")]
+    backup_dir: PathBuf,
 }
 
 impl CreateOrMergeNixConfig {
@@ -54,12 +63,15 @@ impl CreateOrMergeNixConfig {
     pub async fn plan(
         path: impl AsRef<Path>,
         pending_nix_config: NixConfig,
+        backup_dir: impl AsRef<Path>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let path = path.as_ref().to_path_buf();
 
         let this = Self {
             path,
             pending_nix_config,
+            backup: None,
+            backup_dir: backup_dir.as_ref().to_path_buf(),
         };
 
         if this.path.exists() {
@@ -233,6 +245,8 @@ impl Action for CreateOrMergeNixConfig {
         let Self {
             path,
             pending_nix_config,
+            backup,
+            backup_dir,
         } = self;
 
         if tracing::enabled!(tracing::Level::TRACE) {
@@ -440,6 +454,12 @@ impl Action for CreateOrMergeNixConfig {
             .sync_all()
             .await
             .map_err(|e| Self::error(ActionErrorKind::Sync(temp_file_path.clone(), e)))?;
+        // Keep the first backup, a repair recreating a removed file must not replace it
+        if backup.is_none() {
+            *backup = FileBackup::create(path, backup_dir)
+                .await
+                .map_err(Self::error)?;
+        }
         tokio::fs::rename(&temp_file_path, &path)
             .await
             .map_err(|e| {
@@ -449,6 +469,9 @@ impl Action for CreateOrMergeNixConfig {
                     e,
                 ))
             })?;
+        if let Some(backup) = backup {
+            backup.record_installed(path).await.map_err(Self::error)?;
+        }
 
         Ok(())
     }
@@ -457,8 +480,21 @@ impl Action for CreateOrMergeNixConfig {
         let Self {
             path,
             pending_nix_config: _,
+            backup,
+            backup_dir: _,
         } = &self;
 
+        if let Some(backup) = backup {
+            return vec![ActionDescription::new(
+                format!("Restore the original `{}`", path.display()),
+                vec![format!(
+                    "Restore `{}` from `{}`, keeping any changes made since Nix was installed in `{}{DRIFT_SUFFIX}`",
+                    path.display(),
+                    backup.original.display(),
+                    path.display(),
+                )],
+            )];
+        }
         vec![ActionDescription::new(
             format!("Delete file `{}`", path.display()),
             vec![format!("Delete file `{}`", path.display())],
@@ -470,14 +506,88 @@ impl Action for CreateOrMergeNixConfig {
         let Self {
             path,
             pending_nix_config: _,
+            backup,
+            backup_dir: _,
         } = self;
 
+        if let Some(backup) = backup {
+            backup.restore(path).await.map_err(Self::error)?;
+            return Ok(());
+        }
+
         remove_file(&path)
             .await
             .map_err(|e| Self::error(ActionErrorKind::Remove(path.to_owned(), e)))?;
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        // An existing configuration may have been edited since, only a missing one is restored
+        if self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let metadata = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Verification::Drift(vec![format!(
+                    "`{}` is missing",
+                    self.path.display()
+                )]))
+            },
+            Err(e) => {
+                return Err(Self::error(ActionErrorKind::GettingMetadata(
+                    self.path.clone(),
+                    e,
+                )))
+            },
+        };
+
+        let mut drift = vec![];
+        let discovered_mode = metadata.permissions().mode() & 0o777;
+        if discovered_mode != NIX_CONF_MODE {
+            drift.push(
+                ActionErrorKind::PathModeMismatch(
+                    self.path.clone(),
+                    discovered_mode,
+                    NIX_CONF_MODE,
+                )
+                .to_string(),
+            );
+        }
+
+        let existing_nix_config = NixConfig::parse_file(&self.path)
+            .map_err(CreateOrMergeNixConfigError::ParseNixConfig)
+            .map_err(Self::error)?;
+        for (name, pending_value) in self.pending_nix_config.settings() {
+            let existing_value = existing_nix_config
+                .settings()
+                .get(name)
+                .map(String::as_str)
+                .unwrap_or_default()
+                .split(' ')
+                .collect::<Vec<_>>();
+            if !pending_value
+                .split(' ')
+                .all(|value| existing_value.contains(&value))
+            {
+                drift.push(format!(
+                    "`{name}` in `{}` no longer includes `{pending_value}`",
+                    self.path.display()
+                ));
+            }
+        }
+
+        Ok(Verification::from_drift(drift))
+    }
 }
 
 #[cfg(test)]
@@ -494,7 +604,9 @@ mod test {
         nix_config
             .settings_mut()
             .insert("experimental-features".into(), "ca-references".into());
-        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+        let mut action =
+            CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+                .await?;
 
         action.try_execute().await?;
 
@@ -520,7 +632,9 @@ mod test {
         nix_config
             .settings_mut()
             .insert("experimental-features".into(), "ca-references".into());
-        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+        let mut action =
+            CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+                .await?;
 
         action.try_execute().await?;
 
@@ -548,7 +662,9 @@ mod test {
         nix_config
             .settings_mut()
             .insert("experimental-features".into(), "flakes".into());
-        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+        let mut action =
+            CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+                .await?;
 
         action.try_execute().await?;
 
@@ -566,11 +682,8 @@ mod test {
             .path()
             .join("recognizes_existing_different_files_and_merges");
 
-        write(
-            test_file.as_path(),
-            "experimental-features = flakes\nwarn-dirty = true\n",
-        )
-        .await?;
+        let original_content = "experimental-features = flakes\nwarn-dirty = true\n";
+        write(test_file.as_path(), original_content).await?;
         tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(NIX_CONF_MODE)).await?;
 
         let mut nix_config = NixConfig::new();
@@ -580,7 +693,9 @@ mod test {
         nix_config
             .settings_mut()
             .insert("allow-dirty".into(), "false".into());
-        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+        let mut action =
+            CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+                .await?;
 
         action.try_execute().await?;
 
@@ -599,7 +714,11 @@ mod test {
 
         action.try_revert().await?;
 
-        assert!(!test_file.exists(), "File should have been deleted");
+        assert_eq!(
+            std::fs::read_to_string(&test_file)?,
+            original_content,
+            "Original should have been restored"
+        );
 
         Ok(())
     }
@@ -625,7 +744,9 @@ mod test {
         nix_config
             .settings_mut()
             .insert("warn-dirty".into(), "false".into());
-        match CreateOrMergeNixConfig::plan(&test_file, nix_config).await {
+        match CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+            .await
+        {
             Err(err) => {
                 if let ActionErrorKind::Custom(e) = err.kind() {
                     match e.downcast_ref::<CreateOrMergeNixConfigError>() {
@@ -657,17 +778,16 @@ mod test {
         let temp_dir = tempfile::TempDir::new()?;
         let test_file = temp_dir.path().join("preserves_comments");
 
-        write(
-            test_file.as_path(),
-            "# test 2\n# test\nexperimental-features = flakes # some inline comment about experimental-features\n# the following line should be warn-dirty = true\nwarn-dirty = true # this is an inline comment\n# this is an ungrouped comment\n# this too",
-        )
-        .await?;
+        let original_content = "# test 2\n# test\nexperimental-features = flakes # some inline comment about experimental-features\n# the following line should be warn-dirty = true\nwarn-dirty = true # this is an inline comment\n# this is an ungrouped comment\n# this too";
+        write(test_file.as_path(), original_content).await?;
         tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(NIX_CONF_MODE)).await?;
         let mut nix_config = NixConfig::new();
         nix_config
             .settings_mut()
             .insert("experimental-features".into(), "ca-references".into());
-        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+        let mut action =
+            CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+                .await?;
 
         action.try_execute().await?;
 
@@ -683,7 +803,11 @@ mod test {
 
         action.try_revert().await?;
 
-        assert!(!test_file.exists(), "File should have been deleted");
+        assert_eq!(
+            std::fs::read_to_string(&test_file)?,
+            original_content,
+            "Original should have been restored"
+        );
 
         Ok(())
     }
@@ -693,13 +817,16 @@ mod test {
         let temp_dir = tempfile::TempDir::new()?;
         let test_file = temp_dir.path().join("preserves_comments");
 
-        write(test_file.as_path(), " a = b\n c = d# lol\n# e = f").await?;
+        let original_content = " a = b\n c = d# lol\n# e = f";
+        write(test_file.as_path(), original_content).await?;
         tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(NIX_CONF_MODE)).await?;
         let mut nix_config = NixConfig::new();
         nix_config
             .settings_mut()
             .insert("experimental-features".into(), "ca-references".into());
-        let mut action = CreateOrMergeNixConfig::plan(&test_file, nix_config).await?;
+        let mut action =
+            CreateOrMergeNixConfig::plan(&test_file, nix_config, temp_dir.path().join("backups"))
+                .await?;
 
         action.try_execute().await?;
 
@@ -711,7 +838,11 @@ mod test {
 
         action.try_revert().await?;
 
-        assert!(!test_file.exists(), "File should have been deleted");
+        assert_eq!(
+            std::fs::read_to_string(&test_file)?,
+            original_content,
+            "Original should have been restored"
+        );
 
         Ok(())
     }
@@ -1,14 +1,10 @@
-use std::path::PathBuf;
-
-use nix::unistd::User;
-use target_lexicon::OperatingSystem;
 use tokio::process::Command;
 use tracing::{span, Span};
 
+use crate::action::base::{UserGroupBackend, UserGroupOperation};
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
-use crate::execute_command;
 
-use crate::action::{Action, ActionDescription, StatefulAction};
+use crate::action::{Action, ActionDescription, StatefulAction, Verification};
 
 /**
 Create an operating system level user in the given group
@@ -17,10 +13,12 @@ Create an operating system level user in the given group
 #[serde(tag = "action_name", rename = "create_user")]
 pub struct CreateUser {
     name: String,
-    uid: u32,
+    pub(crate) uid: u32,
     groupname: String,
-    gid: u32,
+    pub(crate) gid: u32,
     comment: String,
+    #[serde(default = "UserGroupBackend::detect")]
+    backend: UserGroupBackend,
 }
 
 impl CreateUser {
@@ -31,62 +29,69 @@ impl CreateUser {
         groupname: String,
         gid: u32,
         comment: String,
+        backend: UserGroupBackend,
     ) -> Result<StatefulAction<Self>, ActionError> {
-        let this = Self {
+        let mut this = Self {
             name: name.clone(),
             uid,
             groupname,
             gid,
             comment,
+            backend,
         };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => (),
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if !which::which("synouser").is_ok() {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyUserCreationCommand,
-                        ));
-                    }
-                } else {
-                    if !(which::which("useradd").is_ok() || which::which("adduser").is_ok()) {
-                        return Err(Self::error(ActionErrorKind::MissingUserCreationCommand));
-                    }
-                    if !(which::which("userdel").is_ok() || which::which("deluser").is_ok()) {
-                        return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                    }
-                }
-            },
-        }
+        this.backend
+            .ensure_available(&this.create_operation())
+            .map_err(Self::error)?;
+        this.backend
+            .ensure_available(&UserGroupOperation::DeleteUser { name: &name })
+            .map_err(Self::error)?;
 
         // Ensure user does not exists
-        if let Some(user) = User::from_name(name.as_str())
-            .map_err(|e| ActionErrorKind::GettingUserId(name.clone(), e))
-            .map_err(Self::error)?
-        {
-            if user.uid.as_raw() != uid {
+        if let Some(user) = this.backend.user(&name).map_err(Self::error)? {
+            if !this.backend.honours_requested_ids() {
+                // The IDs were allocated by the backend when the user was created, they are authoritative
+                this.uid = user.uid;
+                this.gid = user.gid;
+            }
+
+            if user.uid != this.uid {
                 return Err(Self::error(ActionErrorKind::UserUidMismatch(
                     name.clone(),
-                    user.uid.as_raw(),
+                    user.uid,
                     uid,
                 )));
             }
 
-            if user.gid.as_raw() != gid {
+            if user.gid != this.gid {
                 return Err(Self::error(ActionErrorKind::UserGidMismatch(
                     name.clone(),
-                    user.gid.as_raw(),
+                    user.gid,
                     gid,
                 )));
             }
 
+            this.backend
+                .verify_login_disabled(&this.name)
+                .await
+                .map_err(Self::error)?;
+
             tracing::debug!("Creating user `{}` already complete", this.name);
             return Ok(StatefulAction::completed(this));
         }
 
         Ok(StatefulAction::uncompleted(this))
     }
+
+    fn create_operation(&self) -> UserGroupOperation<'_> {
+        UserGroupOperation::CreateUser {
+            name: &self.name,
+            uid: self.uid,
+            groupname: &self.groupname,
+            gid: self.gid,
+            comment: &self.comment,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -124,101 +129,38 @@ impl Action for CreateUser {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self {
-            name,
-            uid,
-            groupname,
-            gid,
-            comment,
-        } = self;
+        self.backend
+            .execute(&self.create_operation())
+            .await
+            .map_err(Self::error)?;
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => {
-                create_user_macos(name, *uid, *gid)
-                    .await
-                    .map_err(Self::error)?;
-            },
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if which::which("synouser").is_ok() {
-                        execute_command(
-                            Command::new("synouser")
-                                .process_group(0)
-                                .args([
-                                    "--add", name, "",  // Empty password
-                                    "",  // Empty full name
-                                    "0", // Unexpired account
-                                    "",  // Empty email
-                                    "0", // Synology application priviliges
-                                ])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyUserCreationCommand,
-                        ));
-                    }
-                } else {
-                    if which::which("useradd").is_ok() {
-                        execute_command(
-                            Command::new("useradd")
-                                .process_group(0)
-                                .args([
-                                    "--home-dir",
-                                    "/var/empty",
-                                    "--comment",
-                                    comment,
-                                    "--gid",
-                                    &gid.to_string(),
-                                    "--groups",
-                                    &gid.to_string(),
-                                    "--no-user-group",
-                                    "--system",
-                                    "--shell",
-                                    "/sbin/nologin",
-                                    "--uid",
-                                    &uid.to_string(),
-                                    "--password",
-                                    "!",
-                                    name,
-                                ])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else if which::which("adduser").is_ok() {
-                        execute_command(
-                            Command::new("adduser")
-                                .process_group(0)
-                                .args([
-                                    "--home",
-                                    "/var/empty",
-                                    "-H", // Don't create a home.
-                                    "--gecos",
-                                    comment,
-                                    "--ingroup",
-                                    groupname,
-                                    "--system",
-                                    "--shell",
-                                    "/sbin/nologin",
-                                    "--uid",
-                                    &uid.to_string(),
-                                    "--disabled-password",
-                                    name,
-                                ])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(ActionErrorKind::MissingUserCreationCommand));
-                    }
-                }
-            },
+        if !self.backend.honours_requested_ids() {
+            // Record the IDs which were actually allocated, so the receipt reflects the system
+            let user = self
+                .backend
+                .user(&self.name)
+                .map_err(Self::error)?
+                .ok_or_else(|| ActionErrorKind::NoUser(self.name.clone()))
+                .map_err(Self::error)?;
+            if user.uid != self.uid || user.gid != self.gid {
+                tracing::debug!(
+                    requested_uid = self.uid,
+                    requested_gid = self.gid,
+                    uid = user.uid,
+                    gid = user.gid,
+                    "User `{}` was not created with the requested IDs, recording the allocated IDs",
+                    self.name,
+                );
+            }
+            self.uid = user.uid;
+            self.gid = user.gid;
         }
 
+        self.backend
+            .verify_login_disabled(&self.name)
+            .await
+            .map_err(Self::error)?;
+
         Ok(())
     }
 
@@ -236,126 +178,51 @@ impl Action for CreateUser {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn revert(&mut self) -> Result<(), ActionError> {
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => {
-                delete_user_macos(&self.name).await.map_err(Self::error)?;
-            },
-            _ => {
-                if PathBuf::from("/etc/synoinfo.conf").is_file() {
-                    if which::which("synouser").is_ok() {
-                        execute_command(
-                            Command::new("synouser")
-                                .process_group(0)
-                                .args(["--del", &self.name])
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(
-                            ActionErrorKind::MissingSynologyUserDeletionCommand,
-                        ));
-                    }
-                } else {
-                    if which::which("userdel").is_ok() {
-                        execute_command(
-                            Command::new("userdel")
-                                .process_group(0)
-                                .arg(&self.name)
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else if which::which("deluser").is_ok() {
-                        execute_command(
-                            Command::new("deluser")
-                                .process_group(0)
-                                .arg(&self.name)
-                                .stdin(std::process::Stdio::null()),
-                        )
-                        .await
-                        .map_err(Self::error)?;
-                    } else {
-                        return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                    }
-                }
-            },
-        };
+        self.backend
+            .execute(&UserGroupOperation::DeleteUser { name: &self.name })
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
-}
 
-#[tracing::instrument(level = "debug", skip_all)]
-async fn create_user_macos(name: &str, uid: u32, gid: u32) -> Result<(), ActionErrorKind> {
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([".", "-create", &format!("/Users/{name}")])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "UniqueID",
-                &format!("{uid}"),
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "PrimaryGroupID",
-                &format!("{gid}"),
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "NFSHomeDirectory",
-                "/var/empty",
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([
-                ".",
-                "-create",
-                &format!("/Users/{name}"),
-                "UserShell",
-                "/sbin/nologin",
-            ])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
-    execute_command(
-        Command::new("/usr/bin/dscl")
-            .process_group(0)
-            .args([".", "-create", &format!("/Users/{name}"), "IsHidden", "1"])
-            .stdin(std::process::Stdio::null()),
-    )
-    .await?;
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        if self
+            .backend
+            .user(&self.name)
+            .map_err(Self::error)?
+            .is_some()
+        {
+            return Ok(vec![]);
+        }
 
-    Ok(())
+        self.execute().await?;
+        Ok(vec![self.tracing_synopsis()])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let Some(user) = self.backend.user(&self.name).map_err(Self::error)? else {
+            return Ok(Verification::Drift(vec![format!(
+                "User `{}` is missing",
+                self.name
+            )]));
+        };
+
+        let mut drift = vec![];
+        if user.uid != self.uid {
+            drift.push(
+                ActionErrorKind::UserUidMismatch(self.name.clone(), user.uid, self.uid).to_string(),
+            );
+        }
+        if user.gid != self.gid {
+            drift.push(
+                ActionErrorKind::UserGidMismatch(self.name.clone(), user.gid, self.gid).to_string(),
+            );
+        }
+        Ok(Verification::from_drift(drift))
+    }
 }
 
 #[tracing::instrument(level = "debug", skip_all)]
@@ -1,11 +1,7 @@
-use nix::unistd::User;
-use target_lexicon::OperatingSystem;
-use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::base::create_user::delete_user_macos;
+use crate::action::base::{UserGroupBackend, UserGroupOperation};
 use crate::action::{ActionError, ActionErrorKind, ActionTag};
-use crate::execute_command;
 
 use crate::action::{Action, ActionDescription, StatefulAction};
 
@@ -16,25 +12,29 @@ Delete an operating system level user
 #[serde(tag = "action_name", rename = "delete_user")]
 pub struct DeleteUser {
     name: String,
+    #[serde(default = "UserGroupBackend::detect")]
+    backend: UserGroupBackend,
 }
 
 impl DeleteUser {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub async fn plan(name: String) -> Result<StatefulAction<Self>, ActionError> {
-        let this = Self { name: name.clone() };
+    pub async fn plan(
+        name: String,
+        backend: UserGroupBackend,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let this = Self {
+            name: name.clone(),
+            backend,
+        };
 
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => (),
-            _ => {
-                if !(which::which("userdel").is_ok() || which::which("deluser").is_ok()) {
-                    return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                }
-            },
-        }
+        this.backend
+            .ensure_available(&UserGroupOperation::DeleteUser { name: &name })
+            .map_err(Self::error)?;
 
         // Ensure user exists
-        let _ = User::from_name(name.as_str())
-            .map_err(|e| ActionErrorKind::GettingUserId(name.clone(), e))
+        let _ = this
+            .backend
+            .user(&name)
             .map_err(Self::error)?
             .ok_or_else(|| ActionErrorKind::NoUser(name.clone()))
             .map_err(Self::error)?;
@@ -74,34 +74,10 @@ impl Action for DeleteUser {
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        match OperatingSystem::host() {
-            OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin => {
-                delete_user_macos(&self.name).await.map_err(Self::error)?;
-            },
-            _ => {
-                if which::which("userdel").is_ok() {
-                    execute_command(
-                        Command::new("userdel")
-                            .process_group(0)
-                            .arg(&self.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else if which::which("deluser").is_ok() {
-                    execute_command(
-                        Command::new("deluser")
-                            .process_group(0)
-                            .arg(&self.name)
-                            .stdin(std::process::Stdio::null()),
-                    )
-                    .await
-                    .map_err(Self::error)?;
-                } else {
-                    return Err(Self::error(ActionErrorKind::MissingUserDeletionCommand));
-                }
-            },
-        };
+        self.backend
+            .execute(&UserGroupOperation::DeleteUser { name: &self.name })
+            .await
+            .map_err(Self::error)?;
 
         Ok(())
     }
@@ -0,0 +1,396 @@
+/*! Offline equivalents of `useradd` and friends, which edit `passwd`, `group`, `shadow` and `gshadow` under a root directly
+
+Used by [`UserGroupBackend::Files`](super::UserGroupBackend::Files) when installing into a disk image or chroot, where
+the host's tools would change the host's users instead.
+*/
+use std::path::{Path, PathBuf};
+
+use crate::action::base::user_group_backend::{days_since_epoch, GroupEntry, UserEntry};
+use crate::action::ActionErrorKind;
+use crate::settings::rooted;
+
+const PASSWD: &str = "/etc/passwd";
+const GROUP: &str = "/etc/group";
+const SHADOW: &str = "/etc/shadow";
+const GSHADOW: &str = "/etc/gshadow";
+
+/// Look up `name` in `<root>/etc/passwd`
+pub(crate) fn user(root: &Path, name: &str) -> Result<Option<UserEntry>, ActionErrorKind> {
+    let path = rooted(Some(root), PASSWD);
+    let contents = read(&path)?;
+    let Some(fields) = find(&contents, name) else {
+        return Ok(None);
+    };
+    match (id_field(&fields, 2), id_field(&fields, 3)) {
+        (Some(uid), Some(gid)) => Ok(Some(UserEntry { uid, gid })),
+        _ => Err(EtcFilesError::Malformed(name.to_string(), path).into()),
+    }
+}
+
+/// Look up `name` in `<root>/etc/group`
+pub(crate) fn group(root: &Path, name: &str) -> Result<Option<GroupEntry>, ActionErrorKind> {
+    let path = rooted(Some(root), GROUP);
+    let contents = read(&path)?;
+    let Some(fields) = find(&contents, name) else {
+        return Ok(None);
+    };
+    let Some(gid) = id_field(&fields, 2) else {
+        return Err(EtcFilesError::Malformed(name.to_string(), path).into());
+    };
+    Ok(Some(GroupEntry {
+        gid,
+        members: members(fields.get(3).copied().unwrap_or_default()),
+    }))
+}
+
+/// Add a system user with no password, home or shell, like `useradd --system`
+pub(crate) async fn create_user(
+    root: &Path,
+    name: &str,
+    uid: u32,
+    gid: u32,
+    comment: &str,
+) -> Result<(), ActionErrorKind> {
+    append_entry(
+        &rooted(Some(root), PASSWD),
+        name,
+        format!("{name}:x:{uid}:{gid}:{comment}:/var/empty:/sbin/nologin"),
+        true,
+    )
+    .await?;
+    append_entry(
+        &rooted(Some(root), SHADOW),
+        name,
+        format!("{name}:!:{}::::::", days_since_epoch()),
+        false,
+    )
+    .await
+}
+
+/// Remove a user, and its membership of any groups, like `userdel`
+pub(crate) async fn delete_user(root: &Path, name: &str) -> Result<(), ActionErrorKind> {
+    remove_entry(&rooted(Some(root), PASSWD), name).await?;
+    remove_entry(&rooted(Some(root), SHADOW), name).await?;
+    for path in [GROUP, GSHADOW] {
+        edit_lines(&rooted(Some(root), path), false, |lines| {
+            for line in lines.iter_mut() {
+                if let Some(edited) = edit_members(line, |members| members.retain(|m| m != name)) {
+                    *line = edited;
+                }
+            }
+            Ok(())
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// Replace the password of `name` with one nothing hashes to, like `usermod --password '!'`
+pub(crate) async fn lock_password(root: &Path, name: &str) -> Result<(), ActionErrorKind> {
+    let path = rooted(Some(root), SHADOW);
+    edit_lines(&path, true, |lines| {
+        let line = lines
+            .iter_mut()
+            .find(|line| entry_name(line) == name)
+            .ok_or_else(|| EtcFilesError::Missing(name.to_string(), path.clone()))?;
+        let mut fields = line.split(':').map(ToString::to_string).collect::<Vec<_>>();
+        let password = fields
+            .get_mut(1)
+            .ok_or_else(|| EtcFilesError::Malformed(name.to_string(), path.clone()))?;
+        *password = "!".to_string();
+        *line = fields.join(":");
+        Ok(())
+    })
+    .await
+}
+
+/// Add a group, like `groupadd --system`
+pub(crate) async fn create_group(root: &Path, name: &str, gid: u32) -> Result<(), ActionErrorKind> {
+    append_entry(
+        &rooted(Some(root), GROUP),
+        name,
+        format!("{name}:x:{gid}:"),
+        true,
+    )
+    .await?;
+    append_entry(
+        &rooted(Some(root), GSHADOW),
+        name,
+        format!("{name}:!::"),
+        false,
+    )
+    .await
+}
+
+/// Remove a group, like `groupdel`
+pub(crate) async fn delete_group(root: &Path, name: &str) -> Result<(), ActionErrorKind> {
+    remove_entry(&rooted(Some(root), GROUP), name).await?;
+    remove_entry(&rooted(Some(root), GSHADOW), name).await
+}
+
+/// Add `name` to the members of `groupname`, like `gpasswd -a`
+pub(crate) async fn add_member(
+    root: &Path,
+    name: &str,
+    groupname: &str,
+) -> Result<(), ActionErrorKind> {
+    for (path, required) in [(GROUP, true), (GSHADOW, false)] {
+        let path = rooted(Some(root), path);
+        edit_lines(&path, required, |lines| {
+            let line = lines
+                .iter_mut()
+                .find(|line| entry_name(line) == groupname)
+                .ok_or_else(|| EtcFilesError::Missing(groupname.to_string(), path.clone()))?;
+            if let Some(edited) = edit_members(line, |members| {
+                if !members.iter().any(|m| m == name) {
+                    members.push(name.to_string());
+                }
+            }) {
+                *line = edited;
+            }
+            Ok(())
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// Remove `name` from the members of `groupname`, like `gpasswd -d`
+pub(crate) async fn remove_member(
+    root: &Path,
+    name: &str,
+    groupname: &str,
+) -> Result<(), ActionErrorKind> {
+    for path in [GROUP, GSHADOW] {
+        edit_lines(&rooted(Some(root), path), false, |lines| {
+            for line in lines
+                .iter_mut()
+                .filter(|line| entry_name(line) == groupname)
+            {
+                if let Some(edited) = edit_members(line, |members| members.retain(|m| m != name)) {
+                    *line = edited;
+                }
+            }
+            Ok(())
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<String, ActionErrorKind> {
+    std::fs::read_to_string(path).map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))
+}
+
+fn entry_name(line: &str) -> &str {
+    line.split(':').next().unwrap_or_default()
+}
+
+fn find<'a>(contents: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    contents
+        .lines()
+        .find(|line| entry_name(line) == name)
+        .map(|line| line.split(':').collect())
+}
+
+fn id_field(fields: &[&str], index: usize) -> Option<u32> {
+    fields.get(index).and_then(|field| field.parse().ok())
+}
+
+fn members(field: &str) -> Vec<String> {
+    field
+        .split(',')
+        .filter(|member| !member.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// `line` with its member list (the fourth field of `group` and `gshadow` entries) edited, `None` if it has no such field
+fn edit_members(line: &str, edit: impl FnOnce(&mut Vec<String>)) -> Option<String> {
+    let mut fields = line.split(':').map(ToString::to_string).collect::<Vec<_>>();
+    let field = fields.get_mut(3)?;
+    let mut members = members(field);
+    edit(&mut members);
+    *field = members.join(",");
+    Some(fields.join(":"))
+}
+
+/// Edit the lines of `path` in place, keeping its mode and owner
+///
+/// If `path` does not exist it is an error when `required`, otherwise nothing is done.
+async fn edit_lines(
+    path: &Path,
+    required: bool,
+    edit: impl FnOnce(&mut Vec<String>) -> Result<(), EtcFilesError>,
+) -> Result<(), ActionErrorKind> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+            tracing::trace!("`{}` does not exist, not editing it", path.display());
+            return Ok(());
+        },
+        Err(e) => return Err(ActionErrorKind::Read(path.to_path_buf(), e)),
+    };
+    let mut lines = contents
+        .lines()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    edit(&mut lines)?;
+
+    let mut buf = lines.join("\n");
+    if !buf.is_empty() {
+        buf.push('\n');
+    }
+    if buf != contents {
+        tokio::fs::write(path, buf)
+            .await
+            .map_err(|e| ActionErrorKind::Write(path.to_path_buf(), e))?;
+    }
+    Ok(())
+}
+
+async fn append_entry(
+    path: &Path,
+    name: &str,
+    entry: String,
+    required: bool,
+) -> Result<(), ActionErrorKind> {
+    edit_lines(path, required, |lines| {
+        if lines.iter().any(|line| entry_name(line) == name) {
+            return Err(EtcFilesError::Exists(name.to_string(), path.to_path_buf()));
+        }
+        lines.push(entry);
+        Ok(())
+    })
+    .await
+}
+
+async fn remove_entry(path: &Path, name: &str) -> Result<(), ActionErrorKind> {
+    edit_lines(path, false, |lines| {
+        lines.retain(|line| entry_name(line) != name);
+        Ok(())
+    })
+    .await
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum EtcFilesError {
+    #[error("`{0}` already has an entry in `{1}`")]
+    Exists(String, PathBuf),
+    #[error("`{0}` has no entry in `{1}`")]
+    Missing(String, PathBuf),
+    #[error("The entry for `{0}` in `{1}` is malformed")]
+    Malformed(String, PathBuf),
+}
+
+impl From<EtcFilesError> for ActionErrorKind {
+    fn from(val: EtcFilesError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PASSWD_BUF: &str = "root:x:0:0:root:/root:/bin/sh\n";
+    const GROUP_BUF: &str = "root:x:0:\nwheel:x:10:root\n";
+    const SHADOW_BUF: &str = "root:*:19000:0:99999:7:::\n";
+
+    async fn image() -> eyre::Result<tempfile::TempDir> {
+        let root = tempfile::tempdir()?;
+        tokio::fs::create_dir_all(root.path().join("etc")).await?;
+        tokio::fs::write(root.path().join("etc/passwd"), PASSWD_BUF).await?;
+        tokio::fs::write(root.path().join("etc/group"), GROUP_BUF).await?;
+        tokio::fs::write(root.path().join("etc/shadow"), SHADOW_BUF).await?;
+        Ok(root)
+    }
+
+    #[tokio::test]
+    async fn creates_and_deletes_users_and_groups() -> eyre::Result<()> {
+        let root = image().await?;
+        let root = root.path();
+
+        create_group(root, "nixbld", 30000).await?;
+        create_user(root, "nixbld1", 30001, 30000, "Nix build user 1").await?;
+        add_member(root, "nixbld1", "nixbld").await?;
+        add_member(root, "nixbld1", "nixbld").await?;
+
+        assert_eq!(
+            user(root, "nixbld1")?,
+            Some(UserEntry {
+                uid: 30001,
+                gid: 30000
+            })
+        );
+        assert_eq!(
+            group(root, "nixbld")?,
+            Some(GroupEntry {
+                gid: 30000,
+                members: vec!["nixbld1".to_string()]
+            })
+        );
+        assert_eq!(user(root, "nixbld2")?, None);
+        let shadow = tokio::fs::read_to_string(root.join("etc/shadow")).await?;
+        assert!(shadow.lines().any(|line| line.starts_with("nixbld1:!:")));
+        assert!(
+            !root.join("etc/gshadow").exists(),
+            "A missing gshadow should not be created"
+        );
+        assert!(
+            create_user(root, "nixbld1", 30001, 30000, "Nix build user 1")
+                .await
+                .is_err()
+        );
+
+        remove_member(root, "nixbld1", "nixbld").await?;
+        delete_user(root, "nixbld1").await?;
+        delete_group(root, "nixbld").await?;
+
+        assert_eq!(
+            tokio::fs::read_to_string(root.join("etc/passwd")).await?,
+            PASSWD_BUF
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(root.join("etc/group")).await?,
+            GROUP_BUF
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(root.join("etc/shadow")).await?,
+            SHADOW_BUF
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn locks_password() -> eyre::Result<()> {
+        let root = image().await?;
+        let root = root.path();
+        tokio::fs::write(
+            root.join("etc/shadow"),
+            format!("{SHADOW_BUF}nixbld1:$6$salt$hash:19000:0:99999:7:19000::\n"),
+        )
+        .await?;
+
+        lock_password(root, "nixbld1").await?;
+
+        assert_eq!(
+            tokio::fs::read_to_string(root.join("etc/shadow")).await?,
+            format!("{SHADOW_BUF}nixbld1:!:19000:0:99999:7:19000::\n")
+        );
+        assert!(lock_password(root, "nixbld2").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn adding_to_a_missing_group_fails() -> eyre::Result<()> {
+        let root = image().await?;
+
+        assert!(add_member(root.path(), "nixbld1", "nixbld").await.is_err());
+
+        Ok(())
+    }
+}
@@ -1,17 +1,42 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use bytes::{Buf, Bytes};
-use reqwest::Url;
-use tracing::{span, Span};
+use bytes::{Buf, Bytes, BytesMut};
+use rand::Rng;
+use reqwest::{header::RANGE, StatusCode, Url};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc::{Receiver, Sender},
+};
+use tracing::{span, Instrument, Span};
 
 use crate::{
-    action::{Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction},
+    action::{
+        Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, Sha256Mismatch,
+        SignatureMismatch, StatefulAction,
+    },
     parse_ssl_cert,
-    settings::UrlOrPath,
+    settings::{CommonSettings, NixPackagePublicKey, NixPackageSha256, UrlOrPath},
 };
 
+/// How many chunks of the tarball may be waiting to be unpacked, which bounds the memory used to fetch it
+const CHUNKS_IN_FLIGHT: usize = 16;
+/// The size of the chunks a tarball is read from a file in
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How often progress is reported when the size of the tarball is not known
+const PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+/// The name a tarball is downloaded to in `dest` when it has to be checked before it is unpacked
+const DOWNLOAD_NAME: &str = ".nix-package.tar.xz";
+
 /**
 Fetch a URL to the given path
+
+The tarball is unpacked as it is fetched, and anything unpacked is removed again if either fails.
+If a SHA-256 or a signature is given, the tarball is checked before any of it is unpacked.
 */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "fetch_and_unpack_nix")]
@@ -20,23 +45,68 @@ pub struct FetchAndUnpackNix {
     dest: PathBuf,
     proxy: Option<Url>,
     ssl_cert_file: Option<PathBuf>,
+    /// The SHA-256 the tarball must have
+    #[serde(default)]
+    sha256: Option<NixPackageSha256>,
+    /// A minisign signature of the tarball, which `public_key` must have made
+    #[serde(default)]
+    signature: Option<UrlOrPath>,
+    #[serde(default)]
+    public_key: Option<NixPackagePublicKey>,
+    /// The SHA-256 of the tarball which was unpacked
+    #[serde(default)]
+    unpacked_sha256: Option<NixPackageSha256>,
+    /// Mirrors of `url_or_path`, tried in order once it fails
+    #[serde(default)]
+    mirrors: Vec<Url>,
+    /// How many times fetching from each URL is retried
+    #[serde(default)]
+    retries: u32,
+    /// A directory tarballs are reused from, and fetched ones are added to
+    #[serde(default)]
+    cache: Option<PathBuf>,
 }
 
 impl FetchAndUnpackNix {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
-        url_or_path: Option<UrlOrPath>,
+        settings: &CommonSettings,
         dest: PathBuf,
-        proxy: Option<Url>,
-        ssl_cert_file: Option<PathBuf>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         // TODO(@hoverbear): Check URL exists?
         // TODO(@hoverbear): Check tempdir exists
+        let CommonSettings {
+            nix_package_url: url_or_path,
+            nix_package_sha256: sha256,
+            nix_package_signature: signature,
+            nix_package_public_key: public_key,
+            nix_package_mirrors: mirrors,
+            nix_package_retries: retries,
+            nix_package_cache: cache,
+            proxy,
+            ssl_cert_file,
+            ..
+        } = settings.clone();
 
-        if let Some(UrlOrPath::Url(url)) = &url_or_path {
-            match url.scheme() {
-                "https" | "http" | "file" => (),
-                _ => return Err(Self::error(ActionErrorKind::UnknownUrlScheme)),
+        for url_or_path in [&url_or_path, &signature].into_iter().flatten() {
+            Source::new(url_or_path).map_err(Self::error)?;
+        }
+
+        if signature.is_some() != public_key.is_some() {
+            return Err(Self::error(FetchUrlError::IncompleteSignature));
+        }
+
+        if !mirrors.is_empty() {
+            if !matches!(
+                url_or_path.as_ref().map(Source::new),
+                Some(Ok(Source::Http(_)))
+            ) {
+                return Err(Self::error(FetchUrlError::MirrorsWithoutUrl));
+            }
+            for mirror in &mirrors {
+                if !matches!(mirror.scheme(), "https" | "http") {
+                    return Err(Self::error(ActionErrorKind::UnknownUrlScheme));
+                }
             }
         }
 
@@ -56,9 +126,532 @@ impl FetchAndUnpackNix {
             dest,
             proxy,
             ssl_cert_file,
+            sha256,
+            signature,
+            public_key,
+            unpacked_sha256: None,
+            mirrors,
+            retries,
+            cache,
         }
         .into())
     }
+
+    /// How the tarball is named in messages
+    fn package(&self) -> String {
+        match &self.url_or_path {
+            Some(url_or_path) => url_or_path.to_string(),
+            None => "bundled Nix".to_string(),
+        }
+    }
+
+    async fn check_and_unpack(&self) -> Result<NixPackageSha256, ActionError> {
+        let res = self.fetch_check_and_unpack().await;
+        // Offline, a tarball is reused by its URL alone, a fetched one could have moved on from it
+        if let (Err(err), Some(cache), None, Some(UrlOrPath::Url(url))) =
+            (&res, &self.cache, &self.sha256, &self.url_or_path)
+        {
+            if is_fetch_failure(err) {
+                if let Some(cached) = self.cached_by_url(cache, url).await {
+                    tracing::warn!(
+                        "Fetching `{url}` failed, using the Nix package last fetched from it, cached at `{}`",
+                        cached.display()
+                    );
+                    return self.check_then_unpack(Some(&cached)).await;
+                }
+            }
+        }
+        res
+    }
+
+    async fn fetch_check_and_unpack(&self) -> Result<NixPackageSha256, ActionError> {
+        let source = self
+            .url_or_path
+            .as_ref()
+            .map(Source::new)
+            .transpose()
+            .map_err(Self::error)?;
+
+        if let Some(cache) = &self.cache {
+            if let Some(cached) = self.cached_by_sha256(cache).await {
+                tracing::info!("Using the Nix package cached at `{}`", cached.display());
+                return self.check_then_unpack(Some(&cached)).await;
+            }
+            if let Some(Source::Http(url)) = &source {
+                // Downloaded into the cache, and only added to it once it checks out
+                match create_partial(cache).await {
+                    Ok(partial) => {
+                        let res = match self.download(url, &partial).await {
+                            Ok(()) => self.check_then_unpack(Some(&partial)).await,
+                            Err(err) => Err(err),
+                        };
+                        match &res {
+                            Ok(sha256) => add_to_cache(cache, url, &partial, sha256).await,
+                            Err(_) => {
+                                tokio::fs::remove_file(&partial).await.ok();
+                            },
+                        }
+                        return res;
+                    },
+                    Err(err) => tracing::warn!(
+                        error = %err,
+                        "Could not write to the Nix package cache at `{}`, fetching the Nix package without it",
+                        cache.display()
+                    ),
+                }
+            }
+        }
+
+        if self.sha256.is_none() && self.signature.is_none() {
+            return self.unpack_from(source.as_ref()).await;
+        }
+
+        // Nothing may be unpacked before the tarball is checked, so one which is not local is downloaded first
+        let (local, downloaded) = match source {
+            None => (None, false),
+            Some(Source::File(path)) => (Some(path), false),
+            Some(Source::Http(url)) => {
+                let download = self.dest.join(DOWNLOAD_NAME);
+                self.download(&url, &download).await?;
+                (Some(download), true)
+            },
+        };
+
+        let res = self.check_then_unpack(local.as_deref()).await;
+        if let (Some(download), true) = (&local, downloaded) {
+            if let Err(e) = tokio::fs::remove_file(download).await {
+                if res.is_ok() && e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(Self::error(ActionErrorKind::Remove(download.clone(), e)));
+                }
+            }
+        }
+        res
+    }
+
+    /// The Nix package in `cache` with the SHA-256 it must have, if that is known and the tarball is there and intact
+    async fn cached_by_sha256(&self, cache: &Path) -> Option<PathBuf> {
+        let sha256 = self.sha256.as_ref()?;
+        cached(cache, &cache_name(sha256)).await
+    }
+
+    /// The Nix package last fetched from `url` into `cache`, if it is there and intact
+    async fn cached_by_url(&self, cache: &Path, url: &Url) -> Option<PathBuf> {
+        let name = tokio::fs::read_to_string(cache.join(url_name(url)))
+            .await
+            .ok()?;
+        cached(cache, name.trim()).await
+    }
+
+    /// Check the local (or bundled, if `None`) tarball, and unpack it if it passes
+    async fn check_then_unpack(
+        &self,
+        local: Option<&Path>,
+    ) -> Result<NixPackageSha256, ActionError> {
+        let checked = self.check(local).await?;
+        let unpacked = self
+            .unpack_from(local.map(|path| Source::File(path.to_path_buf())).as_ref())
+            .await?;
+        if unpacked != checked {
+            // The tarball changed after it was checked
+            return Err(Self::error(ActionErrorKind::NixPackageSha256Mismatch(
+                Box::new(Sha256Mismatch {
+                    package: self.package(),
+                    expected: checked,
+                    actual: unpacked,
+                }),
+            )));
+        }
+        Ok(unpacked)
+    }
+
+    /// Check the SHA-256 and signature of the local (or bundled, if `None`) tarball
+    async fn check(&self, local: Option<&Path>) -> Result<NixPackageSha256, ActionError> {
+        let signature = match (&self.signature, &self.public_key) {
+            (Some(signature_source), Some(public_key)) => Some((
+                signature_source,
+                public_key,
+                self.fetch_signature(signature_source).await?,
+            )),
+            (None, None) => None,
+            _ => return Err(Self::error(FetchUrlError::IncompleteSignature)),
+        };
+        // Both are checked in the same pass over the tarball, which is never in memory at once
+        let mut verifier = match &signature {
+            Some((signature_source, public_key, signature)) => Some(
+                public_key
+                    .minisign()
+                    .verify_stream(signature)
+                    .map_err(|e| match e {
+                        minisign_verify::Error::UnsupportedLegacyMode => Self::error(
+                            FetchUrlError::LegacySignature(signature_source.to_string()),
+                        ),
+                        _ => self.signature_mismatch(signature_source, public_key),
+                    })?,
+            ),
+            None => None,
+        };
+
+        let sha256 = match local {
+            Some(path) => hash_file(path, verifier.as_mut())
+                .await
+                .map_err(Self::error)?,
+            None => {
+                if let Some(verifier) = &mut verifier {
+                    verifier.update(crate::settings::NIX_TARBALL);
+                }
+                NixPackageSha256::of(crate::settings::NIX_TARBALL)
+            },
+        };
+        if let Some(expected) = self.sha256 {
+            if expected != sha256 {
+                return Err(Self::error(ActionErrorKind::NixPackageSha256Mismatch(
+                    Box::new(Sha256Mismatch {
+                        package: self.package(),
+                        expected,
+                        actual: sha256,
+                    }),
+                )));
+            }
+            tracing::debug!(%sha256, "Checked the SHA-256 of the Nix tarball");
+        }
+
+        if let (Some(mut verifier), Some((signature_source, public_key, _))) =
+            (verifier, &signature)
+        {
+            verifier
+                .finalize()
+                .map_err(|_| self.signature_mismatch(signature_source, public_key))?;
+            tracing::debug!(%public_key, "Checked the signature of the Nix tarball");
+        }
+
+        Ok(sha256)
+    }
+
+    fn signature_mismatch(
+        &self,
+        signature: &UrlOrPath,
+        public_key: &NixPackagePublicKey,
+    ) -> ActionError {
+        Self::error(ActionErrorKind::NixPackageSignatureMismatch(Box::new(
+            SignatureMismatch {
+                package: self.package(),
+                signature: signature.to_string(),
+                public_key: public_key.clone(),
+            },
+        )))
+    }
+
+    async fn fetch_signature(
+        &self,
+        signature: &UrlOrPath,
+    ) -> Result<minisign_verify::Signature, ActionError> {
+        let bytes = match Source::new(signature).map_err(Self::error)? {
+            Source::Http(url) => self
+                .request(&url)
+                .await?
+                .bytes()
+                .await
+                .map_err(ActionErrorKind::Reqwest)
+                .map_err(Self::error)?
+                .to_vec(),
+            Source::File(path) => tokio::fs::read(&path)
+                .await
+                .map_err(|e| ActionErrorKind::Read(path, e))
+                .map_err(Self::error)?,
+        };
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|text| minisign_verify::Signature::decode(text).ok())
+            .ok_or_else(|| Self::error(FetchUrlError::InvalidSignature(signature.to_string())))
+    }
+
+    async fn client(&self) -> Result<reqwest::Client, ActionError> {
+        let mut buildable_client = reqwest::Client::builder();
+        if let Some(proxy) = &self.proxy {
+            buildable_client = buildable_client.proxy(
+                reqwest::Proxy::all(proxy.clone())
+                    .map_err(ActionErrorKind::Reqwest)
+                    .map_err(Self::error)?,
+            )
+        }
+        if let Some(ssl_cert_file) = &self.ssl_cert_file {
+            let ssl_cert = parse_ssl_cert(ssl_cert_file).await.map_err(Self::error)?;
+            buildable_client = buildable_client.add_root_certificate(ssl_cert);
+        }
+        buildable_client
+            .build()
+            .map_err(ActionErrorKind::Reqwest)
+            .map_err(Self::error)
+    }
+
+    async fn request(&self, url: &Url) -> Result<reqwest::Response, ActionError> {
+        let client = self.client().await?;
+        let req = client
+            .get(url.clone())
+            .build()
+            .map_err(ActionErrorKind::Reqwest)
+            .map_err(Self::error)?;
+        client
+            .execute(req)
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(ActionErrorKind::Reqwest)
+            .map_err(Self::error)
+    }
+
+    /// Download the tarball at `url` (or its mirrors) to `download`
+    async fn download(&self, url: &Url, download: &Path) -> Result<(), ActionError> {
+        if let Some(parent) = download.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| ActionErrorKind::CreateDirectory(parent.to_path_buf(), e))
+                .map_err(Self::error)?;
+        }
+        let write_error =
+            |e: std::io::Error| Self::error(ActionErrorKind::Write(download.to_path_buf(), e));
+        let mut file = tokio::fs::File::create(download)
+            .await
+            .map_err(write_error)?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(CHUNKS_IN_FLIGHT);
+        let source = Source::Http(url.clone());
+        let fetch = async {
+            let fetched = self.fetch(Some(&source), &tx).await;
+            drop(tx);
+            fetched
+        };
+        let write = async {
+            while let Some(chunk) = rx.recv().await {
+                file.write_all(&chunk.map_err(write_error)?)
+                    .await
+                    .map_err(write_error)?;
+            }
+            file.flush().await.map_err(write_error)
+        };
+        let (fetched, written) = tokio::join!(fetch, write);
+        fetched?;
+        written?;
+
+        Ok(())
+    }
+
+    /// Unpack the tarball (or the bundled one, if `None`) while it is fetched, keeping at most [`CHUNKS_IN_FLIGHT`] chunks of it in memory
+    async fn unpack_from(&self, source: Option<&Source>) -> Result<NixPackageSha256, ActionError> {
+        let (tx, rx) = tokio::sync::mpsc::channel(CHUNKS_IN_FLIGHT);
+        let dest = self.dest.clone();
+        let unpack = tokio::task::spawn_blocking(move || {
+            let mut reader = ChunkReader::new(rx);
+            unpack(&mut reader, &dest)?;
+            // Anything after the compressed stream is not unpacked, but is still part of what was checked and hashed
+            std::io::copy(&mut reader, &mut std::io::sink()).map_err(FetchUrlError::Unarchive)?;
+            Ok::<_, FetchUrlError>(())
+        });
+
+        let fetched = self.fetch(source, &tx).await;
+        if fetched.is_err() {
+            // Stop the unpack, rather than have it treat what was fetched so far as the whole tarball
+            tx.send(Err(std::io::Error::other("fetching the tarball failed")))
+                .await
+                .ok();
+        }
+        drop(tx);
+
+        let unpacked = unpack
+            .await
+            .map_err(ActionErrorKind::Join)
+            .map_err(Self::error)?;
+        let sha256 = fetched?;
+        unpacked.map_err(Self::error)?;
+        Ok(sha256)
+    }
+
+    /// Send the tarball to `tx` in chunks, stopping early if the unpack stopped receiving them, and return its SHA-256
+    async fn fetch(
+        &self,
+        source: Option<&Source>,
+        tx: &Sender<std::io::Result<Bytes>>,
+    ) -> Result<NixPackageSha256, ActionError> {
+        let mut digest = ring::digest::Context::new(&ring::digest::SHA256);
+        match source {
+            None => {
+                let tarball = crate::settings::NIX_TARBALL;
+                let mut progress = Progress::new(Some(tarball.len() as u64));
+                for chunk in tarball.chunks(CHUNK_SIZE) {
+                    progress.advance(chunk.len());
+                    digest.update(chunk);
+                    if tx.send(Ok(Bytes::from_static(chunk))).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            Some(Source::Http(url)) => self.fetch_http(url, tx, &mut digest).await?,
+            Some(Source::File(path)) => {
+                let read_error =
+                    |e: std::io::Error| Self::error(ActionErrorKind::Read(path.clone(), e));
+                let mut file = tokio::fs::File::open(path).await.map_err(read_error)?;
+                let len = file.metadata().await.map_err(read_error)?.len();
+
+                let mut progress = Progress::new(Some(len));
+                loop {
+                    let mut chunk = BytesMut::with_capacity(CHUNK_SIZE);
+                    let read = file.read_buf(&mut chunk).await.map_err(read_error)?;
+                    if read == 0 {
+                        break;
+                    }
+                    progress.advance(read);
+                    digest.update(&chunk);
+                    if tx.send(Ok(chunk.freeze())).await.is_err() {
+                        break;
+                    }
+                }
+            },
+        }
+        Ok(NixPackageSha256::from_digest(digest.finish()))
+    }
+
+    /// Fetch from `url`, then each mirror in turn, retrying each up to `retries` times
+    ///
+    /// Each attempt resumes where the last one stopped, so what was already sent to `tx` is not sent again. A mirror
+    /// is only resumed from if the tarball is checked against a SHA-256 afterwards, otherwise it is fetched from the
+    /// start and must begin with what was already sent.
+    async fn fetch_http(
+        &self,
+        url: &Url,
+        tx: &Sender<std::io::Result<Bytes>>,
+        digest: &mut ring::digest::Context,
+    ) -> Result<(), ActionError> {
+        let client = self.client().await?;
+        let mut fetched = Fetched {
+            len: 0,
+            from: None,
+            progress: None,
+        };
+        // A tarball joined from two mirrors which differ would fail the SHA-256 check
+        let resume_across_urls = self.sha256.is_some();
+        let mut attempts = 0;
+        let mut last_error = None;
+        for url in std::iter::once(url).chain(&self.mirrors) {
+            for retry in 0..=self.retries {
+                if retry > 0 {
+                    tokio::time::sleep(backoff(retry)).await;
+                }
+                attempts += 1;
+                let span = span!(
+                    tracing::Level::DEBUG,
+                    "fetch_attempt",
+                    url = %url,
+                    attempt = attempts,
+                    resume_from = fetched.len,
+                );
+                let res = fetch_attempt(&client, url, &mut fetched, resume_across_urls, tx, digest)
+                    .instrument(span.clone())
+                    .await;
+                match res {
+                    Ok(()) => return Ok(()),
+                    Err(err) => {
+                        // Another attempt would get the same answer, so move on to the next mirror
+                        let permanent = match &err {
+                            FetchUrlError::Http(err) => err.status().is_some_and(|status| {
+                                status.is_client_error()
+                                    && status != StatusCode::REQUEST_TIMEOUT
+                                    && status != StatusCode::TOO_MANY_REQUESTS
+                            }),
+                            _ => true,
+                        };
+                        span.in_scope(|| tracing::warn!(error = %err, "Fetching `{url}` failed"));
+                        last_error = Some(err);
+                        if permanent {
+                            break;
+                        }
+                    },
+                }
+            }
+        }
+
+        Err(Self::error(FetchUrlError::Exhausted(
+            attempts,
+            Box::new(last_error.expect("At least one attempt is made")),
+        )))
+    }
+}
+
+/// What was fetched over HTTP so far, across attempts
+struct Fetched {
+    len: u64,
+    /// The URL the last of it came from
+    from: Option<Url>,
+    progress: Option<Progress>,
+}
+
+/// How long to wait before the `retry`th retry of a URL: one second, doubling each retry up to a minute
+fn backoff(retry: u32) -> Duration {
+    Duration::from_secs(1 << (retry - 1).min(6)).min(Duration::from_secs(60))
+}
+
+/// Fetch `url` from where the last attempt stopped, sending the rest of it to `tx`
+///
+/// Another URL is fetched from the start unless `resume_across_urls`, and what it sends again must match what was fetched.
+async fn fetch_attempt(
+    client: &reqwest::Client,
+    url: &Url,
+    fetched: &mut Fetched,
+    resume_across_urls: bool,
+    tx: &Sender<std::io::Result<Bytes>>,
+    digest: &mut ring::digest::Context,
+) -> Result<(), FetchUrlError> {
+    let same_url = fetched.from.as_ref() == Some(url);
+    let resume = fetched.len > 0 && (same_url || resume_across_urls);
+    let mut req = client.get(url.clone());
+    if resume {
+        req = req.header(RANGE, format!("bytes={}-", fetched.len));
+    }
+    let res = req.send().await.map_err(FetchUrlError::Http)?;
+    if resume && same_url && res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The last attempt failed after fetching everything
+        return Ok(());
+    }
+    let mut res = res.error_for_status().map_err(FetchUrlError::Http)?;
+
+    // A server which does not support ranges, or one started over from, sends everything again
+    let mut skip = match (resume, res.status()) {
+        (true, StatusCode::PARTIAL_CONTENT) => 0,
+        _ => fetched.len,
+    };
+    if skip > 0 {
+        tracing::debug!(skip, "Fetching from the start, skipping what was fetched");
+    }
+    let expected_prefix = digest.clone().finish();
+    let mut prefix = ring::digest::Context::new(&ring::digest::SHA256);
+    let progress = fetched
+        .progress
+        .get_or_insert_with(|| Progress::new(res.content_length()));
+    while let Some(mut chunk) = res.chunk().await.map_err(FetchUrlError::Http)? {
+        if skip > 0 {
+            let skipped = skip.min(chunk.len() as u64);
+            prefix.update(&chunk[..skipped as usize]);
+            chunk.advance(skipped as usize);
+            skip -= skipped;
+            if skip == 0 && prefix.clone().finish().as_ref() != expected_prefix.as_ref() {
+                return Err(FetchUrlError::SourceChanged(url.clone()));
+            }
+        }
+        if chunk.is_empty() {
+            continue;
+        }
+        fetched.len += chunk.len() as u64;
+        fetched.from = Some(url.clone());
+        progress.advance(chunk.len());
+        digest.update(&chunk);
+        if tx.send(Ok(chunk)).await.is_err() {
+            // The unpack stopped
+            break;
+        }
+    }
+    if skip > 0 {
+        // It ended before what was already fetched
+        return Err(FetchUrlError::SourceChanged(url.clone()));
+    }
+    Ok(())
 }
 
 #[async_trait::async_trait]
@@ -85,6 +678,8 @@ impl Action for FetchAndUnpackNix {
             url_or_path = self.url_or_path.as_ref().map(tracing::field::display),
             proxy = tracing::field::Empty,
             ssl_cert_file = tracing::field::Empty,
+            sha256 = tracing::field::Empty,
+            signature = tracing::field::Empty,
             dest = tracing::field::display(self.dest.display()),
         );
         if let Some(proxy) = &self.proxy {
@@ -96,87 +691,55 @@ impl Action for FetchAndUnpackNix {
                 tracing::field::display(&ssl_cert_file.display()),
             );
         }
+        if let Some(sha256) = &self.sha256 {
+            span.record("sha256", tracing::field::display(sha256));
+        }
+        if let Some(signature) = &self.signature {
+            span.record("signature", tracing::field::display(signature));
+        }
         span
     }
 
     fn execute_description(&self) -> Vec<ActionDescription> {
-        vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+        let mut explanation = vec![];
+        if let Some(sha256) = &self.sha256 {
+            explanation.push(format!("Check that its SHA-256 is `{sha256}`"));
+        }
+        if let (Some(signature), Some(public_key)) = (&self.signature, &self.public_key) {
+            explanation.push(format!(
+                "Check that `{signature}` is its signature by `{public_key}`"
+            ));
+        }
+        if let Some(cache) = &self.cache {
+            explanation.push(format!(
+                "Reuse it from `{}` if it is there, or add it there once fetched",
+                cache.display()
+            ));
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let bytes = match &self.url_or_path {
-            &None => Bytes::from(crate::settings::NIX_TARBALL),
-            Some(UrlOrPath::Url(url)) => {
-                let bytes = match url.scheme() {
-                    "https" | "http" => {
-                        let mut buildable_client = reqwest::Client::builder();
-                        if let Some(proxy) = &self.proxy {
-                            buildable_client = buildable_client.proxy(
-                                reqwest::Proxy::all(proxy.clone())
-                                    .map_err(ActionErrorKind::Reqwest)
-                                    .map_err(Self::error)?,
-                            )
-                        }
-                        if let Some(ssl_cert_file) = &self.ssl_cert_file {
-                            let ssl_cert =
-                                parse_ssl_cert(ssl_cert_file).await.map_err(Self::error)?;
-                            buildable_client = buildable_client.add_root_certificate(ssl_cert);
-                        }
-                        let client = buildable_client
-                            .build()
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?;
-                        let req = client
-                            .get(url.clone())
-                            .build()
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?;
-                        let res = client
-                            .execute(req)
-                            .await
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?;
-                        res.bytes()
-                            .await
-                            .map_err(ActionErrorKind::Reqwest)
-                            .map_err(Self::error)?
-                    },
-                    "file" => {
-                        let buf = tokio::fs::read(url.path())
-                            .await
-                            .map_err(|e| ActionErrorKind::Read(PathBuf::from(url.path()), e))
-                            .map_err(Self::error)?;
-                        Bytes::from(buf)
-                    },
-                    _ => return Err(Self::error(ActionErrorKind::UnknownUrlScheme)),
-                };
-                bytes
+        let existing = existing_entries(&self.dest).await.map_err(Self::error)?;
+
+        let unpacked = match self.check_and_unpack().await {
+            Ok(sha256) => check_layout(&self.dest, existing.as_ref())
+                .await
+                .map(|()| sha256)
+                .map_err(Self::error),
+            Err(err) => Err(err),
+        };
+        match unpacked {
+            Ok(sha256) => {
+                self.unpacked_sha256 = Some(sha256);
+                Ok(())
             },
-            Some(UrlOrPath::Path(path)) => {
-                let buf = tokio::fs::read(path)
-                    .await
-                    .map_err(|e| ActionErrorKind::Read(PathBuf::from(path), e))
-                    .map_err(Self::error)?;
-                Bytes::from(buf)
+            Err(err) => {
+                remove_partial_unpack(&self.dest, existing).await;
+                Err(err)
             },
-        };
-
-        // TODO(@Hoverbear): Pick directory
-        tracing::trace!("Unpacking tar.xz");
-        let dest_clone = self.dest.clone();
-
-        let decoder = xz2::read::XzDecoder::new(bytes.reader());
-        let mut archive = tar::Archive::new(decoder);
-        archive.set_preserve_permissions(true);
-        archive.set_preserve_mtime(true);
-        archive.set_unpack_xattrs(true);
-        archive
-            .unpack(&dest_clone)
-            .map_err(FetchUrlError::Unarchive)
-            .map_err(Self::error)?;
-
-        Ok(())
+        }
     }
 
     fn revert_description(&self) -> Vec<ActionDescription> {
@@ -189,6 +752,352 @@ impl Action for FetchAndUnpackNix {
     }
 }
 
+/// The tarball called `name` in `cache`, if it is there and still matches the SHA-256 it is named after
+async fn cached(cache: &Path, name: &str) -> Option<PathBuf> {
+    let cached = cache.join(name);
+    let sha256 = hash_file(&cached, None).await.ok()?;
+    if cache_name(&sha256) != name {
+        tracing::warn!(
+            "`{}` in the Nix package cache does not match its SHA-256, ignoring it",
+            cached.display()
+        );
+        return None;
+    }
+    Some(cached)
+}
+
+/// Whether `err` is from failing to fetch the tarball, rather than from checking or unpacking it
+fn is_fetch_failure(err: &ActionError) -> bool {
+    match err.kind() {
+        ActionErrorKind::Custom(err) => matches!(
+            err.downcast_ref::<FetchUrlError>(),
+            Some(FetchUrlError::Exhausted(..))
+        ),
+        _ => false,
+    }
+}
+
+/// The name of a tarball in the cache
+fn cache_name(sha256: &NixPackageSha256) -> String {
+    format!("sha256-{}", sha256.hex())
+}
+
+/// The name of the file in the cache which holds the [`cache_name`] of the tarball fetched from `url`
+fn url_name(url: &Url) -> String {
+    format!(
+        "url-{}",
+        NixPackageSha256::of(url.as_str().as_bytes()).hex()
+    )
+}
+
+/// Create an empty file in `cache` to download a tarball into, before it is added to the cache
+async fn create_partial(cache: &Path) -> Result<PathBuf, std::io::Error> {
+    tokio::fs::create_dir_all(cache).await?;
+    let partial = {
+        let mut rng = rand::thread_rng();
+        cache.join(format!(".partial.{}", rng.gen::<u32>()))
+    };
+    tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)
+        .await?;
+    Ok(partial)
+}
+
+/// Move the `partial` download of `url` into `cache`, which is best effort: the install goes on without it
+async fn add_to_cache(cache: &Path, url: &Url, partial: &Path, sha256: &NixPackageSha256) {
+    let cached = cache.join(cache_name(sha256));
+    let url_file = cache.join(url_name(url));
+    let url_partial = {
+        let mut url_partial = partial.as_os_str().to_owned();
+        url_partial.push(".url");
+        PathBuf::from(url_partial)
+    };
+    let res = async {
+        tokio::fs::rename(partial, &cached).await?;
+        // Written then renamed, so it never names a tarball only in part
+        tokio::fs::write(&url_partial, format!("{}\n", cache_name(sha256))).await?;
+        tokio::fs::rename(&url_partial, &url_file).await
+    }
+    .await;
+    match res {
+        Ok(()) => tracing::debug!(
+            "Added `{url}` to the Nix package cache at `{}`",
+            cached.display()
+        ),
+        Err(err) => {
+            tracing::warn!(error = %err, "Could not add `{url}` to the Nix package cache at `{}`", cache.display());
+            tokio::fs::remove_file(partial).await.ok();
+            tokio::fs::remove_file(&url_partial).await.ok();
+        },
+    }
+}
+
+/// Where a tarball or signature is read from
+enum Source {
+    Http(Url),
+    File(PathBuf),
+}
+
+impl Source {
+    fn new(url_or_path: &UrlOrPath) -> Result<Self, ActionErrorKind> {
+        match url_or_path {
+            UrlOrPath::Url(url) => match url.scheme() {
+                "https" | "http" => Ok(Self::Http(url.clone())),
+                "file" => Ok(Self::File(PathBuf::from(url.path()))),
+                _ => Err(ActionErrorKind::UnknownUrlScheme),
+            },
+            UrlOrPath::Path(path) => Ok(Self::File(path.clone())),
+        }
+    }
+}
+
+/// The SHA-256 of the file at `path`, which is also fed to `verifier` as it is read
+async fn hash_file(
+    path: &Path,
+    mut verifier: Option<&mut minisign_verify::StreamVerifier<'_>>,
+) -> Result<NixPackageSha256, ActionErrorKind> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+    let mut digest = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| ActionErrorKind::Read(path.to_path_buf(), e))?;
+        if read == 0 {
+            break;
+        }
+        digest.update(&buf[..read]);
+        if let Some(verifier) = &mut verifier {
+            verifier.update(&buf[..read]);
+        }
+    }
+    Ok(NixPackageSha256::from_digest(digest.finish()))
+}
+
+/// The entries of `dir` before unpacking into it, or `None` if it does not exist yet
+async fn existing_entries(dir: &Path) -> Result<Option<HashSet<PathBuf>>, ActionErrorKind> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ActionErrorKind::ReadDir(dir.to_path_buf(), e)),
+    };
+    let mut existing = HashSet::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| ActionErrorKind::ReadDir(dir.to_path_buf(), e))?
+    {
+        existing.insert(entry.path());
+    }
+    Ok(Some(existing))
+}
+
+/// Remove what a failed unpack left in `dir`, keeping anything which was there before it
+async fn remove_partial_unpack(dir: &Path, existing: Option<HashSet<PathBuf>>) {
+    let Some(existing) = existing else {
+        if let Err(err) = tokio::fs::remove_dir_all(dir).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(error = %err, "Could not remove partially unpacked `{}`", dir.display());
+            }
+        }
+        return;
+    };
+
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if existing.contains(&path) {
+            continue;
+        }
+        let removed = match entry.file_type().await {
+            Ok(file_type) if file_type.is_dir() => tokio::fs::remove_dir_all(&path).await,
+            _ => tokio::fs::remove_file(&path).await,
+        };
+        if let Err(err) = removed {
+            tracing::warn!(error = %err, "Could not remove partially unpacked `{}`", path.display());
+        }
+    }
+}
+
+/// How a tarball is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Xz,
+    Zstd,
+    Gzip,
+    Uncompressed,
+}
+
+impl Compression {
+    /// How much of a tarball [`Compression::detect`] needs, enough to reach the magic of an uncompressed one
+    const MAGIC_LEN: usize = 262;
+
+    /// Detect the compression from the magic bytes at the start of a tarball
+    fn detect(start: &[u8]) -> Option<Self> {
+        match start {
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Self::Xz),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            // Both POSIX (`ustar\0`) and GNU (`ustar  \0`) tar headers
+            _ if start.get(257..262) == Some(b"ustar") => Some(Self::Uncompressed),
+            _ => None,
+        }
+    }
+}
+
+fn unpack(mut reader: impl Read, dest: &Path) -> Result<(), FetchUrlError> {
+    let mut start = Vec::with_capacity(Compression::MAGIC_LEN);
+    (&mut reader)
+        .take(Compression::MAGIC_LEN as u64)
+        .read_to_end(&mut start)
+        .map_err(FetchUrlError::Unarchive)?;
+    let compression = Compression::detect(&start).ok_or(FetchUrlError::UnknownCompression)?;
+    tracing::trace!(?compression, "Unpacking tarball");
+
+    let reader = std::io::Read::chain(std::io::Cursor::new(start), reader);
+    match compression {
+        Compression::Xz => unpack_tar(xz2::read::XzDecoder::new(reader), dest),
+        Compression::Zstd => unpack_tar(
+            zstd::stream::read::Decoder::new(reader).map_err(FetchUrlError::Unarchive)?,
+            dest,
+        ),
+        Compression::Gzip => unpack_tar(flate2::read::MultiGzDecoder::new(reader), dest),
+        Compression::Uncompressed => unpack_tar(reader, dest),
+    }
+}
+
+fn unpack_tar(decoder: impl Read, dest: &Path) -> Result<(), FetchUrlError> {
+    let mut archive = tar::Archive::new(decoder);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(true);
+    archive.unpack(dest).map_err(FetchUrlError::Unarchive)?;
+    // Read to the end of the tarball past the end of the archive, so all of it is hashed and its checksum is checked
+    std::io::copy(&mut archive.into_inner(), &mut std::io::sink())
+        .map_err(FetchUrlError::Unarchive)?;
+    Ok(())
+}
+
+/// Check what was unpacked into `dest` is a Nix binary tarball, a single `nix-*` directory with a `store` in it
+async fn check_layout(
+    dest: &Path,
+    existing: Option<&HashSet<PathBuf>>,
+) -> Result<(), ActionErrorKind> {
+    let mut entries = tokio::fs::read_dir(dest)
+        .await
+        .map_err(|e| ActionErrorKind::ReadDir(dest.to_path_buf(), e))?;
+    let mut unpacked = vec![];
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| ActionErrorKind::ReadDir(dest.to_path_buf(), e))?
+    {
+        let path = entry.path();
+        if !existing.is_some_and(|existing| existing.contains(&path)) {
+            unpacked.push(path);
+        }
+    }
+
+    match unpacked.as_slice() {
+        [nix]
+            if nix
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("nix-"))
+                && nix.join("store").is_dir() =>
+        {
+            Ok(())
+        },
+        _ => Err(ActionErrorKind::MalformedBinaryTarball),
+    }
+}
+
+/// Reads the chunks of a tarball as they are fetched, for the (blocking) unpack
+struct ChunkReader {
+    chunks: Receiver<std::io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl ChunkReader {
+    fn new(chunks: Receiver<std::io::Result<Bytes>>) -> Self {
+        Self {
+            chunks,
+            current: Bytes::new(),
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !self.current.has_remaining() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk?,
+                // The whole tarball was received
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.remaining());
+        self.current.copy_to_slice(&mut buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Reports how much of a tarball was fetched, every tenth of it, or every [`PROGRESS_INTERVAL`] if its size is unknown
+struct Progress {
+    received: u64,
+    total: Option<u64>,
+    next_report: u64,
+}
+
+impl Progress {
+    fn new(total: Option<u64>) -> Self {
+        let mut progress = Self {
+            received: 0,
+            total,
+            next_report: 0,
+        };
+        progress.next_report = progress.interval();
+        progress
+    }
+
+    fn interval(&self) -> u64 {
+        match self.total {
+            Some(total) => (total / 10).max(1),
+            None => PROGRESS_INTERVAL,
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.received += len as u64;
+        if self.received < self.next_report {
+            return;
+        }
+        while self.next_report <= self.received {
+            self.next_report += self.interval();
+        }
+
+        match self.total {
+            Some(total) => tracing::info!(
+                received = self.received,
+                total,
+                "Read {}% of the Nix tarball",
+                (self.received * 100 / total.max(1)).min(100)
+            ),
+            None => tracing::info!(
+                received = self.received,
+                "Read {} MiB of the Nix tarball",
+                self.received / (1024 * 1024)
+            ),
+        }
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug, thiserror::Error)]
 pub enum FetchUrlError {
@@ -196,6 +1105,26 @@ pub enum FetchUrlError {
     Unarchive(#[source] std::io::Error),
     #[error("Unknown proxy scheme, `https://`, `socks5://`, and `http://` supported")]
     UnknownProxyScheme,
+    #[error("Checking the signature of the Nix package needs both `--nix-package-signature` and `--nix-package-public-key`")]
+    IncompleteSignature,
+    #[error("`{0}` is not a minisign signature, as `minisign -S` writes to a `.minisig` file")]
+    InvalidSignature(String),
+    #[error(
+        "`{0}` is a legacy minisign signature, sign the Nix package again without `minisign -l`"
+    )]
+    LegacySignature(String),
+    #[error(
+        "The Nix package is not a tarball, either uncompressed or compressed with xz, zstd or gzip"
+    )]
+    UnknownCompression,
+    #[error("Mirrors of the Nix package need an `http://` or `https://` `--nix-package-url`")]
+    MirrorsWithoutUrl,
+    #[error(transparent)]
+    Http(reqwest::Error),
+    #[error("`{0}` does not serve the same Nix package as what was already fetched of it")]
+    SourceChanged(Url),
+    #[error("Fetching the Nix package failed after {0} attempts")]
+    Exhausted(u32, #[source] Box<FetchUrlError>),
 }
 
 impl From<FetchUrlError> for ActionErrorKind {
@@ -203,3 +1132,482 @@ impl From<FetchUrlError> for ActionErrorKind {
         ActionErrorKind::Custom(Box::new(val))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use base64::Engine as _;
+
+    use super::*;
+
+    /// Settings which fetch the Nix package from `url_or_path`
+    async fn settings(url_or_path: UrlOrPath) -> eyre::Result<CommonSettings> {
+        let mut settings = CommonSettings::default().await?;
+        settings.nix_package_url = Some(url_or_path);
+        Ok(settings)
+    }
+
+    /// Serves `body` over HTTP, dropping the first connection halfway through it, and sends where each request started
+    fn flaky_server(body: Vec<u8>) -> eyre::Result<(Url, std::sync::mpsc::Receiver<usize>)> {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/nix.tar.xz", listener.local_addr()?))?;
+        let (starts_tx, starts) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let Ok(mut stream) = stream else { continue };
+                let Ok(reader) = stream.try_clone() else {
+                    continue;
+                };
+                let mut range_start = None;
+                for line in std::io::BufReader::new(reader).lines() {
+                    let Ok(line) = line else { break };
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(range) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        range_start = range.trim_end_matches('-').parse::<usize>().ok();
+                    }
+                }
+                let start = range_start.unwrap_or(0);
+                starts_tx.send(start).ok();
+
+                let status = match range_start {
+                    Some(_) => "206 Partial Content",
+                    None => "200 OK",
+                };
+                let rest = &body[start..];
+                let head = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    rest.len()
+                );
+                let rest = match connection {
+                    0 => &rest[..rest.len() / 2],
+                    _ => rest,
+                };
+                stream.write_all(head.as_bytes()).ok();
+                stream.write_all(rest).ok();
+            }
+        });
+        Ok((url, starts))
+    }
+
+    /// Serves the first half of `body` over HTTP and then drops the connection, ignoring any range asked for
+    fn broken_server(body: Vec<u8>) -> eyre::Result<Url> {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/nix.tar.xz", listener.local_addr()?))?;
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let Ok(reader) = stream.try_clone() else {
+                    continue;
+                };
+                for line in std::io::BufReader::new(reader).lines() {
+                    let Ok(line) = line else { break };
+                    if line.is_empty() {
+                        break;
+                    }
+                }
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).ok();
+                stream.write_all(&body[..body.len() / 2]).ok();
+            }
+        });
+        Ok(url)
+    }
+
+    /// A `.tar.xz` of a Nix-like tree, large enough that it must be fetched in many chunks
+    fn tarball() -> eyre::Result<(Vec<u8>, Vec<u8>)> {
+        let (tar, contents) = tar("nix-2.23.3-x86_64-linux/store/blob")?;
+        Ok((compress(tar, Compression::Xz)?, contents))
+    }
+
+    /// An uncompressed tarball with only the file `path`, and the contents of that file
+    fn tar(path: &str) -> eyre::Result<(Vec<u8>, Vec<u8>)> {
+        // Incompressible, so the tarball is as large as its contents
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let contents = (0..CHUNKS_IN_FLIGHT * CHUNK_SIZE * 2)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, contents.as_slice())?;
+        Ok((builder.into_inner()?, contents))
+    }
+
+    fn compress(tar: Vec<u8>, compression: Compression) -> eyre::Result<Vec<u8>> {
+        use std::io::Write;
+
+        Ok(match compression {
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 0);
+                encoder.write_all(&tar)?;
+                encoder.finish()?
+            },
+            Compression::Zstd => zstd::stream::encode_all(tar.as_slice(), 1)?,
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(&tar)?;
+                encoder.finish()?
+            },
+            Compression::Uncompressed => tar,
+        })
+    }
+
+    #[tokio::test]
+    async fn unpacks_while_fetching() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, contents) = tarball()?;
+        let tarball_path = temp_dir.path().join("nix.tar.xz");
+        tokio::fs::write(&tarball_path, &tarball).await?;
+        let dest = temp_dir.path().join("unpacked");
+
+        let mut action = FetchAndUnpackNix::plan(
+            &settings(UrlOrPath::Path(tarball_path)).await?,
+            dest.clone(),
+        )
+        .await?;
+        action.try_execute().await?;
+
+        let unpacked = tokio::fs::read(dest.join("nix-2.23.3-x86_64-linux/store/blob")).await?;
+        assert!(unpacked == contents, "Unpacked contents differ");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removes_partial_unpack() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, _) = tarball()?;
+        let tarball_path = temp_dir.path().join("nix.tar.xz");
+        tokio::fs::write(&tarball_path, &tarball[..tarball.len() / 2]).await?;
+        let dest = temp_dir.path().join("unpacked");
+        tokio::fs::create_dir(&dest).await?;
+        tokio::fs::write(dest.join("existing"), "Existing").await?;
+
+        let mut action = FetchAndUnpackNix::plan(
+            &settings(UrlOrPath::Path(tarball_path)).await?,
+            dest.clone(),
+        )
+        .await?;
+        assert!(action.try_execute().await.is_err());
+
+        assert!(dest.join("existing").exists());
+        assert!(!dest.join("nix-2.23.3-x86_64-linux").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_wrong_sha256() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, _) = tarball()?;
+        let tarball_path = temp_dir.path().join("nix.tar.xz");
+        tokio::fs::write(&tarball_path, &tarball).await?;
+        let dest = temp_dir.path().join("unpacked");
+
+        let mut settings = settings(UrlOrPath::Path(tarball_path)).await?;
+        settings.nix_package_sha256 = Some(NixPackageSha256::of(b"Altered"));
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        let err = action.try_execute().await.unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                ActionErrorKind::NixPackageSha256Mismatch(mismatch) if mismatch.actual == NixPackageSha256::of(&tarball)
+            ),
+            "{err:?}"
+        );
+        assert!(!dest.join("nix-2.23.3-x86_64-linux").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checks_signature() -> eyre::Result<()> {
+        use blake2::Digest;
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, _) = tarball()?;
+        let tarball_path = temp_dir.path().join("nix.tar.xz");
+        tokio::fs::write(&tarball_path, &tarball).await?;
+        let rng = ring::rand::SystemRandom::new();
+        let key_pair = |rng: &ring::rand::SystemRandom| -> eyre::Result<Ed25519KeyPair> {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(rng).map_err(|e| eyre::eyre!("{e}"))?;
+            Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|e| eyre::eyre!("{e}"))
+        };
+        let (signer, other) = (key_pair(&rng)?, key_pair(&rng)?);
+        // Both keys share a key ID, so only the signature itself tells them apart
+        let key_id = [1, 2, 3, 4, 5, 6, 7, 8];
+        let base64 =
+            |parts: &[&[u8]]| base64::engine::general_purpose::STANDARD.encode(parts.concat());
+
+        // What `minisign -S -m nix.tar.xz` writes: a signature of the BLAKE2b-512 of the tarball,
+        // and one of that signature with the trusted comment
+        let signature = signer.sign(&blake2::Blake2b512::digest(&tarball));
+        let trusted_comment = "timestamp:1700000000\tfile:nix.tar.xz";
+        let global_signature =
+            signer.sign(&[signature.as_ref(), trusted_comment.as_bytes()].concat());
+        let signature_path = temp_dir.path().join("nix.tar.xz.minisig");
+        tokio::fs::write(
+            &signature_path,
+            format!(
+                "untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {trusted_comment}\n{}\n",
+                base64(&[b"ED", &key_id, signature.as_ref()]),
+                base64(&[global_signature.as_ref()]),
+            ),
+        )
+        .await?;
+
+        for (key, signed) in [(&signer, true), (&other, false)] {
+            let public_key = base64(&[b"Ed", &key_id, key.public_key().as_ref()])
+                .parse::<NixPackagePublicKey>()?;
+            let dest = temp_dir.path().join(format!("unpacked-{signed}"));
+            let mut settings = settings(UrlOrPath::Path(tarball_path.clone())).await?;
+            settings.nix_package_signature = Some(UrlOrPath::Path(signature_path.clone()));
+            settings.nix_package_public_key = Some(public_key);
+            let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+
+            match action.try_execute().await {
+                Ok(()) => assert!(signed, "Accepted a signature by another key"),
+                Err(err) => assert!(
+                    !signed
+                        && matches!(err.kind(), ActionErrorKind::NixPackageSignatureMismatch(_)),
+                    "{err:?}"
+                ),
+            }
+            assert_eq!(dest.join("nix-2.23.3-x86_64-linux").exists(), signed);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_resumes_and_falls_back_to_mirrors() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, contents) = tarball()?;
+        let (mirror, starts) = flaky_server(tarball.clone())?;
+        // Nothing listens on a port which was just freed
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+            Url::parse(&format!("http://{}/nix.tar.xz", listener.local_addr()?))?
+        };
+        let dest = temp_dir.path().join("unpacked");
+
+        let mut settings = settings(UrlOrPath::Url(unreachable)).await?;
+        settings.nix_package_mirrors = vec![mirror];
+        settings.nix_package_retries = 1;
+        settings.nix_package_sha256 = Some(NixPackageSha256::of(&tarball));
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        action.try_execute().await?;
+
+        let unpacked = tokio::fs::read(dest.join("nix-2.23.3-x86_64-linux/store/blob")).await?;
+        assert!(unpacked == contents, "Unpacked contents differ");
+        // The first request to the mirror was dropped halfway, the second resumed from there
+        assert_eq!(
+            starts.try_iter().collect::<Vec<_>>(),
+            vec![0, tarball.len() / 2]
+        );
+        assert!(!dest.join(DOWNLOAD_NAME).exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn starts_over_on_another_mirror() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tar, contents) = tar("nix-2.23.3-x86_64-linux/store/blob")?;
+        let tarball = compress(tar.clone(), Compression::Xz)?;
+        let other = compress(tar, Compression::Gzip)?;
+
+        // Without a SHA-256, a mirror is fetched from the start, and must send what was already fetched again
+        let (mirror, starts) = flaky_server(tarball.clone())?;
+        let mut settings = settings(UrlOrPath::Url(broken_server(tarball.clone())?)).await?;
+        settings.nix_package_mirrors = vec![mirror];
+        settings.nix_package_retries = 1;
+        let dest = temp_dir.path().join("unpacked");
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        action.try_execute().await?;
+
+        let unpacked = tokio::fs::read(dest.join("nix-2.23.3-x86_64-linux/store/blob")).await?;
+        assert!(unpacked == contents, "Unpacked contents differ");
+        // Nothing new came from the first request to the mirror, so the second started over too
+        assert_eq!(starts.try_iter().collect::<Vec<_>>(), vec![0, 0]);
+
+        // A mirror serving another tarball is not joined onto what was fetched
+        let (mirror, _starts) = flaky_server(other)?;
+        settings.nix_package_url = Some(UrlOrPath::Url(broken_server(tarball)?));
+        settings.nix_package_mirrors = vec![mirror.clone()];
+        let dest = temp_dir.path().join("unpacked-other");
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        let err = action.try_execute().await.unwrap_err();
+        let source_changed = match err.kind() {
+            ActionErrorKind::Custom(err) => err.downcast_ref::<FetchUrlError>().is_some_and(|err| {
+                matches!(err, FetchUrlError::Exhausted(3, last) if matches!(&**last, FetchUrlError::SourceChanged(url) if *url == mirror))
+            }),
+            _ => false,
+        };
+        assert!(source_changed, "{err:?}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detects_compression() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tar, contents) = tar("nix-2.23.3-x86_64-linux/store/blob")?;
+
+        for compression in [
+            Compression::Xz,
+            Compression::Zstd,
+            Compression::Gzip,
+            Compression::Uncompressed,
+        ] {
+            let tarball = compress(tar.clone(), compression)?;
+            assert_eq!(Compression::detect(&tarball), Some(compression));
+            let tarball_path = temp_dir.path().join(format!("{compression:?}.tar"));
+            tokio::fs::write(&tarball_path, &tarball).await?;
+            let dest = temp_dir.path().join(format!("{compression:?}"));
+
+            let mut action = FetchAndUnpackNix::plan(
+                &settings(UrlOrPath::Path(tarball_path)).await?,
+                dest.clone(),
+            )
+            .await?;
+            action.try_execute().await?;
+
+            let unpacked = tokio::fs::read(dest.join("nix-2.23.3-x86_64-linux/store/blob")).await?;
+            assert!(
+                unpacked == contents,
+                "Unpacked contents differ for {compression:?}"
+            );
+        }
+
+        assert_eq!(Compression::detect(b"<!DOCTYPE html>"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checks_layout() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tar, _) = tar("nix-2.23.3-x86_64-linux/blob")?;
+        let tarball_path = temp_dir.path().join("nix.tar.zst");
+        tokio::fs::write(&tarball_path, compress(tar, Compression::Zstd)?).await?;
+        let dest = temp_dir.path().join("unpacked");
+
+        let mut action = FetchAndUnpackNix::plan(
+            &settings(UrlOrPath::Path(tarball_path)).await?,
+            dest.clone(),
+        )
+        .await?;
+        let err = action.try_execute().await.unwrap_err();
+        assert!(
+            matches!(err.kind(), ActionErrorKind::MalformedBinaryTarball),
+            "{err:?}"
+        );
+        assert!(!dest.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn caches_tarballs() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, contents) = tarball()?;
+        let sha256 = NixPackageSha256::of(&tarball);
+        let (url, starts) = flaky_server(tarball)?;
+        let cache = temp_dir.path().join("cache");
+
+        let mut settings = settings(UrlOrPath::Url(url.clone())).await?;
+        settings.nix_package_cache = Some(cache.clone());
+        // Without a SHA-256 the URL is fetched every time, it may serve a newer tarball
+        for run in 0..2 {
+            let dest = temp_dir.path().join(format!("unpacked-{run}"));
+            let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+            action.try_execute().await?;
+
+            let unpacked = tokio::fs::read(dest.join("nix-2.23.3-x86_64-linux/store/blob")).await?;
+            assert!(unpacked == contents, "Unpacked contents differ");
+        }
+        // The first run took two requests
+        assert_eq!(starts.try_iter().count(), 3);
+        assert!(cache.join(cache_name(&sha256)).is_file());
+
+        // With a SHA-256 it is reused
+        settings.nix_package_sha256 = Some(sha256);
+        let dest = temp_dir.path().join("unpacked-sha256");
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        action.try_execute().await?;
+        assert!(dest.join("nix-2.23.3-x86_64-linux/store/blob").exists());
+        assert_eq!(starts.try_iter().count(), 0);
+
+        // Offline, it is found by its SHA-256, or else by the URL it was last fetched from
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+            Url::parse(&format!("http://{}/nix.tar.xz", listener.local_addr()?))?
+        };
+        tokio::fs::copy(
+            cache.join(url_name(&url)),
+            cache.join(url_name(&unreachable)),
+        )
+        .await?;
+        settings.nix_package_url = Some(UrlOrPath::Url(unreachable));
+        settings.nix_package_retries = 0;
+        for sha256 in [Some(sha256), None] {
+            settings.nix_package_sha256 = sha256;
+            let dest = temp_dir
+                .path()
+                .join(format!("unpacked-offline-{}", sha256.is_some()));
+            let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+            action.try_execute().await?;
+            assert!(dest.join("nix-2.23.3-x86_64-linux/store/blob").exists());
+        }
+
+        // A cached tarball which does not match its SHA-256 is not used
+        settings.nix_package_sha256 = Some(sha256);
+        tokio::fs::write(cache.join(cache_name(&sha256)), "Corrupted").await?;
+        let dest = temp_dir.path().join("unpacked-corrupted");
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        assert!(action.try_execute().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetches_without_an_unwritable_cache() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (tarball, contents) = tarball()?;
+        let (url, _starts) = flaky_server(tarball)?;
+        // A file where the cache directory should be, which is unwritable even for root
+        let cache = temp_dir.path().join("cache");
+        tokio::fs::write(&cache, "Not a directory").await?;
+
+        let mut settings = settings(UrlOrPath::Url(url)).await?;
+        settings.nix_package_cache = Some(cache.clone());
+        let dest = temp_dir.path().join("unpacked");
+        let mut action = FetchAndUnpackNix::plan(&settings, dest.clone()).await?;
+        action.try_execute().await?;
+
+        let unpacked = tokio::fs::read(dest.join("nix-2.23.3-x86_64-linux/store/blob")).await?;
+        assert!(unpacked == contents, "Unpacked contents differ");
+        assert!(cache.is_file());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+/*! Byte-exact copies of the system files the installer edits, so uninstalling can put them back */
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{chown, Gid, Uid};
+use rand::Rng;
+
+use crate::action::ActionErrorKind;
+
+/// Where the originals of edited files are kept, it is removed along with the rest of `/nix`
+///
+/// When installing into a root, the `/nix` inside it is used instead.
+pub const BACKUP_DIR: &str = "/nix/.installer-backups";
+
+/// The backup directory of actions from receipts which did not record one
+pub(crate) fn default_backup_dir() -> PathBuf {
+    PathBuf::from(BACKUP_DIR)
+}
+
+/// Appended to the path of a file which changed after install, when the original is restored over it
+pub const DRIFT_SUFFIX: &str = ".nix-installer-drift";
+
+/// The original contents, mode and owner of a file from before the installer edited it
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct FileBackup {
+    /// A copy of the file from before the install
+    pub original: PathBuf,
+    /// A copy of the file as the install left it, to tell if it was edited since
+    pub installed: Option<PathBuf>,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FileBackup {
+    /// Copy `path` into `backup_dir`, `None` if there is no file to back up
+    pub async fn create(path: &Path, backup_dir: &Path) -> Result<Option<Self>, ActionErrorKind> {
+        let metadata = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ActionErrorKind::GettingMetadata(path.to_path_buf(), e)),
+        };
+
+        tokio::fs::create_dir_all(backup_dir)
+            .await
+            .map_err(|e| ActionErrorKind::CreateDirectory(backup_dir.to_path_buf(), e))?;
+        let original = backup_dir.join(format!("{}.orig", backup_name(path)));
+        copy_private(path, &original).await?;
+        tracing::debug!(
+            path = %path.display(),
+            backup = %original.display(),
+            "Backed up original"
+        );
+
+        Ok(Some(Self {
+            original,
+            installed: None,
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }))
+    }
+
+    /// Keep a copy of `path` as the install left it
+    pub async fn record_installed(&mut self, path: &Path) -> Result<(), ActionErrorKind> {
+        let installed = self.original.with_extension("installed");
+        copy_private(path, &installed).await?;
+        self.installed = Some(installed);
+        Ok(())
+    }
+
+    /// Whether `path` was changed (or removed) since the install left it
+    pub async fn drifted(&self, path: &Path) -> Result<bool, ActionErrorKind> {
+        let Some(installed) = &self.installed else {
+            return Ok(false);
+        };
+        let current = match tokio::fs::read(path).await {
+            Ok(current) => current,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(ActionErrorKind::Read(path.to_path_buf(), e)),
+        };
+        let installed = tokio::fs::read(installed)
+            .await
+            .map_err(|e| ActionErrorKind::Read(installed.clone(), e))?;
+        Ok(current != installed)
+    }
+
+    /// Put the original back at `path`, byte for byte and with its mode and owner, then remove the backup
+    ///
+    /// If `path` was edited after the install, the edited version is kept next to it with [`DRIFT_SUFFIX`].
+    pub async fn restore(&self, path: &Path) -> Result<(), ActionErrorKind> {
+        if self.drifted(path).await? {
+            if path.exists() {
+                let drift = PathBuf::from(format!("{}{DRIFT_SUFFIX}", path.display()));
+                tokio::fs::copy(path, &drift)
+                    .await
+                    .map_err(|e| ActionErrorKind::Copy(path.to_path_buf(), drift.clone(), e))?;
+                tracing::warn!(
+                    "`{}` was changed after Nix was installed, restoring the original and keeping the changed version at `{}`",
+                    path.display(),
+                    drift.display(),
+                );
+            } else {
+                tracing::warn!(
+                    "`{}` was removed after Nix was installed, restoring the original",
+                    path.display(),
+                );
+            }
+        }
+
+        // Restore through a temporary file in the same directory, so the rename is atomic
+        let parent_dir = path.parent().expect("File must be in a directory");
+        let mut temp_file_path = parent_dir.to_owned();
+        {
+            let mut rng = rand::thread_rng();
+            temp_file_path.push(format!("nix-installer-tmp.{}", rng.gen::<u32>()));
+        }
+        copy_private(&self.original, &temp_file_path).await?;
+        // Change ownership _before_ applying mode, as with creating files
+        chown(
+            &temp_file_path,
+            Some(Uid::from_raw(self.uid)),
+            Some(Gid::from_raw(self.gid)),
+        )
+        .map_err(|e| ActionErrorKind::Chown(path.to_path_buf(), e))?;
+        tokio::fs::set_permissions(&temp_file_path, PermissionsExt::from_mode(self.mode))
+            .await
+            .map_err(|e| ActionErrorKind::SetPermissions(self.mode, path.to_path_buf(), e))?;
+        tokio::fs::rename(&temp_file_path, path)
+            .await
+            .map_err(|e| ActionErrorKind::Rename(temp_file_path.clone(), path.to_path_buf(), e))?;
+
+        for backup in std::iter::once(&self.original).chain(self.installed.iter()) {
+            tokio::fs::remove_file(backup)
+                .await
+                .map_err(|e| ActionErrorKind::Remove(backup.clone(), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy `src` to `dest`, readable only by root, since backups may hold secrets such as access tokens in `nix.conf`
+async fn copy_private(src: &Path, dest: &Path) -> Result<(), ActionErrorKind> {
+    tokio::fs::copy(src, dest)
+        .await
+        .map_err(|e| ActionErrorKind::Copy(src.to_path_buf(), dest.to_path_buf(), e))?;
+    tokio::fs::set_permissions(dest, PermissionsExt::from_mode(0o600))
+        .await
+        .map_err(|e| ActionErrorKind::SetPermissions(0o600, dest.to_path_buf(), e))?;
+    Ok(())
+}
+
+/// A file name for the backup of `path`, eg. `etc%2Fnix%2Fnix.conf` for `/etc/nix/nix.conf`
+fn backup_name(path: &Path) -> String {
+    path.to_string_lossy()
+        .trim_start_matches('/')
+        .replace('%', "%25")
+        .replace('/', "%2F")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_backup_name() {
+        assert_eq!(
+            backup_name(Path::new("/etc/nix/nix.conf")),
+            "etc%2Fnix%2Fnix.conf"
+        );
+        assert_eq!(backup_name(Path::new("/etc/100%")), "etc%2F100%25");
+    }
+
+    #[tokio::test]
+    async fn backs_up_into_backup_dir() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("etc/bashrc");
+        tokio::fs::create_dir_all(temp_dir.path().join("etc")).await?;
+        tokio::fs::write(&test_file, "original\n").await?;
+        let backup_dir = temp_dir.path().join("nix/.installer-backups");
+
+        let backup = FileBackup::create(&test_file, &backup_dir)
+            .await?
+            .ok_or_else(|| eyre::eyre!("File should have been backed up"))?;
+
+        assert_eq!(backup.original.parent(), Some(backup_dir.as_path()));
+        assert_eq!(
+            tokio::fs::read_to_string(&backup.original).await?,
+            "original\n"
+        );
+        assert!(
+            FileBackup::create(&temp_dir.path().join("missing"), &backup_dir)
+                .await?
+                .is_none()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_original_and_keeps_drift() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let test_file = temp_dir.path().join("restores_original_and_keeps_drift");
+        tokio::fs::write(&test_file, "original\n").await?;
+        tokio::fs::set_permissions(&test_file, PermissionsExt::from_mode(0o640)).await?;
+
+        let mut backup = FileBackup::create(&test_file, &temp_dir.path().join("backups"))
+            .await?
+            .ok_or_else(|| eyre::eyre!("File should have been backed up"))?;
+        tokio::fs::write(&test_file, "original\ninstalled\n").await?;
+        backup.record_installed(&test_file).await?;
+        assert!(!backup.drifted(&test_file).await?);
+
+        tokio::fs::write(&test_file, "original\ninstalled\nedited\n").await?;
+        assert!(backup.drifted(&test_file).await?);
+
+        backup.restore(&test_file).await?;
+
+        assert_eq!(tokio::fs::read_to_string(&test_file).await?, "original\n");
+        assert_eq!(
+            tokio::fs::metadata(&test_file).await?.permissions().mode() & 0o777,
+            0o640
+        );
+        let drift = PathBuf::from(format!("{}{DRIFT_SUFFIX}", test_file.display()));
+        assert_eq!(
+            tokio::fs::read_to_string(&drift).await?,
+            "original\ninstalled\nedited\n"
+        );
+        assert!(!backup.original.exists(), "Backup should have been removed");
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 //! Base [`Action`](crate::action::Action)s that themselves have no other actions as dependencies
 
 pub(crate) mod add_user_to_group;
+pub(crate) mod create_btrfs_subvolume;
 pub(crate) mod create_directory;
 pub(crate) mod create_file;
 pub(crate) mod create_group;
@@ -8,12 +9,16 @@ pub(crate) mod create_or_insert_into_file;
 pub(crate) mod create_or_merge_nix_config;
 pub(crate) mod create_user;
 pub(crate) mod delete_user;
+pub(crate) mod etc_files;
 pub(crate) mod fetch_and_unpack_nix;
+pub(crate) mod file_backup;
 pub(crate) mod move_unpacked_nix;
 pub(crate) mod remove_directory;
 pub(crate) mod setup_default_profile;
+pub mod user_group_backend;
 
 pub use add_user_to_group::AddUserToGroup;
+pub use create_btrfs_subvolume::CreateBtrfsSubvolume;
 pub use create_directory::CreateDirectory;
 pub use create_file::CreateFile;
 pub use create_group::CreateGroup;
@@ -21,7 +26,10 @@ pub use create_or_insert_into_file::CreateOrInsertIntoFile;
 pub use create_or_merge_nix_config::CreateOrMergeNixConfig;
 pub use create_user::CreateUser;
 pub use delete_user::DeleteUser;
+pub use etc_files::EtcFilesError;
 pub use fetch_and_unpack_nix::{FetchAndUnpackNix, FetchUrlError};
+pub use file_backup::FileBackup;
 pub use move_unpacked_nix::{MoveUnpackedNix, MoveUnpackedNixError};
 pub use remove_directory::RemoveDirectory;
 pub use setup_default_profile::{SetupDefaultProfile, SetupDefaultProfileError};
+pub use user_group_backend::{UserGroupBackend, UserGroupOperation};
@@ -1,7 +1,4 @@
-use std::{
-    os::unix::prelude::PermissionsExt,
-    path::{Path, PathBuf},
-};
+use std::{os::unix::prelude::PermissionsExt, path::PathBuf};
 
 use tracing::{span, Span};
 use walkdir::WalkDir;
@@ -9,23 +6,37 @@ use walkdir::WalkDir;
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
 };
+use crate::settings::rooted;
 
 pub(crate) const DEST: &str = "/nix/";
 
 /**
-Move an unpacked Nix at `src` to `/nix`
+Move an unpacked Nix at `src` to `/nix`, or `/nix` under `root`
 */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "mount_unpacked_nix")]
 pub struct MoveUnpackedNix {
     unpacked_path: PathBuf,
+    #[serde(default)]
+    root: Option<PathBuf>,
 }
 
 impl MoveUnpackedNix {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub async fn plan(unpacked_path: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
+    pub async fn plan(
+        unpacked_path: PathBuf,
+        root: Option<PathBuf>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
         // Note: Do NOT try to check for the src/dest since the installer creates those
-        Ok(Self { unpacked_path }.into())
+        Ok(Self {
+            unpacked_path,
+            root,
+        }
+        .into())
+    }
+
+    fn dest(&self) -> PathBuf {
+        rooted(self.root.as_deref(), DEST)
     }
 }
 
@@ -36,7 +47,7 @@ impl Action for MoveUnpackedNix {
         ActionTag("move_unpacked_nix")
     }
     fn tracing_synopsis(&self) -> String {
-        "Move the downloaded Nix into `/nix`".to_string()
+        format!("Move the downloaded Nix into `{}`", self.dest().display())
     }
 
     fn tracing_span(&self) -> Span {
@@ -44,23 +55,28 @@ impl Action for MoveUnpackedNix {
             tracing::Level::DEBUG,
             "mount_unpacked_nix",
             src = tracing::field::display(self.unpacked_path.display()),
-            dest = DEST,
+            dest = tracing::field::display(self.dest().display()),
         )
     }
 
     fn execute_description(&self) -> Vec<ActionDescription> {
         vec![ActionDescription::new(
-            "Move the downloaded Nix into `/nix`".to_string(),
+            self.tracing_synopsis(),
             vec![format!(
-                "Nix is being downloaded to `{}` and should be in `/nix`",
+                "Nix is being downloaded to `{}` and should be in `{}`",
                 self.unpacked_path.display(),
+                self.dest().display(),
             )],
         )]
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
-        let Self { unpacked_path } = self;
+        let dest = self.dest();
+        let Self {
+            unpacked_path,
+            root,
+        } = self;
 
         // This is the `nix-$VERSION` folder which unpacks from the tarball, not a nix derivation
         let found_nix_paths = glob::glob(&format!("{}/nix-*", unpacked_path.display()))
@@ -71,12 +87,25 @@ impl Action for MoveUnpackedNix {
             return Err(Self::error(ActionErrorKind::MalformedBinaryTarball));
         }
         let found_nix_path = found_nix_paths.into_iter().next().unwrap();
+        // A root may be an image for another system, such as an armv7l NAS image built on x86_64
+        if let Some(found) = found_nix_path
+            .file_name()
+            .and_then(|name| tarball_system(&name.to_string_lossy()))
+            .filter(|_| root.is_none())
+        {
+            if found != crate::self_test::SYSTEM {
+                return Err(Self::error(MoveUnpackedNixError::SystemMismatch {
+                    found,
+                    expected: crate::self_test::SYSTEM,
+                }));
+            }
+        }
         let src_store = found_nix_path.join("store");
         let mut src_store_listing = tokio::fs::read_dir(src_store.clone())
             .await
             .map_err(|e| ActionErrorKind::ReadDir(src_store.clone(), e))
             .map_err(Self::error)?;
-        let dest_store = Path::new(DEST).join("store");
+        let dest_store = dest.join("store");
         if dest_store.exists() {
             if !dest_store.is_dir() {
                 return Err(Self::error(ActionErrorKind::PathWasNotDirectory(
@@ -173,6 +202,11 @@ pub enum MoveUnpackedNixError {
         #[source]
         glob::GlobError,
     ),
+    #[error("The Nix binary tarball is for `{found}`, but this machine needs `{expected}`; pass `--nix-package-url` with a `nix-$VERSION-{expected}.tar.xz` from `https://releases.nixos.org/?prefix=nix/`")]
+    SystemMismatch {
+        found: String,
+        expected: &'static str,
+    },
 }
 
 impl From<MoveUnpackedNixError> for ActionErrorKind {
@@ -180,3 +214,57 @@ impl From<MoveUnpackedNixError> for ActionErrorKind {
         ActionErrorKind::Custom(Box::new(val))
     }
 }
+
+/// The Nix system a `nix-$VERSION-$SYSTEM` directory from a binary tarball was built for, eg. `armv7l-linux`
+fn tarball_system(dir_name: &str) -> Option<String> {
+    let mut parts = dir_name.rsplitn(3, '-');
+    let os = parts.next()?;
+    let arch = parts.next()?;
+    parts.next()?;
+    matches!(os, "linux" | "darwin").then(|| format!("{arch}-{os}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_tarball_system() {
+        assert_eq!(
+            tarball_system("nix-2.23.3-armv7l-linux"),
+            Some("armv7l-linux".to_string())
+        );
+        assert_eq!(
+            tarball_system("nix-2.23.3-aarch64-darwin"),
+            Some("aarch64-darwin".to_string())
+        );
+        // Custom builds may not be named after a system, they are not checked
+        assert_eq!(tarball_system("nix-2.23.3"), None);
+        assert_eq!(tarball_system("nix-custom"), None);
+    }
+
+    #[tokio::test]
+    async fn moves_other_systems_into_root() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let unpacked = temp_dir.path().join("unpacked");
+        let other_system = if crate::self_test::SYSTEM == "armv7l-linux" {
+            "i686-linux"
+        } else {
+            "armv7l-linux"
+        };
+        let store_path = unpacked
+            .join(format!("nix-2.23.3-{other_system}"))
+            .join("store/abc-nix-2.23.3");
+        tokio::fs::create_dir_all(store_path.join("bin")).await?;
+        tokio::fs::write(store_path.join("bin/nix"), "").await?;
+        let root = temp_dir.path().join("root");
+        tokio::fs::create_dir_all(root.join("nix")).await?;
+
+        let mut action = MoveUnpackedNix::plan(unpacked, Some(root.clone())).await?;
+        action.try_execute().await?;
+
+        assert!(root.join("nix/store/abc-nix-2.23.3/bin/nix").exists());
+
+        Ok(())
+    }
+}
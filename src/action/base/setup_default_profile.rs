@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::{
     action::{common::ConfigureNix, ActionError, ActionErrorKind, ActionTag, StatefulAction},
@@ -12,17 +12,85 @@ use crate::action::{Action, ActionDescription};
 
 /**
 Setup the default Nix profile with `nss-cacert` and `nix` itself.
+
+With a `root`, the installed Nix is run inside it with `chroot`, as its store paths only resolve there.
  */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "setup_default_profile")]
 pub struct SetupDefaultProfile {
     unpacked_path: PathBuf,
+    #[serde(default)]
+    root: Option<PathBuf>,
 }
 
 impl SetupDefaultProfile {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub async fn plan(unpacked_path: PathBuf) -> Result<StatefulAction<Self>, ActionError> {
-        Ok(Self { unpacked_path }.into())
+    pub async fn plan(
+        unpacked_path: PathBuf,
+        root: Option<PathBuf>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        if root.is_some() {
+            which::which("chroot")
+                .map_err(|_| SetupDefaultProfileError::NoChroot)
+                .map_err(Self::error)?;
+        }
+        Ok(Self {
+            unpacked_path,
+            root,
+        }
+        .into())
+    }
+
+    /// `path` as seen from inside `root`
+    fn logical(&self, path: PathBuf) -> PathBuf {
+        match &self.root {
+            Some(root) => match path.strip_prefix(root) {
+                Ok(inside) => Path::new("/").join(inside),
+                Err(_) => path,
+            },
+            None => path,
+        }
+    }
+
+    /// A command running `program` from the installed Nix
+    fn nix_command(&self, program: PathBuf) -> Command {
+        match &self.root {
+            Some(root) => {
+                let mut command = Command::new("chroot");
+                command.arg(root).arg(program);
+                command
+            },
+            None => Command::new(program),
+        }
+    }
+
+    /// A `nix-env -i` of `pkg` into the default profile
+    fn install_command(
+        &self,
+        nix_pkg: &Path,
+        nss_ca_cert_pkg: &Path,
+        pkg: &Path,
+    ) -> Result<Command, ActionError> {
+        let mut command = self.nix_command(nix_pkg.join("bin/nix-env"));
+        command.process_group(0);
+        command.arg("-i").arg(pkg);
+        if self.root.is_some() {
+            // Nothing is mounted in the root to sandbox with, and the build users only exist in it
+            command.args(["--option", "sandbox", "false"]);
+            command.args(["--option", "build-users-group", ""]);
+        }
+        command
+            .stdin(std::process::Stdio::null())
+            .env(
+                "HOME",
+                dirs::home_dir()
+                    .ok_or_else(|| Self::error(SetupDefaultProfileError::NoRootHome))?,
+            )
+            .env(
+                "NIX_SSL_CERT_FILE",
+                nss_ca_cert_pkg.join("etc/ssl/certs/ca-bundle.crt"),
+            ); /* This is apparently load bearing... */
+        Ok(command)
     }
 }
 
@@ -45,13 +113,22 @@ impl Action for SetupDefaultProfile {
     }
 
     fn execute_description(&self) -> Vec<ActionDescription> {
-        vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+        let mut explanation = vec![];
+        if let Some(root) = &self.root {
+            explanation.push(format!(
+                "Nix is run inside `{}` with `chroot`, so it must be able to run on this machine",
+                root.display()
+            ));
+        }
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
     async fn execute(&mut self) -> Result<(), ActionError> {
         let (nix_pkg, nss_ca_cert_pkg) =
             ConfigureNix::find_nix_and_ca_cert(&self.unpacked_path).await?;
+        let nix_pkg = self.logical(nix_pkg);
+        let nss_ca_cert_pkg = self.logical(nss_ca_cert_pkg);
         let found_nix_paths = glob::glob(&format!("{}/nix-*", self.unpacked_path.display()))
             .map_err(Self::error)?
             .collect::<Result<Vec<_>, _>>()
@@ -65,7 +142,7 @@ impl Action for SetupDefaultProfile {
             .await
             .map_err(|e| ActionErrorKind::Read(reginfo_path.to_path_buf(), e))
             .map_err(Self::error)?;
-        let mut load_db_command = Command::new(nix_pkg.join("bin/nix-store"));
+        let mut load_db_command = self.nix_command(nix_pkg.join("bin/nix-store"));
         load_db_command.process_group(0);
         load_db_command.arg("--load-db");
         load_db_command.stdin(std::process::Stdio::piped());
@@ -115,49 +192,22 @@ impl Action for SetupDefaultProfile {
         };
 
         // Install `nix` itself into the store
-        execute_command(
-            Command::new(nix_pkg.join("bin/nix-env"))
-                .process_group(0)
-                .arg("-i")
-                .arg(&nix_pkg)
-                .stdin(std::process::Stdio::null())
-                .env(
-                    "HOME",
-                    dirs::home_dir()
-                        .ok_or_else(|| Self::error(SetupDefaultProfileError::NoRootHome))?,
-                )
-                .env(
-                    "NIX_SSL_CERT_FILE",
-                    nss_ca_cert_pkg.join("etc/ssl/certs/ca-bundle.crt"),
-                ), /* This is apparently load bearing... */
-        )
-        .await
-        .map_err(Self::error)?;
+        execute_command(&mut self.install_command(&nix_pkg, &nss_ca_cert_pkg, &nix_pkg)?)
+            .await
+            .map_err(Self::error)?;
 
-        // Install `nix` itself into the store
-        execute_command(
-            Command::new(nix_pkg.join("bin/nix-env"))
-                .process_group(0)
-                .arg("-i")
-                .arg(&nss_ca_cert_pkg)
-                .stdin(std::process::Stdio::null())
-                .env(
-                    "HOME",
-                    dirs::home_dir()
-                        .ok_or_else(|| Self::error(SetupDefaultProfileError::NoRootHome))?,
-                )
-                .env(
-                    "NIX_SSL_CERT_FILE",
-                    nss_ca_cert_pkg.join("etc/ssl/certs/ca-bundle.crt"),
-                ), /* This is apparently load bearing... */
-        )
-        .await
-        .map_err(Self::error)?;
+        // Install `nss-cacert` into the store
+        execute_command(&mut self.install_command(&nix_pkg, &nss_ca_cert_pkg, &nss_ca_cert_pkg)?)
+            .await
+            .map_err(Self::error)?;
 
-        set_env(
-            "NIX_SSL_CERT_FILE",
-            "/nix/var/nix/profiles/default/etc/ssl/certs/ca-bundle.crt",
-        );
+        // Only later steps on this machine use it, and a root's profile is not this machine's
+        if self.root.is_none() {
+            set_env(
+                "NIX_SSL_CERT_FILE",
+                "/nix/var/nix/profiles/default/etc/ssl/certs/ca-bundle.crt",
+            );
+        }
 
         Ok(())
     }
@@ -182,6 +232,8 @@ impl Action for SetupDefaultProfile {
 pub enum SetupDefaultProfileError {
     #[error("No root home found to place channel configuration in")]
     NoRootHome,
+    #[error("`chroot` is needed to set up the default profile in an alternate root, but it is not in `PATH`")]
+    NoChroot,
 }
 
 impl From<SetupDefaultProfileError> for ActionErrorKind {
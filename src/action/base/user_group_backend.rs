@@ -0,0 +1,654 @@
+/*! The tooling used to manage operating system level users and groups
+
+The backend is selected once when planning and stored in each user/group action, so `uninstall` uses
+the same tooling as `install` did, even if the `PATH` changed in between.
+*/
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::unistd::{Group, User};
+use rand::Rng;
+use target_lexicon::OperatingSystem;
+use tokio::process::Command;
+
+use crate::action::base::create_user::delete_user_macos;
+use crate::action::base::etc_files;
+use crate::action::ActionErrorKind;
+use crate::execute_command;
+use crate::planner::synology::SYNOINFO_CONF;
+
+const ETC_SHADOW: &str = "/etc/shadow";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserGroupBackend {
+    /// `useradd`, `userdel`, `groupadd`, `groupdel` and `gpasswd`
+    ShadowUtils,
+    /// `adduser`, `deluser`, `addgroup` and `delgroup`, as found in Busybox (and Debian's `adduser`)
+    Busybox,
+    /// `synouser` and `synogroup` on Synology DSM
+    Synology,
+    /// `dscl` and `dseditgroup` on macOS
+    Dscl,
+    /// Editing `etc/passwd`, `etc/group` and `etc/shadow` under `root` directly, for installs into a disk image or chroot
+    Files { root: PathBuf },
+}
+
+/// A user as found by [`UserGroupBackend::user`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserEntry {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// A group as found by [`UserGroupBackend::group`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// An operation on users or groups which a [`UserGroupBackend`] can perform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserGroupOperation<'a> {
+    CreateUser {
+        name: &'a str,
+        uid: u32,
+        groupname: &'a str,
+        gid: u32,
+        comment: &'a str,
+    },
+    DeleteUser {
+        name: &'a str,
+    },
+    CreateGroup {
+        name: &'a str,
+        gid: u32,
+    },
+    DeleteGroup {
+        name: &'a str,
+    },
+    AddUserToGroup {
+        name: &'a str,
+        groupname: &'a str,
+    },
+    RemoveUserFromGroup {
+        name: &'a str,
+        groupname: &'a str,
+    },
+}
+
+impl UserGroupBackend {
+    /// Select the backend for the host
+    pub fn detect() -> Self {
+        Self::detect_with(
+            matches!(
+                OperatingSystem::host(),
+                OperatingSystem::MacOSX { .. } | OperatingSystem::Darwin
+            ),
+            Path::new(SYNOINFO_CONF).is_file(),
+            |program| which::which(program).is_ok(),
+        )
+    }
+
+    /// Select the backend given facts about the host, `has_program` reports if a program is in `PATH`
+    pub(crate) fn detect_with(
+        is_macos: bool,
+        is_synology: bool,
+        has_program: impl Fn(&str) -> bool,
+    ) -> Self {
+        if is_macos {
+            Self::Dscl
+        } else if is_synology {
+            Self::Synology
+        } else if !has_program("useradd") && has_program("adduser") {
+            Self::Busybox
+        } else {
+            // If neither is present, `ensure_available` reports the shadow-utils commands as missing
+            Self::ShadowUtils
+        }
+    }
+
+    /// The program this backend runs to perform `operation`, `None` if it edits files itself
+    fn program(&self, operation: &UserGroupOperation) -> Option<&'static str> {
+        use UserGroupOperation::*;
+        let program = match (self, operation) {
+            (Self::ShadowUtils, CreateUser { .. }) => "useradd",
+            (Self::ShadowUtils, DeleteUser { .. }) => "userdel",
+            (Self::ShadowUtils, CreateGroup { .. }) => "groupadd",
+            (Self::ShadowUtils, DeleteGroup { .. }) => "groupdel",
+            (Self::ShadowUtils, AddUserToGroup { .. } | RemoveUserFromGroup { .. }) => "gpasswd",
+            (Self::Busybox, CreateUser { .. }) => "adduser",
+            (Self::Busybox, DeleteUser { .. }) => "deluser",
+            (Self::Busybox, CreateGroup { .. } | AddUserToGroup { .. }) => "addgroup",
+            (Self::Busybox, DeleteGroup { .. } | RemoveUserFromGroup { .. }) => "delgroup",
+            (Self::Synology, CreateUser { .. } | DeleteUser { .. }) => "synouser",
+            (Self::Synology, _) => "synogroup",
+            (Self::Dscl, CreateGroup { .. }) => "/usr/sbin/dseditgroup",
+            (Self::Dscl, _) => "/usr/bin/dscl",
+            (Self::Files { .. }, _) => return None,
+        };
+        Some(program)
+    }
+
+    /// The error reported when the program for `operation` is missing
+    fn missing_error(&self, operation: &UserGroupOperation) -> ActionErrorKind {
+        use UserGroupOperation::*;
+        match (self, operation) {
+            (Self::Synology, CreateUser { .. }) => {
+                ActionErrorKind::MissingSynologyUserCreationCommand
+            },
+            (Self::Synology, DeleteUser { .. }) => {
+                ActionErrorKind::MissingSynologyUserDeletionCommand
+            },
+            (Self::Synology, CreateGroup { .. }) => {
+                ActionErrorKind::MissingSynologyGroupCreationCommand
+            },
+            (Self::Synology, DeleteGroup { .. }) => {
+                ActionErrorKind::MissingSynologyGroupDeletionCommand
+            },
+            (Self::Synology, AddUserToGroup { .. }) => {
+                ActionErrorKind::MissingSynologyAddUserToGroupCommand
+            },
+            (Self::Synology, RemoveUserFromGroup { .. }) => {
+                ActionErrorKind::MissingSynologyRemoveUserFromGroupCommand
+            },
+            (_, CreateUser { .. }) => ActionErrorKind::MissingUserCreationCommand,
+            (_, DeleteUser { .. }) => ActionErrorKind::MissingUserDeletionCommand,
+            (_, CreateGroup { .. }) => ActionErrorKind::MissingGroupCreationCommand,
+            (_, DeleteGroup { .. }) => ActionErrorKind::MissingGroupDeletionCommand,
+            (_, AddUserToGroup { .. }) => ActionErrorKind::MissingAddUserToGroupCommand,
+            (_, RemoveUserFromGroup { .. }) => ActionErrorKind::MissingRemoveUserFromGroupCommand,
+        }
+    }
+
+    /// Whether users and groups are created with the requested UID and GID
+    ///
+    /// `synouser` and `synogroup` allocate their own, so the IDs they picked are recorded in the receipt instead.
+    pub fn honours_requested_ids(&self) -> bool {
+        !matches!(self, Self::Synology)
+    }
+
+    /// Ensure the program needed to perform `operation` is present
+    pub fn ensure_available(&self, operation: &UserGroupOperation) -> Result<(), ActionErrorKind> {
+        self.ensure_available_with(operation, |program| which::which(program).is_ok())
+    }
+
+    pub(crate) fn ensure_available_with(
+        &self,
+        operation: &UserGroupOperation,
+        has_program: impl Fn(&str) -> bool,
+    ) -> Result<(), ActionErrorKind> {
+        match (self, self.program(operation)) {
+            // These ship with macOS
+            (Self::Dscl, _) => Ok(()),
+            (_, None) => Ok(()),
+            (_, Some(program)) if has_program(program) => Ok(()),
+            _ => Err(self.missing_error(operation)),
+        }
+    }
+
+    /// The commands which perform `operation`, in order
+    pub(crate) fn commands(&self, operation: &UserGroupOperation) -> Vec<Command> {
+        use UserGroupOperation::*;
+        let Some(program) = self.program(operation) else {
+            return vec![];
+        };
+        let command = |args: &[&str]| {
+            let mut command = Command::new(program);
+            command.process_group(0);
+            command.args(args);
+            command.stdin(std::process::Stdio::null());
+            command
+        };
+
+        match (self, *operation) {
+            (
+                Self::ShadowUtils,
+                CreateUser {
+                    name,
+                    uid,
+                    gid,
+                    comment,
+                    ..
+                },
+            ) => vec![command(&[
+                "--home-dir",
+                "/var/empty",
+                "--comment",
+                comment,
+                "--gid",
+                &gid.to_string(),
+                "--groups",
+                &gid.to_string(),
+                "--no-user-group",
+                "--system",
+                "--shell",
+                "/sbin/nologin",
+                "--uid",
+                &uid.to_string(),
+                "--password",
+                "!",
+                name,
+            ])],
+            (
+                Self::Busybox,
+                CreateUser {
+                    name,
+                    uid,
+                    groupname,
+                    comment,
+                    ..
+                },
+            ) => vec![command(&[
+                "--home",
+                "/var/empty",
+                "-H", // Don't create a home.
+                "--gecos",
+                comment,
+                "--ingroup",
+                groupname,
+                "--system",
+                "--shell",
+                "/sbin/nologin",
+                "--uid",
+                &uid.to_string(),
+                "--disabled-password",
+                name,
+            ])],
+            (Self::Synology, CreateUser { name, comment, .. }) => vec![command(&[
+                "--add",
+                name,
+                &random_password(), // Required, it is visible in the process list until it is locked in `execute`
+                comment,            // Full name, so the account is recognizable in DSM
+                "1",                // Expired, which disables the account
+                "",                 // Empty email
+                "0",                // No application privileges (file services, SSH, ...)
+            ])],
+            (Self::Dscl, CreateUser { name, uid, gid, .. }) => {
+                let record = format!("/Users/{name}");
+                vec![
+                    command(&[".", "-create", &record]),
+                    command(&[".", "-create", &record, "UniqueID", &uid.to_string()]),
+                    command(&[".", "-create", &record, "PrimaryGroupID", &gid.to_string()]),
+                    command(&[".", "-create", &record, "NFSHomeDirectory", "/var/empty"]),
+                    command(&[".", "-create", &record, "UserShell", "/sbin/nologin"]),
+                    command(&[".", "-create", &record, "IsHidden", "1"]),
+                ]
+            },
+            (Self::ShadowUtils | Self::Busybox, DeleteUser { name }) => vec![command(&[name])],
+            (Self::Synology, DeleteUser { name }) => vec![command(&["--del", name])],
+            (Self::Dscl, DeleteUser { name }) => {
+                vec![command(&[".", "-delete", &format!("/Users/{name}")])]
+            },
+            (Self::ShadowUtils | Self::Busybox, CreateGroup { name, gid }) => {
+                vec![command(&["-g", &gid.to_string(), "--system", name])]
+            },
+            (Self::Synology, CreateGroup { name, .. }) => vec![command(&["--add", name])],
+            (Self::Dscl, CreateGroup { name, gid }) => vec![command(&[
+                "-o",
+                "create",
+                "-r",
+                "Nix build group for nix-daemon",
+                "-i",
+                &gid.to_string(),
+                name,
+            ])],
+            (Self::ShadowUtils | Self::Busybox, DeleteGroup { name }) => vec![command(&[name])],
+            (Self::Synology, DeleteGroup { name }) => vec![command(&["--del", name])],
+            (Self::Dscl, DeleteGroup { name }) => {
+                vec![command(&[".", "-delete", &format!("/Groups/{name}")])]
+            },
+            (Self::ShadowUtils, AddUserToGroup { name, groupname }) => {
+                vec![command(&["-a", name, groupname])]
+            },
+            (Self::Busybox, AddUserToGroup { name, groupname }) => {
+                vec![command(&[name, groupname])]
+            },
+            (Self::Synology, AddUserToGroup { name, groupname }) => {
+                // `--member` would replace the existing members of the group
+                vec![command(&["--memberadd", groupname, name])]
+            },
+            (Self::Dscl, AddUserToGroup { name, groupname }) => {
+                let mut dseditgroup = Command::new("/usr/sbin/dseditgroup");
+                dseditgroup.process_group(0);
+                dseditgroup.args(["-o", "edit", "-a", name, "-t", name, groupname]);
+                dseditgroup.stdin(std::process::Stdio::null());
+                vec![
+                    command(&[
+                        ".",
+                        "-append",
+                        &format!("/Groups/{groupname}"),
+                        "GroupMembership",
+                        name,
+                    ]),
+                    dseditgroup,
+                ]
+            },
+            (Self::ShadowUtils, RemoveUserFromGroup { name, groupname }) => {
+                vec![command(&["-d", name, groupname])]
+            },
+            (Self::Busybox, RemoveUserFromGroup { name, groupname }) => {
+                vec![command(&[name, groupname])]
+            },
+            (Self::Synology, RemoveUserFromGroup { name, groupname }) => {
+                vec![command(&["--memberdel", groupname, name])]
+            },
+            (Self::Dscl, RemoveUserFromGroup { name, groupname }) => vec![command(&[
+                ".",
+                "-delete",
+                &format!("/Groups/{groupname}"),
+                "users",
+                name,
+            ])],
+            (Self::Files { .. }, _) => vec![],
+        }
+    }
+
+    /// Look up the user `name`, from the name service or the `passwd` file this backend edits
+    pub fn user(&self, name: &str) -> Result<Option<UserEntry>, ActionErrorKind> {
+        if let Self::Files { root } = self {
+            return etc_files::user(root, name);
+        }
+        let user = User::from_name(name)
+            .map_err(|e| ActionErrorKind::GettingUserId(name.to_string(), e))?;
+        Ok(user.map(|user| UserEntry {
+            uid: user.uid.as_raw(),
+            gid: user.gid.as_raw(),
+        }))
+    }
+
+    /// Look up the group `name`, from the name service or the `group` file this backend edits
+    pub fn group(&self, name: &str) -> Result<Option<GroupEntry>, ActionErrorKind> {
+        if let Self::Files { root } = self {
+            return etc_files::group(root, name);
+        }
+        let group = Group::from_name(name)
+            .map_err(|e| ActionErrorKind::GettingGroupId(name.to_string(), e))?;
+        Ok(group.map(|group| GroupEntry {
+            gid: group.gid.as_raw(),
+            members: group.mem,
+        }))
+    }
+
+    /// Ensure the build user `name` can't be used to log in
+    ///
+    /// On DSM, build users are regular DSM accounts and would otherwise be usable over the network.
+    /// Other backends create system users with a locked password and no shell.
+    pub async fn verify_login_disabled(&self, name: &str) -> Result<(), ActionErrorKind> {
+        if !matches!(self, Self::Synology) {
+            return Ok(());
+        }
+
+        let shadow_path = Path::new(ETC_SHADOW);
+        let shadow = tokio::fs::read_to_string(shadow_path)
+            .await
+            .map_err(|e| ActionErrorKind::Read(shadow_path.to_path_buf(), e))?;
+        let today = days_since_epoch();
+        let shadow_disabled = shadow
+            .lines()
+            .find(|line| line.split(':').next() == Some(name))
+            .map(|line| shadow_entry_login_disabled(line, today));
+
+        match shadow_disabled {
+            Some(true) => return Ok(()),
+            // An empty password can always be used
+            Some(false) if shadow_entry_has_empty_password(&shadow, name) => {
+                return Err(ActionErrorKind::BuildUserCanAuthenticate(name.to_string()))
+            },
+            _ => (),
+        }
+
+        // DSM may track that the account is disabled in its own database rather than `/etc/shadow`
+        let mut command = Command::new("synouser");
+        command.process_group(0);
+        command.args(["--get", name]);
+        command.stdin(std::process::Stdio::null());
+        let output = execute_command(&mut command).await?;
+        if synouser_reports_expired(&String::from_utf8(output.stdout)?) {
+            Ok(())
+        } else {
+            Err(ActionErrorKind::BuildUserCanAuthenticate(name.to_string()))
+        }
+    }
+
+    /// Perform `operation`
+    pub async fn execute(&self, operation: &UserGroupOperation<'_>) -> Result<(), ActionErrorKind> {
+        self.ensure_available(operation)?;
+
+        if let (Self::Dscl, UserGroupOperation::DeleteUser { name }) = (self, operation) {
+            // Deleting users on macOS can fail in ways which are not an error for us
+            return delete_user_macos(name).await;
+        }
+
+        if let Self::Files { root } = self {
+            use UserGroupOperation::*;
+            return match *operation {
+                CreateUser {
+                    name,
+                    uid,
+                    gid,
+                    comment,
+                    ..
+                } => etc_files::create_user(root, name, uid, gid, comment).await,
+                DeleteUser { name } => etc_files::delete_user(root, name).await,
+                CreateGroup { name, gid } => etc_files::create_group(root, name, gid).await,
+                DeleteGroup { name } => etc_files::delete_group(root, name).await,
+                AddUserToGroup { name, groupname } => {
+                    etc_files::add_member(root, name, groupname).await
+                },
+                RemoveUserFromGroup { name, groupname } => {
+                    etc_files::remove_member(root, name, groupname).await
+                },
+            };
+        }
+
+        for mut command in self.commands(operation) {
+            execute_command(&mut command).await?;
+        }
+
+        // `synouser` only takes the password as an argument, so it must not be left usable
+        if let (Self::Synology, UserGroupOperation::CreateUser { name, .. }) = (self, operation) {
+            etc_files::lock_password(Path::new("/"), name).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Today, in days since the epoch, as `/etc/shadow` counts dates
+pub(crate) fn days_since_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() / (60 * 60 * 24))
+        .unwrap_or_default()
+}
+
+/// A random password for accounts which should never be logged into
+fn random_password() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                            abcdefghijklmnopqrstuvwxyz\
+                            0123456789";
+    const PASSWORD_LEN: usize = 32;
+    let mut rng = rand::thread_rng();
+
+    (0..PASSWORD_LEN)
+        .map(|_| {
+            let idx = rng.gen_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect()
+}
+
+/// Whether an `/etc/shadow` entry has a locked password or an expired account, `today` is in days since the epoch
+fn shadow_entry_login_disabled(entry: &str, today: u64) -> bool {
+    let fields = entry.split(':').collect::<Vec<_>>();
+    let locked = fields
+        .get(1)
+        .is_some_and(|password| password.starts_with('!') || password.starts_with('*'));
+    let expired = fields
+        .get(7)
+        .and_then(|expire| expire.parse::<u64>().ok())
+        .is_some_and(|expire| expire <= today);
+    locked || expired
+}
+
+fn shadow_entry_has_empty_password(shadow: &str, name: &str) -> bool {
+    shadow
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .any(|fields| fields.first() == Some(&name) && fields.get(1) == Some(&""))
+}
+
+/// Whether `synouser --get` reports the account as expired, which is how DSM disables accounts
+fn synouser_reports_expired(output: &str) -> bool {
+    output.lines().any(|line| {
+        let line = line.trim();
+        line.starts_with("Expired") && line.ends_with("[true]")
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        shadow_entry_has_empty_password, shadow_entry_login_disabled, synouser_reports_expired,
+        UserGroupBackend, UserGroupOperation,
+    };
+    use crate::action::ActionErrorKind;
+
+    fn args(backend: UserGroupBackend, operation: UserGroupOperation) -> Vec<Vec<String>> {
+        backend
+            .commands(&operation)
+            .iter()
+            .map(|command| {
+                std::iter::once(command.as_std().get_program())
+                    .chain(command.as_std().get_args())
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_backend() {
+        let has =
+            |present: &'static [&'static str]| move |program: &str| present.contains(&program);
+
+        assert_eq!(
+            UserGroupBackend::detect_with(true, false, has(&["useradd"])),
+            UserGroupBackend::Dscl
+        );
+        assert_eq!(
+            UserGroupBackend::detect_with(false, true, has(&["useradd"])),
+            UserGroupBackend::Synology
+        );
+        assert_eq!(
+            UserGroupBackend::detect_with(false, false, has(&["useradd", "adduser"])),
+            UserGroupBackend::ShadowUtils
+        );
+        assert_eq!(
+            UserGroupBackend::detect_with(false, false, has(&["adduser"])),
+            UserGroupBackend::Busybox
+        );
+        assert_eq!(
+            UserGroupBackend::detect_with(false, false, has(&[])),
+            UserGroupBackend::ShadowUtils
+        );
+    }
+
+    #[test]
+    fn reports_the_missing_synology_command() {
+        let only_synouser = |program: &str| program == "synouser";
+        let add_user_to_group = UserGroupOperation::AddUserToGroup {
+            name: "nixbld1",
+            groupname: "nixbld",
+        };
+
+        assert!(matches!(
+            UserGroupBackend::Synology.ensure_available_with(&add_user_to_group, only_synouser),
+            Err(ActionErrorKind::MissingSynologyAddUserToGroupCommand)
+        ));
+        assert!(UserGroupBackend::Synology
+            .ensure_available_with(
+                &UserGroupOperation::DeleteUser { name: "nixbld1" },
+                only_synouser
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn synology_adds_group_members_without_replacing_them() {
+        assert_eq!(
+            args(
+                UserGroupBackend::Synology,
+                UserGroupOperation::AddUserToGroup {
+                    name: "nixbld1",
+                    groupname: "nixbld",
+                }
+            ),
+            vec![vec!["synogroup", "--memberadd", "nixbld", "nixbld1"]]
+        );
+        assert_eq!(
+            args(
+                UserGroupBackend::Synology,
+                UserGroupOperation::RemoveUserFromGroup {
+                    name: "nixbld1",
+                    groupname: "nixbld",
+                }
+            ),
+            vec![vec!["synogroup", "--memberdel", "nixbld", "nixbld1"]]
+        );
+    }
+
+    #[test]
+    fn files_backend_runs_no_programs() {
+        let files = UserGroupBackend::Files {
+            root: "/mnt/image".into(),
+        };
+        let create_group = UserGroupOperation::CreateGroup {
+            name: "nixbld",
+            gid: 30000,
+        };
+
+        assert!(files
+            .ensure_available_with(&create_group, |_| false)
+            .is_ok());
+        assert!(args(files, create_group).is_empty());
+    }
+
+    #[test]
+    fn detects_disabled_logins() {
+        let today = 19_000;
+
+        assert!(shadow_entry_login_disabled(
+            "nixbld1:!:19000:0:99999:7:::",
+            today
+        ));
+        assert!(shadow_entry_login_disabled(
+            "nixbld1:$6$salt$hash:19000:0:99999:7::1:",
+            today
+        ));
+        assert!(!shadow_entry_login_disabled(
+            "nixbld1:$6$salt$hash:19000:0:99999:7:::",
+            today
+        ));
+        assert!(shadow_entry_has_empty_password(
+            "root:*:19000::::::\nnixbld1::19000:0:99999:7:::\n",
+            "nixbld1"
+        ));
+        assert!(!shadow_entry_has_empty_password(
+            "nixbld1:!:19000:0:99999:7:::\n",
+            "nixbld1"
+        ));
+    }
+
+    #[test]
+    fn detects_synouser_expired() {
+        assert!(synouser_reports_expired(
+            "User Name   : [nixbld1]\nFullname    : [Nix build user 1]\nExpired     : [true]\n"
+        ));
+        assert!(!synouser_reports_expired(
+            "User Name   : [nixbld1]\nExpired     : [false]\n"
+        ));
+    }
+}
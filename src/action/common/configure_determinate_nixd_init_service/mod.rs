@@ -7,7 +7,7 @@ use tracing::{span, Span};
 
 use crate::action::common::configure_init_service::{SocketFile, UnitSrc};
 use crate::action::{common::ConfigureInitService, Action, ActionDescription};
-use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction, Verification};
 use crate::settings::InitSystem;
 
 // Linux
@@ -40,6 +40,11 @@ impl ConfigureDeterminateNixdInitService {
             InitSystem::Launchd => Some(DARWIN_NIXD_DAEMON_DEST.into()),
             InitSystem::Systemd => Some(LINUX_NIXD_DAEMON_DEST.into()),
             InitSystem::None => None,
+            InitSystem::Upstart | InitSystem::SynologyRcD => {
+                return Err(Self::error(
+                    ConfigureDeterminateNixDaemonServiceError::UnsupportedInitSystem(init),
+                ))
+            },
         };
         let service_name: Option<String> = match init {
             InitSystem::Launchd => Some(DARWIN_NIXD_SERVICE_NAME.into()),
@@ -68,6 +73,7 @@ impl ConfigureDeterminateNixdInitService {
                     dest: "/etc/systemd/system/determinate-nixd.socket".into(),
                 },
             ],
+            None,
         )
         .await
         .map_err(Self::error)?;
@@ -165,7 +171,7 @@ impl Action for ConfigureDeterminateNixdInitService {
         let file_to_remove = match self.init {
             InitSystem::Launchd => Some(DARWIN_NIXD_DAEMON_DEST),
             InitSystem::Systemd => Some(LINUX_NIXD_DAEMON_DEST),
-            InitSystem::None => None,
+            InitSystem::None | InitSystem::Upstart | InitSystem::SynologyRcD => None,
         };
 
         if let Some(file_to_remove) = file_to_remove {
@@ -178,11 +184,69 @@ impl Action for ConfigureDeterminateNixdInitService {
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let daemon_file = match self.init {
+            InitSystem::Launchd => Some(DARWIN_NIXD_DAEMON_DEST),
+            InitSystem::Systemd => Some(LINUX_NIXD_DAEMON_DEST),
+            InitSystem::None | InitSystem::Upstart | InitSystem::SynologyRcD => None,
+        };
+
+        match daemon_file {
+            Some(daemon_file) if !std::path::Path::new(daemon_file).exists() => {
+                // `execute` rewrites the daemon definition, but skips the already completed init service
+                self.execute().await?;
+                self.configure_init_service
+                    .action
+                    .execute()
+                    .await
+                    .map_err(Self::error)?;
+                Ok(vec![format!("Restore `{daemon_file}`")])
+            },
+            _ => self
+                .configure_init_service
+                .try_repair()
+                .await
+                .map_err(Self::error),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let daemon_file = match self.init {
+            InitSystem::Launchd => Some(DARWIN_NIXD_DAEMON_DEST),
+            InitSystem::Systemd => Some(LINUX_NIXD_DAEMON_DEST),
+            InitSystem::None | InitSystem::Upstart | InitSystem::SynologyRcD => None,
+        };
+
+        let verification = self
+            .configure_init_service
+            .try_verify()
+            .await
+            .map_err(Self::error)?;
+        match daemon_file {
+            Some(daemon_file) if !std::path::Path::new(daemon_file).exists() => Ok(verification
+                .merge(Verification::Drift(vec![format!(
+                    "`{daemon_file}` is missing"
+                )]))),
+            _ => Ok(verification),
+        }
+    }
 }
 
 #[non_exhaustive]
 #[derive(Debug, thiserror::Error)]
-pub enum ConfigureDeterminateNixDaemonServiceError {}
+pub enum ConfigureDeterminateNixDaemonServiceError {
+    #[error("Determinate Nixd relies on socket activation, which `--init {0}` does not provide")]
+    UnsupportedInitSystem(InitSystem),
+}
+
+impl From<ConfigureDeterminateNixDaemonServiceError> for ActionErrorKind {
+    fn from(val: ConfigureDeterminateNixDaemonServiceError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
 
 #[derive(Deserialize, Clone, Debug, Serialize, PartialEq)]
 #[serde(rename_all = "PascalCase")]
@@ -0,0 +1,729 @@
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::action::base::file_backup::BACKUP_DIR;
+use crate::action::base::CreateFile;
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
+};
+use crate::planner::synology::SYNOINFO_CONF;
+use crate::settings::{parse_gc_older_than, rooted, GcSchedule, InitSystem};
+use crate::{execute_command, shell_quote};
+
+const NIX_COLLECT_GARBAGE: &str = "/nix/var/nix/profiles/default/bin/nix-collect-garbage";
+
+// systemd
+const GC_SERVICE_DEST: &str = "/etc/systemd/system/nix-gc.service";
+const GC_TIMER_DEST: &str = "/etc/systemd/system/nix-gc.timer";
+const GC_TIMER_NAME: &str = "nix-gc.timer";
+/// Where `systemctl enable` links units with `WantedBy=timers.target`
+const TIMERS_TARGET_WANTS: &str = "/etc/systemd/system/timers.target.wants";
+
+// cron
+const CRONTAB: &str = "/etc/crontab";
+const CRONTAB_COMMENT: &str = "# Nix garbage collection, created by `nix-installer`";
+/// DSM regenerates `/etc/crontab` from its Task Scheduler, this script adds the entry back at boot
+const GC_BOOT_SCRIPT: &str = "/usr/local/etc/rc.d/nix-gc.sh";
+
+/**
+Periodically run `nix-collect-garbage`, with a systemd timer or an `/etc/crontab` entry
+
+The crontab entry is added and removed by matching its lines, so other changes to `/etc/crontab`
+are kept. On DSM, a script in `/usr/local/etc/rc.d/` adds it back at boot.
+
+With a `root`, the files are placed inside it and the timer is enabled by linking it into `timers.target.wants`.
+*/
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "configure_garbage_collection")]
+pub struct ConfigureGarbageCollection {
+    init: InitSystem,
+    schedule: GcSchedule,
+    older_than: Option<String>,
+    start_timer: bool,
+    create_units: Vec<StatefulAction<CreateFile>>,
+    crontab_entry: Option<String>,
+    create_boot_script: Option<StatefulAction<CreateFile>>,
+    #[serde(default)]
+    root: Option<PathBuf>,
+}
+
+impl ConfigureGarbageCollection {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        init: InitSystem,
+        schedule: GcSchedule,
+        older_than: Option<String>,
+        start_timer: bool,
+        root: Option<&Path>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        // The period ends up unquoted in a unit, a crontab line and a shell script, so it must only be a number of days
+        if let Some(older_than) = &older_than {
+            parse_gc_older_than(older_than)
+                .map_err(ConfigureGarbageCollectionError::InvalidOlderThan)
+                .map_err(Self::error)?;
+        }
+        let command = match &older_than {
+            Some(older_than) => format!("{NIX_COLLECT_GARBAGE} --delete-older-than {older_than}"),
+            None => NIX_COLLECT_GARBAGE.to_string(),
+        };
+
+        let mut create_units = vec![];
+        let mut crontab_entry = None;
+        let mut create_boot_script = None;
+        match init {
+            InitSystem::Systemd => {
+                let service_buf = format!(
+                    "\
+                    [Unit]\n\
+                    Description=Nix garbage collection\n\
+                    RequiresMountsFor=/nix/store\n\
+                    \n\
+                    [Service]\n\
+                    Type=oneshot\n\
+                    ExecStart={command}\n\
+                    "
+                );
+                let timer_buf = format!(
+                    "\
+                    [Unit]\n\
+                    Description=Nix garbage collection, {schedule}\n\
+                    \n\
+                    [Timer]\n\
+                    OnCalendar={schedule}\n\
+                    Persistent=true\n\
+                    \n\
+                    [Install]\n\
+                    WantedBy=timers.target\n\
+                    "
+                );
+                for (dest, buf) in [(GC_SERVICE_DEST, service_buf), (GC_TIMER_DEST, timer_buf)] {
+                    create_units.push(
+                        CreateFile::plan(
+                            rooted(root, dest),
+                            None,
+                            None,
+                            0o0644,
+                            buf,
+                            false,
+                            rooted(root, BACKUP_DIR),
+                        )
+                        .await
+                        .map_err(Self::error)?,
+                    );
+                }
+            },
+            // Upstart has no timers, DSM 6's cron reads the system crontab
+            InitSystem::Upstart | InitSystem::SynologyRcD | InitSystem::None => {
+                let (minute, hour, day_of_month, day_of_week) = match schedule {
+                    GcSchedule::Daily => ("0", "3", "*", "*"),
+                    GcSchedule::Weekly => ("0", "3", "*", "0"),
+                    GcSchedule::Monthly => ("0", "3", "1", "*"),
+                };
+                let entry =
+                    format!("{minute}\t{hour}\t{day_of_month}\t*\t{day_of_week}\troot\t{command}");
+                if rooted(root, SYNOINFO_CONF).is_file() {
+                    let boot_script_buf = format!(
+                        "\
+                        #!/bin/sh\n\
+                        # Add the Nix garbage collection entry back to `{CRONTAB}`, created by `nix-installer`\n\
+                        \n\
+                        COMMENT={comment}\n\
+                        ENTRY={entry}\n\
+                        \n\
+                        case \"$1\" in\n\
+                        \x20   start)\n\
+                        \x20       if ! grep -qxF \"$ENTRY\" {CRONTAB}; then\n\
+                        \x20           printf '%s\\n%s\\n' \"$COMMENT\" \"$ENTRY\" >> {CRONTAB}\n\
+                        \x20           synoservicectl --reload crond > /dev/null 2>&1\n\
+                        \x20       fi\n\
+                        \x20       ;;\n\
+                        \x20   stop)\n\
+                        \x20       ;;\n\
+                        esac\n\
+                        ",
+                        comment = shell_quote(CRONTAB_COMMENT),
+                        entry = shell_quote(&entry),
+                    );
+                    create_boot_script = Some(
+                        CreateFile::plan(
+                            rooted(root, GC_BOOT_SCRIPT),
+                            None,
+                            None,
+                            0o0755,
+                            boot_script_buf,
+                            false,
+                            rooted(root, BACKUP_DIR),
+                        )
+                        .await
+                        .map_err(Self::error)?,
+                    );
+                }
+                crontab_entry = Some(entry);
+            },
+            InitSystem::Launchd => {
+                return Err(Self::error(
+                    ConfigureGarbageCollectionError::UnsupportedInitSystem(init),
+                ))
+            },
+        }
+
+        Ok(Self {
+            init,
+            schedule,
+            older_than,
+            start_timer,
+            create_units,
+            crontab_entry,
+            create_boot_script,
+            root: root.map(Path::to_path_buf),
+        }
+        .into())
+    }
+
+    fn crontab(&self) -> PathBuf {
+        rooted(self.root.as_deref(), CRONTAB)
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "configure_garbage_collection")]
+impl Action for ConfigureGarbageCollection {
+    fn action_tag() -> ActionTag {
+        ActionTag("configure_garbage_collection")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!("Schedule {} Nix garbage collection", self.schedule)
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "configure_garbage_collection",
+            schedule = %self.schedule,
+            older_than = self.older_than.as_deref(),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![match &self.older_than {
+            Some(older_than) => format!(
+                "Run `nix-collect-garbage` {}, deleting store paths unused for {older_than}",
+                self.schedule
+            ),
+            None => format!("Run `nix-collect-garbage` {}", self.schedule),
+        }];
+        for create_unit in &self.create_units {
+            explanation.push(create_unit.tracing_synopsis());
+        }
+        if self.crontab_entry.is_some() {
+            explanation.push(format!("Add an entry to `{}`", self.crontab().display()));
+        }
+        if let Some(create_boot_script) = &self.create_boot_script {
+            explanation.push(create_boot_script.tracing_synopsis());
+        }
+        if let (InitSystem::Systemd, Some(root)) = (self.init, &self.root) {
+            explanation.push(format!(
+                "Enable `{GC_TIMER_NAME}` by linking it in `{}`",
+                rooted(Some(root), TIMERS_TARGET_WANTS).display()
+            ));
+        } else if self.init == InitSystem::Systemd {
+            explanation.push(format!("Run `systemctl enable {GC_TIMER_NAME}`"));
+            if self.start_timer {
+                explanation.push(format!("Run `systemctl start {GC_TIMER_NAME}`"));
+            }
+        }
+
+        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        for create_unit in self.create_units.iter_mut() {
+            create_unit.try_execute().await.map_err(Self::error)?;
+        }
+        if let Some(crontab_entry) = &self.crontab_entry {
+            let crontab = self.crontab();
+            if add_crontab_entry(&crontab, crontab_entry)
+                .await
+                .map_err(Self::error)?
+                && self.root.is_none()
+            {
+                reload_synology_crond().await;
+            }
+        }
+        if let Some(create_boot_script) = self.create_boot_script.as_mut() {
+            create_boot_script
+                .try_execute()
+                .await
+                .map_err(Self::error)?;
+        }
+
+        if let (InitSystem::Systemd, Some(root)) = (self.init, &self.root) {
+            let wants_dir = rooted(Some(root), TIMERS_TARGET_WANTS);
+            tokio::fs::create_dir_all(&wants_dir)
+                .await
+                .map_err(|e| ActionErrorKind::CreateDirectory(wants_dir.clone(), e))
+                .map_err(Self::error)?;
+            let link = wants_dir.join(GC_TIMER_NAME);
+            if tokio::fs::symlink_metadata(&link).await.is_err() {
+                tokio::fs::symlink(GC_TIMER_DEST, &link)
+                    .await
+                    .map_err(|e| ActionErrorKind::Symlink(GC_TIMER_DEST.into(), link.clone(), e))
+                    .map_err(Self::error)?;
+            }
+        } else if self.init == InitSystem::Systemd {
+            if self.start_timer {
+                execute_command(
+                    Command::new("systemctl")
+                        .process_group(0)
+                        .arg("daemon-reload")
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)?;
+            }
+            // DSM 7's systemd 219 has no `enable --now`
+            execute_command(
+                Command::new("systemctl")
+                    .process_group(0)
+                    .arg("enable")
+                    .arg(GC_TIMER_NAME)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .map_err(Self::error)?;
+            if self.start_timer {
+                execute_command(
+                    Command::new("systemctl")
+                        .process_group(0)
+                        .arg("start")
+                        .arg(GC_TIMER_NAME)
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        let mut explanation = vec![];
+        if let (InitSystem::Systemd, Some(root)) = (self.init, &self.root) {
+            explanation.push(format!(
+                "Remove `{}`",
+                rooted(Some(root), TIMERS_TARGET_WANTS)
+                    .join(GC_TIMER_NAME)
+                    .display()
+            ));
+        } else if self.init == InitSystem::Systemd {
+            if self.start_timer {
+                explanation.push(format!("Run `systemctl stop {GC_TIMER_NAME}`"));
+            }
+            explanation.push(format!("Run `systemctl disable {GC_TIMER_NAME}`"));
+        }
+        for create_unit in &self.create_units {
+            explanation.push(format!("Remove `{}`", create_unit.action.path.display()));
+        }
+        if let Some(create_boot_script) = &self.create_boot_script {
+            explanation.push(format!(
+                "Remove `{}`",
+                create_boot_script.action.path.display()
+            ));
+        }
+        if self.crontab_entry.is_some() {
+            explanation.push(format!(
+                "Remove the garbage collection entry from `{}`",
+                self.crontab().display()
+            ));
+        }
+
+        vec![ActionDescription::new(
+            format!("Remove the {} Nix garbage collection", self.schedule),
+            explanation,
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        if let (InitSystem::Systemd, Some(root)) = (self.init, &self.root) {
+            let link = rooted(Some(root), TIMERS_TARGET_WANTS).join(GC_TIMER_NAME);
+            if tokio::fs::symlink_metadata(&link).await.is_ok() {
+                if let Err(err) = tokio::fs::remove_file(&link)
+                    .await
+                    .map_err(|e| ActionErrorKind::Remove(link.clone(), e))
+                    .map_err(Self::error)
+                {
+                    errors.push(err);
+                }
+            }
+        } else if self.init == InitSystem::Systemd {
+            let subcommands: &[&str] = if self.start_timer {
+                &["stop", "disable"]
+            } else {
+                &["disable"]
+            };
+            for subcommand in subcommands {
+                if let Err(err) = execute_command(
+                    Command::new("systemctl")
+                        .process_group(0)
+                        .arg(subcommand)
+                        .arg(GC_TIMER_NAME)
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)
+                {
+                    errors.push(err);
+                }
+            }
+        }
+
+        for create_unit in self.create_units.iter_mut().rev() {
+            if let Err(err) = create_unit.try_revert().await {
+                errors.push(err);
+            }
+        }
+        // The boot script goes first, so it cannot add the entry back
+        if let Some(create_boot_script) = self.create_boot_script.as_mut() {
+            if let Err(err) = create_boot_script.try_revert().await {
+                errors.push(err);
+            }
+        }
+        if let Some(crontab_entry) = &self.crontab_entry {
+            match remove_crontab_entry(&self.crontab(), crontab_entry).await {
+                Ok(true) if self.root.is_none() => reload_synology_crond().await,
+                Ok(_) => (),
+                Err(err) => errors.push(Self::error(err)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let mut restored = vec![];
+        for create_unit in self.create_units.iter_mut() {
+            restored.append(&mut create_unit.try_repair().await.map_err(Self::error)?);
+        }
+        if let Some(crontab_entry) = &self.crontab_entry {
+            let crontab = self.crontab();
+            if add_crontab_entry(&crontab, crontab_entry)
+                .await
+                .map_err(Self::error)?
+            {
+                restored.push(format!(
+                    "Add the garbage collection entry back to `{}`",
+                    crontab.display()
+                ));
+                if self.root.is_none() {
+                    reload_synology_crond().await;
+                }
+            }
+        }
+        if let Some(create_boot_script) = self.create_boot_script.as_mut() {
+            restored.append(&mut create_boot_script.try_repair().await.map_err(Self::error)?);
+        }
+
+        // Restored units are not known to systemd until they are enabled again, `execute` skips
+        // the files which were just restored
+        if self.init == InitSystem::Systemd && !restored.is_empty() {
+            self.execute().await?;
+        }
+
+        Ok(restored)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let mut verification = Verification::Unverified;
+        for create_unit in self.create_units.iter() {
+            verification = verification.merge(create_unit.try_verify().await.map_err(Self::error)?);
+        }
+        if let Some(crontab_entry) = &self.crontab_entry {
+            let crontab = self.crontab();
+            let drift = if crontab_has_entry(&crontab, crontab_entry)
+                .await
+                .map_err(Self::error)?
+            {
+                vec![]
+            } else {
+                vec![format!(
+                    "The garbage collection entry is missing from `{}`",
+                    crontab.display()
+                )]
+            };
+            verification = verification.merge(Verification::from_drift(drift));
+        }
+        if let Some(create_boot_script) = &self.create_boot_script {
+            verification =
+                verification.merge(create_boot_script.try_verify().await.map_err(Self::error)?);
+        }
+        Ok(verification)
+    }
+}
+
+async fn read_crontab(crontab: &Path) -> Result<String, ActionErrorKind> {
+    match tokio::fs::read_to_string(crontab).await {
+        Ok(buf) => Ok(buf),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(ActionErrorKind::Read(crontab.to_path_buf(), e)),
+    }
+}
+
+async fn crontab_has_entry(crontab: &Path, entry: &str) -> Result<bool, ActionErrorKind> {
+    Ok(read_crontab(crontab)
+        .await?
+        .lines()
+        .any(|line| line == entry))
+}
+
+/// Append `entry` to `crontab` unless it is already there, `true` if it was added
+async fn add_crontab_entry(crontab: &Path, entry: &str) -> Result<bool, ActionErrorKind> {
+    let mut buf = read_crontab(crontab).await?;
+    if buf.lines().any(|line| line == entry) {
+        return Ok(false);
+    }
+    if !buf.is_empty() && !buf.ends_with('\n') {
+        buf.push('\n');
+    }
+    buf.push_str(&format!("{CRONTAB_COMMENT}\n{entry}\n"));
+    tokio::fs::write(crontab, buf)
+        .await
+        .map_err(|e| ActionErrorKind::Write(crontab.to_path_buf(), e))?;
+    Ok(true)
+}
+
+/// Remove the lines of `entry` from `crontab`, leaving the rest as it is, `true` if it was there
+async fn remove_crontab_entry(crontab: &Path, entry: &str) -> Result<bool, ActionErrorKind> {
+    let buf = read_crontab(crontab).await?;
+    if !buf.lines().any(|line| line == entry) {
+        return Ok(false);
+    }
+    let kept = buf
+        .split_inclusive('\n')
+        .filter(|line| {
+            let line = line.trim_end_matches('\n');
+            line != entry && line != CRONTAB_COMMENT
+        })
+        .collect::<String>();
+    tokio::fs::write(crontab, kept)
+        .await
+        .map_err(|e| ActionErrorKind::Write(crontab.to_path_buf(), e))?;
+    Ok(true)
+}
+
+/// DSM's `crond` only reads `/etc/crontab` when it starts, other crons notice the change themselves
+async fn reload_synology_crond() {
+    if which::which("synoservicectl").is_err() {
+        return;
+    }
+    if let Err(err) = execute_command(
+        Command::new("synoservicectl")
+            .process_group(0)
+            .args(["--reload", "crond"])
+            .stdin(std::process::Stdio::null()),
+    )
+    .await
+    {
+        tracing::warn!(
+            %err,
+            "Could not reload `crond`, the change to `{CRONTAB}` takes effect after a reboot"
+        );
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigureGarbageCollectionError {
+    #[error("Scheduled garbage collection is not supported with `--init {0}`")]
+    UnsupportedInitSystem(InitSystem),
+    #[error("Invalid garbage collection period: {0}")]
+    InvalidOlderThan(String),
+}
+
+impl From<ConfigureGarbageCollectionError> for ActionErrorKind {
+    fn from(val: ConfigureGarbageCollectionError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_and_removes_systemd_timer() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+        let root = root.path();
+        tokio::fs::create_dir_all(root.join("etc/systemd/system")).await?;
+
+        let mut action = ConfigureGarbageCollection::plan(
+            InitSystem::Systemd,
+            GcSchedule::Weekly,
+            Some("30d".into()),
+            false,
+            Some(root),
+        )
+        .await?;
+
+        action.try_execute().await?;
+
+        let service =
+            tokio::fs::read_to_string(root.join("etc/systemd/system/nix-gc.service")).await?;
+        assert!(service.contains("Type=oneshot\n"));
+        assert!(service.contains(&format!(
+            "ExecStart={NIX_COLLECT_GARBAGE} --delete-older-than 30d\n"
+        )));
+        let timer = tokio::fs::read_to_string(root.join("etc/systemd/system/nix-gc.timer")).await?;
+        assert!(timer.contains("OnCalendar=weekly\n"));
+        assert!(timer.contains("WantedBy=timers.target\n"));
+        let wants = root.join("etc/systemd/system/timers.target.wants/nix-gc.timer");
+        assert_eq!(
+            tokio::fs::read_link(&wants).await?,
+            PathBuf::from(GC_TIMER_DEST)
+        );
+
+        action.try_revert().await?;
+
+        assert!(!root.join("etc/systemd/system/nix-gc.service").exists());
+        assert!(!root.join("etc/systemd/system/nix-gc.timer").exists());
+        assert!(tokio::fs::symlink_metadata(&wants).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_older_than() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+
+        let err = ConfigureGarbageCollection::plan(
+            InitSystem::Upstart,
+            GcSchedule::Daily,
+            Some("30d\n* * * * * root touch /pwned".into()),
+            false,
+            Some(root.path()),
+        )
+        .await
+        .expect_err("A period with more than a number of days should be rejected");
+        assert!(matches!(
+            err.kind(),
+            ActionErrorKind::Custom(e)
+                if e.downcast_ref::<ConfigureGarbageCollectionError>().is_some()
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn adds_and_removes_crontab_entry() -> eyre::Result<()> {
+        for (schedule, fields) in [
+            (GcSchedule::Daily, "0\t3\t*\t*\t*"),
+            (GcSchedule::Weekly, "0\t3\t*\t*\t0"),
+            (GcSchedule::Monthly, "0\t3\t1\t*\t*"),
+        ] {
+            let root = tempfile::tempdir()?;
+            let root = root.path();
+            tokio::fs::create_dir_all(root.join("etc")).await?;
+            let crontab = root.join("etc/crontab");
+            tokio::fs::write(&crontab, "MAILTO=\"\"\n").await?;
+
+            let mut action = ConfigureGarbageCollection::plan(
+                InitSystem::Upstart,
+                schedule,
+                None,
+                false,
+                Some(root),
+            )
+            .await?;
+
+            action.try_execute().await?;
+
+            let entry = format!("{fields}\troot\t{NIX_COLLECT_GARBAGE}");
+            assert_eq!(
+                tokio::fs::read_to_string(&crontab).await?,
+                format!("MAILTO=\"\"\n{CRONTAB_COMMENT}\n{entry}\n")
+            );
+            // Not a DSM root
+            assert!(!root.join("usr/local/etc/rc.d/nix-gc.sh").exists());
+
+            // Changes made since, such as by DSM's Task Scheduler, are kept
+            let mut edited = tokio::fs::read_to_string(&crontab).await?;
+            edited.push_str("0\t4\t*\t*\t*\troot\t/usr/syno/bin/synoschedtask --run id=1\n");
+            tokio::fs::write(&crontab, &edited).await?;
+
+            action.try_revert().await?;
+
+            assert_eq!(
+                tokio::fs::read_to_string(&crontab).await?,
+                "MAILTO=\"\"\n0\t4\t*\t*\t*\troot\t/usr/syno/bin/synoschedtask --run id=1\n"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repairs_regenerated_synology_crontab() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+        let root = root.path();
+        for dir in ["etc", "usr/local/etc/rc.d"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+        tokio::fs::write(root.join("etc/synoinfo.conf"), "").await?;
+        let crontab = root.join("etc/crontab");
+        let boot_script = root.join("usr/local/etc/rc.d/nix-gc.sh");
+
+        let mut action = ConfigureGarbageCollection::plan(
+            InitSystem::SynologyRcD,
+            GcSchedule::Daily,
+            Some("14d".into()),
+            false,
+            Some(root),
+        )
+        .await?;
+
+        action.try_execute().await?;
+
+        let entry = format!("0\t3\t*\t*\t*\troot\t{NIX_COLLECT_GARBAGE} --delete-older-than 14d");
+        let script = tokio::fs::read_to_string(&boot_script).await?;
+        assert!(script.contains(&format!("ENTRY='{entry}'\n")));
+        assert!(script.contains("grep -qxF \"$ENTRY\" /etc/crontab"));
+        assert_eq!(action.try_verify().await?, Verification::Pass);
+
+        // DSM regenerates the crontab
+        tokio::fs::write(&crontab, "MAILTO=\"\"\n").await?;
+        assert_eq!(
+            action.try_verify().await?,
+            Verification::Drift(vec![format!(
+                "The garbage collection entry is missing from `{}`",
+                crontab.display()
+            )])
+        );
+        assert_eq!(action.try_repair().await?.len(), 1);
+        assert_eq!(action.try_verify().await?, Verification::Pass);
+
+        action.try_revert().await?;
+
+        assert_eq!(tokio::fs::read_to_string(&crontab).await?, "MAILTO=\"\"\n");
+        assert!(!boot_script.exists());
+
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -5,17 +6,109 @@ use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction};
+use crate::action::linux::create_synology_nix_bind_mount::SYNOLOGY_NIX_MOUNT_SCRIPT;
+use crate::action::linux::systemd_version::systemctl_supports_now;
+use crate::action::{ActionError, ActionErrorKind, ActionTag, StatefulAction, Verification};
 use crate::execute_command;
 
 use crate::action::{Action, ActionDescription};
-use crate::settings::InitSystem;
+use crate::settings::{rooted, InitSystem};
 
 const TMPFILES_SRC: &str = "/nix/var/nix/profiles/default/lib/tmpfiles.d/nix-daemon.conf";
 const TMPFILES_DEST: &str = "/etc/tmpfiles.d/nix-daemon.conf";
 
+/// Where `systemctl enable` links units with `WantedBy=sockets.target`
+const SOCKETS_TARGET_WANTS: &str = "/etc/systemd/system/sockets.target.wants";
+
 const DARWIN_LAUNCHD_DOMAIN: &str = "system";
 
+/// DSM runs the executable `*.sh` scripts in this directory with `start` at boot and `stop` at shutdown
+pub const SYNOLOGY_RC_D_DAEMON_SCRIPT: &str = "/usr/local/etc/rc.d/nix-daemon.sh";
+
+/// Upstart reads job definitions from this directory, the job name is the file stem
+pub const UPSTART_DAEMON_JOB: &str = "/etc/init/nix-daemon.conf";
+const UPSTART_JOB_NAME: &str = "nix-daemon";
+
+/// Run the Nix daemon once the filesystems are up, respawning it if it exits
+fn upstart_daemon_job() -> String {
+    format!(
+        "\
+        # Run the Nix daemon, created by `nix-installer`\n\
+        description \"Nix daemon\"\n\
+        \n\
+        start on (local-filesystems and net-device-up IFACE!=lo)\n\
+        stop on runlevel [!2345]\n\
+        \n\
+        respawn\n\
+        \n\
+        pre-start script\n\
+        \x20   # On Synology, `/nix` is bind mounted from a data volume by a boot script\n\
+        \x20   if [ -x {mount_script} ]; then\n\
+        \x20       {mount_script} start\n\
+        \x20   fi\n\
+        end script\n\
+        \n\
+        exec /nix/var/nix/profiles/default/bin/nix-daemon\n\
+        ",
+        mount_script = SYNOLOGY_NIX_MOUNT_SCRIPT,
+    )
+}
+
+/// Start the Nix daemon at boot and stop it at shutdown, after making sure `/nix` is mounted
+fn synology_rc_d_daemon_script() -> String {
+    format!(
+        "\
+        #!/bin/sh\n\
+        # Start and stop the Nix daemon with DSM, created by `nix-installer`\n\
+        \n\
+        DAEMON=/nix/var/nix/profiles/default/bin/nix-daemon\n\
+        PIDFILE=/var/run/nix-daemon.pid\n\
+        LOGFILE=/var/log/nix-daemon.log\n\
+        \n\
+        is_running() {{\n\
+        \x20   [ -f \"$PIDFILE\" ] && kill -0 \"$(cat \"$PIDFILE\")\" 2> /dev/null\n\
+        }}\n\
+        \n\
+        case \"$1\" in\n\
+        \x20   start)\n\
+        \x20       # `/nix` is bind mounted from a data volume, DSM may not have run that script yet\n\
+        \x20       if [ -x {mount_script} ]; then\n\
+        \x20           {mount_script} start\n\
+        \x20       fi\n\
+        \x20       if is_running; then\n\
+        \x20           exit 0\n\
+        \x20       fi\n\
+        \x20       \"$DAEMON\" >> \"$LOGFILE\" 2>&1 < /dev/null &\n\
+        \x20       echo $! > \"$PIDFILE\"\n\
+        \x20       ;;\n\
+        \x20   stop)\n\
+        \x20       if is_running; then\n\
+        \x20           kill \"$(cat \"$PIDFILE\")\"\n\
+        \x20           # Give running builds a chance to wind down\n\
+        \x20           for _ in 1 2 3 4 5 6 7 8 9 10; do\n\
+        \x20               is_running || break\n\
+        \x20               sleep 1\n\
+        \x20           done\n\
+        \x20       fi\n\
+        \x20       rm -f \"$PIDFILE\"\n\
+        \x20       ;;\n\
+        \x20   restart)\n\
+        \x20       \"$0\" stop\n\
+        \x20       \"$0\" start\n\
+        \x20       ;;\n\
+        \x20   status)\n\
+        \x20       is_running\n\
+        \x20       ;;\n\
+        \x20   *)\n\
+        \x20       echo \"Usage: $0 {{start|stop|restart|status}}\"\n\
+        \x20       exit 1\n\
+        \x20       ;;\n\
+        esac\n\
+        ",
+        mount_script = SYNOLOGY_NIX_MOUNT_SCRIPT,
+    )
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct SocketFile {
     pub name: String,
@@ -42,6 +135,9 @@ pub struct ConfigureInitService {
     service_name: Option<String>,
     service_dest: Option<PathBuf>,
     socket_files: Vec<SocketFile>,
+    /// Install into this root without a running init, `service_dest` and `socket_files` are already inside it
+    #[serde(default)]
+    root: Option<PathBuf>,
 }
 
 impl ConfigureInitService {
@@ -54,7 +150,7 @@ impl ConfigureInitService {
 
         // NOTE: Check if the unit file already exists...
         let unit_dest = PathBuf::from(dest);
-        if unit_dest.exists() {
+        if exists_or_dangling(&unit_dest) {
             match src {
                 UnitSrc::Path(unit_src) => {
                     if unit_dest.is_symlink() {
@@ -91,6 +187,11 @@ impl ConfigureInitService {
         Ok(())
     }
 
+    /// The link which enables the socket unit `name` in the root
+    fn offline_wants(&self, name: &str) -> PathBuf {
+        rooted(self.root.as_deref(), SOCKETS_TARGET_WANTS).join(name)
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
         init: InitSystem,
@@ -99,15 +200,21 @@ impl ConfigureInitService {
         service_dest: Option<PathBuf>,
         service_name: Option<String>,
         socket_files: Vec<SocketFile>,
+        root: Option<PathBuf>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         match init {
+            InitSystem::Launchd | InitSystem::SynologyRcD if root.is_some() => {
+                return Err(Self::error(
+                    ConfigureNixDaemonServiceError::RootUnsupported(init),
+                ));
+            },
             InitSystem::Launchd => {
                 // No plan checks, yet
             },
             InitSystem::Systemd => {
                 // If `no_start_daemon` is set, then we don't require a running systemd,
                 // so we don't need to check if `/run/systemd/system` exists.
-                if start_daemon {
+                if start_daemon && root.is_none() {
                     // If /run/systemd/system exists, we can be reasonably sure the machine is booted
                     // with systemd: https://www.freedesktop.org/software/systemd/man/sd_booted.html
                     if !Path::new("/run/systemd/system").exists() {
@@ -115,7 +222,8 @@ impl ConfigureInitService {
                     }
                 }
 
-                if which::which("systemctl").is_err() {
+                // Units are enabled by linking them by hand in a root
+                if root.is_none() && which::which("systemctl").is_err() {
                     return Err(Self::error(ActionErrorKind::SystemdMissing));
                 }
 
@@ -136,6 +244,37 @@ impl ConfigureInitService {
                         .map_err(Self::error)?;
                 }
             },
+            InitSystem::Upstart => {
+                if root.is_none() && which::which("initctl").is_err() {
+                    return Err(Self::error(ActionErrorKind::UpstartMissing));
+                }
+
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for upstart");
+                Self::check_if_systemd_unit_exists(
+                    &UnitSrc::Literal(upstart_daemon_job()),
+                    service_dest,
+                )
+                .await
+                .map_err(Self::error)?;
+            },
+            InitSystem::SynologyRcD => {
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for the Synology rc.d script");
+                if service_dest.exists() {
+                    let content = tokio::fs::read_to_string(service_dest)
+                        .await
+                        .map_err(|e| ActionErrorKind::Read(service_dest.clone(), e))
+                        .map_err(Self::error)?;
+                    if content != synology_rc_d_daemon_script() {
+                        return Err(Self::error(ActionErrorKind::DifferentContent(
+                            service_dest.clone(),
+                        )));
+                    }
+                }
+            },
             InitSystem::None => {
                 // Nothing here, no init system
             },
@@ -148,6 +287,7 @@ impl ConfigureInitService {
             service_dest,
             service_name,
             socket_files,
+            root,
         }
         .into())
     }
@@ -165,6 +305,8 @@ impl Action for ConfigureInitService {
             InitSystem::Launchd => {
                 "Configure Nix daemon related settings with launchctl".to_string()
             },
+            InitSystem::Upstart => "Configure the Nix daemon upstart job".to_string(),
+            InitSystem::SynologyRcD => "Configure DSM to start the Nix daemon at boot".to_string(),
             InitSystem::None => "Leave the Nix daemon unconfigured".to_string(),
         }
     }
@@ -176,6 +318,26 @@ impl Action for ConfigureInitService {
     fn execute_description(&self) -> Vec<ActionDescription> {
         let mut vec = Vec::new();
         match self.init {
+            InitSystem::Systemd if self.root.is_some() => {
+                let mut explanation = vec![];
+                if let (Some(service_src), Some(service_dest)) =
+                    (self.service_src.as_ref(), self.service_dest.as_ref())
+                {
+                    explanation.push(format!(
+                        "Symlink `{}` to `{}`",
+                        service_src.display(),
+                        service_dest.display()
+                    ));
+                }
+                for SocketFile { name, dest, .. } in self.socket_files.iter() {
+                    explanation.push(format!("Create `{}`", dest.display()));
+                    explanation.push(format!(
+                        "Enable `{name}` by linking it in `{}`",
+                        self.offline_wants(name).display()
+                    ));
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
             InitSystem::Systemd => {
                 let mut explanation = vec![
                     "Run `systemd-tmpfiles --create --prefix=/nix/var/nix`".to_string(),
@@ -239,6 +401,36 @@ impl Action for ConfigureInitService {
                 }
                 vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
             },
+            InitSystem::Upstart => {
+                let mut explanation = vec![format!(
+                    "Create `{}`",
+                    self.service_dest
+                        .as_ref()
+                        .expect("service_dest should be defined for upstart")
+                        .display()
+                )];
+                if self.root.is_none() {
+                    explanation.push("Run `initctl reload-configuration`".to_string());
+                }
+                if self.start_daemon {
+                    explanation.push(format!("Run `initctl start {UPSTART_JOB_NAME}`"));
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
+            InitSystem::SynologyRcD => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for the Synology rc.d script");
+                let mut explanation = vec![format!(
+                    "Create `{}`, which DSM runs at boot and shutdown to mount `/nix` and start or stop the Nix daemon",
+                    service_dest.display()
+                )];
+                if self.start_daemon {
+                    explanation.push(format!("Run `{} start`", service_dest.display()));
+                }
+                vec.push(ActionDescription::new(self.tracing_synopsis(), explanation))
+            },
             InitSystem::None => (),
         }
         vec
@@ -253,7 +445,9 @@ impl Action for ConfigureInitService {
             service_dest,
             service_name,
             socket_files,
+            root,
         } = self;
+        let offline = root.is_some();
 
         match init {
             InitSystem::Launchd => {
@@ -334,51 +528,57 @@ impl Action for ConfigureInitService {
 
                 // The goal state is the `socket` enabled and active, the service not enabled and stopped (it activates via socket activation)
                 let mut any_socket_was_active = false;
-                for SocketFile { name, .. } in socket_files.iter() {
-                    if is_enabled(name).await.map_err(Self::error)? {
-                        disable(name, false).await.map_err(Self::error)?;
+                if !offline {
+                    for SocketFile { name, .. } in socket_files.iter() {
+                        if is_enabled(name).await.map_err(Self::error)? {
+                            disable(name, false).await.map_err(Self::error)?;
+                        }
+                        if is_active(name).await.map_err(Self::error)? {
+                            stop(name).await.map_err(Self::error)?;
+                            any_socket_was_active = true;
+                        };
                     }
-                    if is_active(name).await.map_err(Self::error)? {
-                        stop(name).await.map_err(Self::error)?;
-                        any_socket_was_active = true;
-                    };
-                }
 
-                if is_enabled("nix-daemon.service")
-                    .await
-                    .map_err(Self::error)?
-                {
-                    let now = is_active("nix-daemon.service").await.map_err(Self::error)?;
-                    disable("nix-daemon.service", now)
+                    if is_enabled("nix-daemon.service")
                         .await
-                        .map_err(Self::error)?;
-                } else if is_active("nix-daemon.service").await.map_err(Self::error)? {
-                    stop("nix-daemon.service").await.map_err(Self::error)?;
-                };
+                        .map_err(Self::error)?
+                    {
+                        let now = is_active("nix-daemon.service").await.map_err(Self::error)?;
+                        disable("nix-daemon.service", now)
+                            .await
+                            .map_err(Self::error)?;
+                    } else if is_active("nix-daemon.service").await.map_err(Self::error)? {
+                        stop("nix-daemon.service").await.map_err(Self::error)?;
+                    };
+                }
 
-                tracing::trace!(src = TMPFILES_SRC, dest = TMPFILES_DEST, "Symlinking");
-                if !Path::new(TMPFILES_DEST).exists() {
-                    tokio::fs::symlink(TMPFILES_SRC, TMPFILES_DEST)
+                let tmpfiles_dest = rooted(root.as_deref(), TMPFILES_DEST);
+                tracing::trace!(src = TMPFILES_SRC, dest = %tmpfiles_dest.display(), "Symlinking");
+                if !exists_or_dangling(&tmpfiles_dest) {
+                    tokio::fs::symlink(TMPFILES_SRC, &tmpfiles_dest)
                         .await
                         .map_err(|e| {
                             ActionErrorKind::Symlink(
                                 PathBuf::from(TMPFILES_SRC),
-                                PathBuf::from(TMPFILES_DEST),
+                                tmpfiles_dest.clone(),
                                 e,
                             )
                         })
                         .map_err(Self::error)?;
                 }
 
-                execute_command(
-                    Command::new("systemd-tmpfiles")
-                        .process_group(0)
-                        .arg("--create")
-                        .arg("--prefix=/nix/var/nix")
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
+                // In a root, `systemd-tmpfiles-setup.service` creates them at boot
+                if !offline {
+                    execute_command(
+                        Command::new("systemd-tmpfiles")
+                            .process_group(0)
+                            .arg("--create")
+                            .arg("--prefix=/nix/var/nix")
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .await
+                    .map_err(Self::error)?;
+                }
 
                 // TODO: once we have a way to communicate interaction between the library and the
                 // cli, interactively ask for permission to remove the file
@@ -390,7 +590,7 @@ impl Action for ConfigureInitService {
                     )
                     .await
                     .map_err(Self::error)?;
-                    if Path::new(service_dest).exists() {
+                    if exists_or_dangling(service_dest) {
                         tracing::trace!(path = %service_dest.display(), "Removing");
                         tokio::fs::remove_file(service_dest)
                             .await
@@ -414,7 +614,7 @@ impl Action for ConfigureInitService {
                     Self::check_if_systemd_unit_exists(src, dest)
                         .await
                         .map_err(Self::error)?;
-                    if Path::new(dest).exists() {
+                    if exists_or_dangling(dest) {
                         tracing::trace!(path = %dest.display(), "Removing");
                         tokio::fs::remove_file(dest)
                             .await
@@ -458,8 +658,12 @@ impl Action for ConfigureInitService {
                     .map_err(Self::error)?;
                 }
 
-                for SocketFile { name, src, .. } in socket_files.iter() {
-                    if *start_daemon || any_socket_was_active {
+                for SocketFile { name, src, dest } in socket_files.iter() {
+                    if let Some(root) = root.as_deref() {
+                        enable_offline(root, name, dest)
+                            .await
+                            .map_err(Self::error)?;
+                    } else if *start_daemon || any_socket_was_active {
                         match src {
                             UnitSrc::Path(path) => {
                                 // NOTE(cole-h): we have to enable by path here because older
@@ -482,6 +686,71 @@ impl Action for ConfigureInitService {
                     }
                 }
             },
+            InitSystem::Upstart => {
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for upstart");
+
+                tracing::trace!(path = %service_dest.display(), "Writing");
+                tokio::fs::write(service_dest, upstart_daemon_job())
+                    .await
+                    .map_err(|e| ActionErrorKind::Write(service_dest.clone(), e))
+                    .map_err(Self::error)?;
+                tokio::fs::set_permissions(service_dest, PermissionsExt::from_mode(0o644))
+                    .await
+                    .map_err(|e| ActionErrorKind::SetPermissions(0o644, service_dest.clone(), e))
+                    .map_err(Self::error)?;
+
+                // Upstart reads the job when the root boots
+                if !offline {
+                    execute_command(
+                        Command::new("initctl")
+                            .process_group(0)
+                            .arg("reload-configuration")
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .await
+                    .map_err(Self::error)?;
+                }
+
+                if *start_daemon && !upstart_job_is_running().await.map_err(Self::error)? {
+                    execute_command(
+                        Command::new("initctl")
+                            .process_group(0)
+                            .arg("start")
+                            .arg(UPSTART_JOB_NAME)
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .await
+                    .map_err(Self::error)?;
+                }
+            },
+            InitSystem::SynologyRcD => {
+                let service_dest = service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for the Synology rc.d script");
+
+                tracing::trace!(path = %service_dest.display(), "Writing");
+                tokio::fs::write(service_dest, synology_rc_d_daemon_script())
+                    .await
+                    .map_err(|e| ActionErrorKind::Write(service_dest.clone(), e))
+                    .map_err(Self::error)?;
+                tokio::fs::set_permissions(service_dest, PermissionsExt::from_mode(0o755))
+                    .await
+                    .map_err(|e| ActionErrorKind::SetPermissions(0o755, service_dest.clone(), e))
+                    .map_err(Self::error)?;
+
+                if *start_daemon {
+                    execute_command(
+                        Command::new(service_dest)
+                            .process_group(0)
+                            .arg("start")
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .await
+                    .map_err(Self::error)?;
+                }
+            },
             InitSystem::None => {
                 // Nothing here, no init system
             },
@@ -492,6 +761,25 @@ impl Action for ConfigureInitService {
 
     fn revert_description(&self) -> Vec<ActionDescription> {
         match self.init {
+            InitSystem::Systemd if self.root.is_some() => {
+                let mut steps = vec![];
+                for SocketFile { name, dest, .. } in self.socket_files.iter() {
+                    steps.push(format!("Remove `{}`", self.offline_wants(name).display()));
+                    steps.push(format!("Remove `{}`", dest.display()));
+                }
+                if let Some(service_dest) = self.service_dest.as_ref() {
+                    steps.push(format!("Remove `{}`", service_dest.display()));
+                }
+                steps.push(format!(
+                    "Remove `{}`",
+                    rooted(self.root.as_deref(), TMPFILES_DEST).display()
+                ));
+
+                vec![ActionDescription::new(
+                    "Unconfigure Nix daemon related settings with systemd".to_string(),
+                    steps,
+                )]
+            },
             InitSystem::Systemd => {
                 let mut steps = vec![];
 
@@ -525,6 +813,38 @@ impl Action for ConfigureInitService {
                     )],
                 )]
             },
+            InitSystem::Upstart => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for upstart");
+                let steps = if self.root.is_some() {
+                    vec![format!("Remove `{}`", service_dest.display())]
+                } else {
+                    vec![
+                        format!("Run `initctl stop {UPSTART_JOB_NAME}`"),
+                        format!("Remove `{}`", service_dest.display()),
+                        "Run `initctl reload-configuration`".to_string(),
+                    ]
+                };
+                vec![ActionDescription::new(
+                    "Remove the Nix daemon upstart job".to_string(),
+                    steps,
+                )]
+            },
+            InitSystem::SynologyRcD => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for the Synology rc.d script");
+                vec![ActionDescription::new(
+                    "Stop starting the Nix daemon at boot".to_string(),
+                    vec![
+                        format!("Run `{} stop`", service_dest.display()),
+                        format!("Remove `{}`", service_dest.display()),
+                    ],
+                )]
+            },
             InitSystem::None => Vec::new(),
         }
     }
@@ -534,6 +854,30 @@ impl Action for ConfigureInitService {
         let mut errors = vec![];
 
         match self.init {
+            InitSystem::Systemd | InitSystem::Upstart if self.root.is_some() => {
+                let mut links = vec![];
+                if self.init == InitSystem::Systemd {
+                    for SocketFile { name, dest, .. } in self.socket_files.iter() {
+                        links.push(self.offline_wants(name));
+                        links.push(dest.clone());
+                    }
+                    links.push(rooted(self.root.as_deref(), TMPFILES_DEST));
+                }
+                links.extend(self.service_dest.clone());
+
+                for link in links {
+                    if !exists_or_dangling(&link) {
+                        continue;
+                    }
+                    tracing::trace!(path = %link.display(), "Removing");
+                    if let Err(err) = tokio::fs::remove_file(&link)
+                        .await
+                        .map_err(|e| ActionErrorKind::Remove(link.clone(), e))
+                    {
+                        errors.push(err);
+                    }
+                }
+            },
             InitSystem::Launchd => {
                 execute_command(
                     Command::new("launchctl")
@@ -681,6 +1025,78 @@ impl Action for ConfigureInitService {
                     errors.push(err);
                 }
             },
+            InitSystem::Upstart => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for upstart");
+
+                match upstart_job_is_running().await {
+                    Ok(true) => {
+                        if let Err(err) = execute_command(
+                            Command::new("initctl")
+                                .process_group(0)
+                                .arg("stop")
+                                .arg(UPSTART_JOB_NAME)
+                                .stdin(std::process::Stdio::null()),
+                        )
+                        .await
+                        {
+                            errors.push(err);
+                        }
+                    },
+                    Ok(false) => (),
+                    Err(err) => errors.push(err),
+                }
+
+                if service_dest.exists() {
+                    tracing::trace!(path = %service_dest.display(), "Removing");
+                    if let Err(err) = tokio::fs::remove_file(service_dest)
+                        .await
+                        .map_err(|e| ActionErrorKind::Remove(service_dest.clone(), e))
+                    {
+                        errors.push(err);
+                    }
+                }
+
+                if let Err(err) = execute_command(
+                    Command::new("initctl")
+                        .process_group(0)
+                        .arg("reload-configuration")
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                {
+                    errors.push(err);
+                }
+            },
+            InitSystem::SynologyRcD => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for the Synology rc.d script");
+
+                if service_dest.exists() {
+                    if let Err(err) = execute_command(
+                        Command::new(service_dest)
+                            .process_group(0)
+                            .arg("stop")
+                            .stdin(std::process::Stdio::null()),
+                    )
+                    .await
+                    {
+                        errors.push(err);
+                    }
+
+                    tracing::trace!(path = %service_dest.display(), "Removing");
+                    if let Err(err) = tokio::fs::remove_file(service_dest)
+                        .await
+                        .map_err(|e| ActionErrorKind::Remove(service_dest.clone(), e))
+                    {
+                        errors.push(err);
+                    }
+                }
+            },
             InitSystem::None => {
                 // Nothing here, no init
             },
@@ -699,6 +1115,105 @@ impl Action for ConfigureInitService {
             Err(Self::error(ActionErrorKind::Multiple(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let missing = self.missing_effects().await?;
+        if missing.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // Reconfiguring is idempotent, it replaces any units which are still in place
+        self.execute().await?;
+        Ok(missing
+            .iter()
+            .map(MissingEffect::restore_description)
+            .collect())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let missing = self.missing_effects().await?;
+        Ok(Verification::from_drift(
+            missing
+                .iter()
+                .map(MissingEffect::drift_description)
+                .collect(),
+        ))
+    }
+}
+
+impl ConfigureInitService {
+    /// The units, scripts and enablements from a previous execution which have since disappeared
+    async fn missing_effects(&self) -> Result<Vec<MissingEffect>, ActionError> {
+        let mut missing = vec![];
+        match self.init {
+            InitSystem::Systemd => {
+                let mut expected = vec![rooted(self.root.as_deref(), TMPFILES_DEST)];
+                expected.extend(self.service_dest.clone());
+                expected.extend(self.socket_files.iter().map(|socket| socket.dest.clone()));
+                for path in expected {
+                    // A dangling symlink (eg. before `/nix` is mounted) is still in place
+                    if tokio::fs::symlink_metadata(&path).await.is_err() {
+                        missing.push(MissingEffect::File(path));
+                    }
+                }
+
+                for SocketFile { name, .. } in self.socket_files.iter() {
+                    if self.root.is_some() {
+                        let wants = self.offline_wants(name);
+                        if !exists_or_dangling(&wants) {
+                            missing.push(MissingEffect::File(wants));
+                        }
+                    } else if !is_enabled(name).await.map_err(Self::error)? {
+                        missing.push(MissingEffect::Enablement(name.clone()));
+                    }
+                }
+            },
+            InitSystem::Launchd => {
+                let service_dest = self
+                    .service_dest
+                    .as_ref()
+                    .expect("service_dest should be defined for launchd");
+                if !service_dest.exists() {
+                    missing.push(MissingEffect::File(service_dest.clone()));
+                }
+            },
+            InitSystem::Upstart | InitSystem::SynologyRcD => {
+                let service_dest = self.service_dest.as_ref().expect(
+                    "service_dest should be defined for upstart and the Synology rc.d script",
+                );
+                if !service_dest.exists() {
+                    missing.push(MissingEffect::File(service_dest.clone()));
+                }
+            },
+            InitSystem::None => (),
+        }
+
+        Ok(missing)
+    }
+}
+
+/// An effect of [`ConfigureInitService`] which has disappeared since it was executed
+enum MissingEffect {
+    File(PathBuf),
+    Enablement(String),
+}
+
+impl MissingEffect {
+    fn restore_description(&self) -> String {
+        match self {
+            Self::File(path) => format!("Restore `{}`", path.display()),
+            Self::Enablement(name) => format!("Run `systemctl enable {name}`"),
+        }
+    }
+
+    fn drift_description(&self) -> String {
+        match self {
+            Self::File(path) => format!("`{}` is missing", path.display()),
+            Self::Enablement(name) => format!("`{name}` is not enabled"),
+        }
+    }
 }
 
 #[non_exhaustive]
@@ -706,6 +1221,14 @@ impl Action for ConfigureInitService {
 pub enum ConfigureNixDaemonServiceError {
     #[error("No supported init system found")]
     InitNotSupported,
+    #[error("Installing into an alternate root is not supported with `--init {0}`")]
+    RootUnsupported(InitSystem),
+}
+
+impl From<ConfigureNixDaemonServiceError> for ActionErrorKind {
+    fn from(val: ConfigureNixDaemonServiceError) -> Self {
+        ActionErrorKind::Custom(Box::new(val))
+    }
 }
 
 #[derive(Deserialize, Clone, Debug, Serialize, PartialEq)]
@@ -726,6 +1249,36 @@ pub struct ResourceLimits {
     number_of_files: usize,
 }
 
+/// Whether something is at `path`, counting symlinks whose target only exists inside a root (or once `/nix` is mounted)
+fn exists_or_dangling(path: &Path) -> bool {
+    path.exists() || path.is_symlink()
+}
+
+/// Enable the socket unit `name` at `dest` in `root` as `systemctl enable` would, by linking it into `sockets.target.wants`
+async fn enable_offline(root: &Path, name: &str, dest: &Path) -> Result<(), ActionErrorKind> {
+    let wants_dir = rooted(Some(root), SOCKETS_TARGET_WANTS);
+    tokio::fs::create_dir_all(&wants_dir)
+        .await
+        .map_err(|e| ActionErrorKind::CreateDirectory(wants_dir.clone(), e))?;
+
+    // The link is followed from inside the root once it boots
+    let target = match dest.strip_prefix(root) {
+        Ok(inside) => Path::new("/").join(inside),
+        Err(_) => dest.to_path_buf(),
+    };
+    let link = wants_dir.join(name);
+    if exists_or_dangling(&link) {
+        tokio::fs::remove_file(&link)
+            .await
+            .map_err(|e| ActionErrorKind::Remove(link.clone(), e))?;
+    }
+    tracing::trace!(src = %target.display(), dest = %link.display(), "Symlinking");
+    tokio::fs::symlink(&target, &link)
+        .await
+        .map_err(|e| ActionErrorKind::Symlink(target.clone(), link.clone(), e))?;
+    Ok(())
+}
+
 async fn stop(unit: &str) -> Result<(), ActionErrorKind> {
     let mut command = Command::new("systemctl");
     command.arg("stop");
@@ -743,12 +1296,38 @@ async fn stop(unit: &str) -> Result<(), ActionErrorKind> {
     }
 }
 
+async fn start(unit: &str) -> Result<(), ActionErrorKind> {
+    let mut command = Command::new("systemctl");
+    command.arg("start");
+    command.arg(unit);
+    let output = command
+        .output()
+        .await
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+    match output.status.success() {
+        true => {
+            tracing::trace!(%unit, "Started");
+            Ok(())
+        },
+        false => Err(ActionErrorKind::command_output(&command, output)),
+    }
+}
+
+/// The name of a unit which may have been given by path, as `systemctl start` and `systemctl stop` require
+fn unit_name(unit: &str) -> &str {
+    Path::new(unit)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(unit)
+}
+
 async fn enable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
+    let now_supported = now && systemctl_supports_now().await?;
     let mut command = Command::new("systemctl");
     command.arg("enable");
     command.arg(unit);
-    if now {
-        //command.arg("--now"); // TODO
+    if now_supported {
+        command.arg("--now");
     }
     let output = command
         .output()
@@ -757,18 +1336,25 @@ async fn enable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
     match output.status.success() {
         true => {
             tracing::trace!(unit = %unit, %now, "Enabled unit");
-            Ok(())
         },
-        false => Err(ActionErrorKind::command_output(&command, output)),
+        false => return Err(ActionErrorKind::command_output(&command, output)),
+    }
+
+    // systemd before 220 (eg on DSM 7) has no `--now`
+    if now && !now_supported {
+        start(unit_name(unit)).await?;
     }
+
+    Ok(())
 }
 
 async fn disable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
+    let now_supported = now && systemctl_supports_now().await?;
     let mut command = Command::new("systemctl");
     command.arg("disable");
     command.arg(unit);
-    if now {
-        //command.arg("--now"); // TODO
+    if now_supported {
+        command.arg("--now");
     }
     let output = command
         .output()
@@ -777,10 +1363,16 @@ async fn disable(unit: &str, now: bool) -> Result<(), ActionErrorKind> {
     match output.status.success() {
         true => {
             tracing::trace!(%unit, %now, "Disabled unit");
-            Ok(())
         },
-        false => Err(ActionErrorKind::command_output(&command, output)),
+        false => return Err(ActionErrorKind::command_output(&command, output)),
+    }
+
+    // systemd before 220 (eg on DSM 7) has no `--now`
+    if now && !now_supported {
+        stop(unit_name(unit)).await?;
     }
+
+    Ok(())
 }
 
 async fn is_active(unit: &str) -> Result<bool, ActionErrorKind> {
@@ -817,3 +1409,90 @@ async fn is_enabled(unit: &str) -> Result<bool, ActionErrorKind> {
         Ok(false)
     }
 }
+
+async fn upstart_job_is_running() -> Result<bool, ActionErrorKind> {
+    let mut command = Command::new("initctl");
+    command.arg("status");
+    command.arg(UPSTART_JOB_NAME);
+    let output = command
+        .output()
+        .await
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+    // eg. `nix-daemon start/running, process 1234`, an unknown job exits non-zero with nothing on stdout
+    if String::from_utf8(output.stdout)?.contains(" start/") {
+        tracing::trace!(job = UPSTART_JOB_NAME, "Is running");
+        Ok(true)
+    } else {
+        tracing::trace!(job = UPSTART_JOB_NAME, "Is not running");
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn links_units_in_root() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+        let root = root.path();
+        for dir in ["etc/systemd/system", "etc/tmpfiles.d"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+        let service_src =
+            PathBuf::from("/nix/var/nix/profiles/default/lib/systemd/system/nix-daemon.service");
+        let service_dest = root.join("etc/systemd/system/nix-daemon.service");
+        let socket_src =
+            PathBuf::from("/nix/var/nix/profiles/default/lib/systemd/system/nix-daemon.socket");
+        let socket_dest = root.join("etc/systemd/system/nix-daemon.socket");
+
+        let mut action = ConfigureInitService::plan(
+            InitSystem::Systemd,
+            false,
+            Some(service_src.clone()),
+            Some(service_dest.clone()),
+            None,
+            vec![SocketFile {
+                name: "nix-daemon.socket".into(),
+                src: UnitSrc::Path(socket_src.clone()),
+                dest: socket_dest.clone(),
+            }],
+            Some(root.to_path_buf()),
+        )
+        .await?;
+
+        action.try_execute().await?;
+
+        let wants = root.join("etc/systemd/system/sockets.target.wants/nix-daemon.socket");
+        assert_eq!(tokio::fs::read_link(&service_dest).await?, service_src);
+        assert_eq!(tokio::fs::read_link(&socket_dest).await?, socket_src);
+        assert_eq!(
+            tokio::fs::read_link(&wants).await?,
+            PathBuf::from("/etc/systemd/system/nix-daemon.socket")
+        );
+        assert_eq!(
+            tokio::fs::read_link(root.join("etc/tmpfiles.d/nix-daemon.conf")).await?,
+            PathBuf::from(TMPFILES_SRC)
+        );
+
+        assert_eq!(action.try_verify().await?, Verification::Pass);
+        tokio::fs::remove_file(&wants).await?;
+        assert_eq!(
+            action.try_verify().await?,
+            Verification::Drift(vec![format!("`{}` is missing", wants.display())])
+        );
+        assert_eq!(action.try_repair().await?.len(), 1);
+        assert_eq!(action.try_verify().await?, Verification::Pass);
+
+        action.try_revert().await?;
+
+        assert!(!exists_or_dangling(&service_dest));
+        assert!(!exists_or_dangling(&socket_dest));
+        assert!(!exists_or_dangling(&wants));
+        assert!(!exists_or_dangling(
+            &root.join("etc/tmpfiles.d/nix-daemon.conf")
+        ));
+
+        Ok(())
+    }
+}
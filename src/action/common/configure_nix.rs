@@ -2,12 +2,13 @@ use std::path::{Path, PathBuf};
 
 use crate::{
     action::{
-        base::SetupDefaultProfile,
+        base::{file_backup::BACKUP_DIR, SetupDefaultProfile},
         common::{ConfigureShellProfile, PlaceNixConfiguration},
         Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        Verification,
     },
     planner::ShellProfileLocations,
-    settings::{CommonSettings, SCRATCH_DIR},
+    settings::{rooted, CommonSettings, SCRATCH_DIR},
 };
 use glob::glob;
 
@@ -31,29 +32,29 @@ impl ConfigureNix {
         settings: &CommonSettings,
         extra_internal_conf: Option<nix_config_parser::NixConfig>,
     ) -> Result<StatefulAction<Self>, ActionError> {
-        let setup_default_profile = SetupDefaultProfile::plan(PathBuf::from(SCRATCH_DIR))
-            .await
-            .map_err(Self::error)?;
+        let root = settings.root.as_deref();
+        let setup_default_profile =
+            SetupDefaultProfile::plan(rooted(root, SCRATCH_DIR), settings.root.clone())
+                .await
+                .map_err(Self::error)?;
 
+        let shell_profile_locations = match root {
+            Some(root) => shell_profile_locations.rooted(root),
+            None => shell_profile_locations,
+        };
         let configure_shell_profile = if settings.modify_profile {
             Some(
-                ConfigureShellProfile::plan(shell_profile_locations)
+                ConfigureShellProfile::plan(shell_profile_locations, rooted(root, BACKUP_DIR))
                     .await
                     .map_err(Self::error)?,
             )
         } else {
             None
         };
-        let place_nix_configuration = PlaceNixConfiguration::plan(
-            settings.nix_build_group_name.clone(),
-            settings.proxy.clone(),
-            settings.ssl_cert_file.clone(),
-            extra_internal_conf.clone(),
-            settings.extra_conf.clone(),
-            settings.force,
-        )
-        .await
-        .map_err(Self::error)?;
+        let place_nix_configuration =
+            PlaceNixConfiguration::plan(settings, extra_internal_conf.clone())
+                .await
+                .map_err(Self::error)?;
 
         Ok(Self {
             place_nix_configuration,
@@ -256,6 +257,45 @@ impl Action for ConfigureNix {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        // The default profile lives in `/nix`, only the configuration outside of it can go missing
+        let mut restored = self
+            .place_nix_configuration
+            .try_repair()
+            .await
+            .map_err(Self::error)?;
+        if let Some(configure_shell_profile) = &mut self.configure_shell_profile {
+            restored.append(
+                &mut configure_shell_profile
+                    .try_repair()
+                    .await
+                    .map_err(Self::error)?,
+            );
+        }
+
+        Ok(restored)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        // The default profile lives in `/nix`, only the configuration outside of it can drift
+        let mut verification = self
+            .place_nix_configuration
+            .try_verify()
+            .await
+            .map_err(Self::error)?;
+        if let Some(configure_shell_profile) = &self.configure_shell_profile {
+            verification = verification.merge(
+                configure_shell_profile
+                    .try_verify()
+                    .await
+                    .map_err(Self::error)?,
+            );
+        }
+        Ok(verification)
+    }
 }
 
 #[non_exhaustive]
@@ -1,6 +1,7 @@
 use crate::action::base::{create_or_insert_into_file, CreateDirectory, CreateOrInsertIntoFile};
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
 };
 use crate::planner::ShellProfileLocations;
 
@@ -27,6 +28,7 @@ impl ConfigureShellProfile {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
         locations: ShellProfileLocations,
+        backup_dir: PathBuf,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let mut create_or_insert_files = Vec::default();
         let mut create_directories = Vec::default();
@@ -63,6 +65,7 @@ impl ConfigureShellProfile {
                             0o644,
                             shell_buf.to_string(),
                             create_or_insert_into_file::Position::Beginning,
+                            &backup_dir,
                         )
                         .await
                         .map_err(Self::error)?,
@@ -110,6 +113,7 @@ impl ConfigureShellProfile {
                         0o644,
                         fish_buf.to_string(),
                         create_or_insert_into_file::Position::Beginning,
+                        &backup_dir,
                     )
                     .await?,
                 );
@@ -140,6 +144,7 @@ impl ConfigureShellProfile {
                     0o644,
                     fish_buf.to_string(),
                     create_or_insert_into_file::Position::Beginning,
+                    &backup_dir,
                 )
                 .await?,
             );
@@ -167,6 +172,7 @@ impl ConfigureShellProfile {
                     0o777,
                     buf,
                     create_or_insert_into_file::Position::End,
+                    &backup_dir,
                 )
                 .await?,
             );
@@ -299,4 +305,40 @@ impl Action for ConfigureShellProfile {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let mut restored = vec![];
+        for create_directory in self.create_directories.iter_mut() {
+            restored.append(&mut create_directory.try_repair().await?);
+        }
+        for create_or_insert_into_file in self.create_or_insert_into_files.iter_mut() {
+            restored.append(
+                &mut create_or_insert_into_file
+                    .try_repair()
+                    .await
+                    .map_err(Self::error)?,
+            );
+        }
+
+        Ok(restored)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let mut verification = Verification::Unverified;
+        for create_directory in self.create_directories.iter() {
+            verification =
+                verification.merge(create_directory.try_verify().await.map_err(Self::error)?);
+        }
+        for create_or_insert_into_file in self.create_or_insert_into_files.iter() {
+            verification = verification.merge(
+                create_or_insert_into_file
+                    .try_verify()
+                    .await
+                    .map_err(Self::error)?,
+            );
+        }
+        Ok(verification)
+    }
 }
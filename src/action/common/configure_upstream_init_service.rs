@@ -1,12 +1,14 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tracing::{span, Span};
 
-use crate::action::{ActionError, ActionTag, StatefulAction};
+use crate::action::{ActionError, ActionTag, StatefulAction, Verification};
 
-use crate::action::common::configure_init_service::{SocketFile, UnitSrc};
+use crate::action::common::configure_init_service::{
+    SocketFile, UnitSrc, SYNOLOGY_RC_D_DAEMON_SCRIPT, UPSTART_DAEMON_JOB,
+};
 use crate::action::{common::ConfigureInitService, Action, ActionDescription};
-use crate::settings::InitSystem;
+use crate::settings::{rooted, InitSystem};
 
 // Linux
 const SERVICE_SRC: &str = "/nix/var/nix/profiles/default/lib/systemd/system/nix-daemon.service";
@@ -19,7 +21,7 @@ const DARWIN_NIX_DAEMON_DEST: &str = "/Library/LaunchDaemons/org.nixos.nix-daemo
 const DARWIN_LAUNCHD_SERVICE_NAME: &str = "org.nixos.nix-daemon";
 
 /**
-Configure the init to run the Nix daemon
+Configure the init to run the Nix daemon, in `root` if one is given
 */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_upstream_init_service")]
@@ -32,15 +34,20 @@ impl ConfigureUpstreamInitService {
     pub async fn plan(
         init: InitSystem,
         start_daemon: bool,
+        root: Option<&Path>,
     ) -> Result<StatefulAction<Self>, ActionError> {
         let service_src: Option<PathBuf> = match init {
             InitSystem::Launchd => Some(DARWIN_NIX_DAEMON_SOURCE.into()),
             InitSystem::Systemd => Some(SERVICE_SRC.into()),
+            // The job and script are generated by `ConfigureInitService`
+            InitSystem::Upstart | InitSystem::SynologyRcD => None,
             InitSystem::None => None,
         };
         let service_dest: Option<PathBuf> = match init {
             InitSystem::Launchd => Some(DARWIN_NIX_DAEMON_DEST.into()),
-            InitSystem::Systemd => Some(SERVICE_DEST.into()),
+            InitSystem::Systemd => Some(rooted(root, SERVICE_DEST)),
+            InitSystem::Upstart => Some(rooted(root, UPSTART_DAEMON_JOB)),
+            InitSystem::SynologyRcD => Some(SYNOLOGY_RC_D_DAEMON_SCRIPT.into()),
             InitSystem::None => None,
         };
         let service_name: Option<String> = match init {
@@ -59,8 +66,9 @@ impl ConfigureUpstreamInitService {
                 src: UnitSrc::Path(
                     "/nix/var/nix/profiles/default/lib/systemd/system/nix-daemon.socket".into(),
                 ),
-                dest: "/etc/systemd/system/nix-daemon.socket".into(),
+                dest: rooted(root, "/etc/systemd/system/nix-daemon.socket"),
             }],
+            root.map(Path::to_path_buf),
         )
         .await
         .map_err(Self::error)?;
@@ -116,4 +124,20 @@ impl Action for ConfigureUpstreamInitService {
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        self.configure_init_service
+            .try_repair()
+            .await
+            .map_err(Self::error)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        self.configure_init_service
+            .try_verify()
+            .await
+            .map_err(Self::error)
+    }
 }
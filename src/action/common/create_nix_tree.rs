@@ -1,9 +1,13 @@
+use std::path::Path;
+
 use tracing::{span, Span};
 
 use crate::action::base::CreateDirectory;
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
 };
+use crate::settings::rooted;
 
 const PATHS: &[&str] = &[
     "/nix/var",
@@ -22,7 +26,7 @@ const PATHS: &[&str] = &[
 ];
 
 /**
-Create the `/nix` tree
+Create the `/nix` tree, under `root` if one is given
  */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[serde(tag = "action_name", rename = "create_nix_tree")]
@@ -32,12 +36,12 @@ pub struct CreateNixTree {
 
 impl CreateNixTree {
     #[tracing::instrument(level = "debug", skip_all)]
-    pub async fn plan() -> Result<StatefulAction<Self>, ActionError> {
+    pub async fn plan(root: Option<&Path>) -> Result<StatefulAction<Self>, ActionError> {
         let mut create_directories = Vec::default();
         for path in PATHS {
             // We use `create_dir` over `create_dir_all` to ensure we always set permissions right
             create_directories.push(
-                CreateDirectory::plan(path, String::from("root"), None, 0o0755, true)
+                CreateDirectory::plan(rooted(root, path), String::from("root"), None, 0o0755, true)
                     .await
                     .map_err(Self::error)?,
             )
@@ -127,4 +131,14 @@ impl Action for CreateNixTree {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let mut verification = Verification::Unverified;
+        for create_directory in self.create_directories.iter() {
+            verification =
+                verification.merge(create_directory.try_verify().await.map_err(Self::error)?);
+        }
+        Ok(verification)
+    }
 }
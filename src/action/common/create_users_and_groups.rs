@@ -1,7 +1,8 @@
 use crate::{
     action::{
-        base::{AddUserToGroup, CreateGroup, CreateUser},
+        base::{AddUserToGroup, CreateGroup, CreateUser, UserGroupBackend},
         Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        Verification,
     },
     settings::CommonSettings,
 };
@@ -23,9 +24,17 @@ pub struct CreateUsersAndGroups {
 impl CreateUsersAndGroups {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(settings: CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
+        let backend = match &settings.root {
+            // The host's tools would create the users on the host
+            Some(root) => UserGroupBackend::Files { root: root.clone() },
+            None => UserGroupBackend::detect(),
+        };
+        tracing::debug!(?backend, "Detected user and group backend");
+
         let create_group = CreateGroup::plan(
             settings.nix_build_group_name.clone(),
             settings.nix_build_group_id,
+            backend.clone(),
         )?;
         let mut create_users = Vec::with_capacity(settings.nix_build_user_count as usize);
         let mut add_users_to_groups = Vec::with_capacity(settings.nix_build_user_count as usize);
@@ -37,6 +46,7 @@ impl CreateUsersAndGroups {
                     settings.nix_build_group_name.clone(),
                     settings.nix_build_group_id,
                     format!("Nix build user {index}"),
+                    backend.clone(),
                 )
                 .await
                 .map_err(Self::error)?,
@@ -47,6 +57,7 @@ impl CreateUsersAndGroups {
                     settings.nix_build_user_id_base + index,
                     settings.nix_build_group_name.clone(),
                     settings.nix_build_group_id,
+                    backend.clone(),
                 )
                 .await
                 .map_err(Self::error)?,
@@ -143,7 +154,7 @@ impl Action for CreateUsersAndGroups {
             add_users_to_groups,
             nix_build_user_count: _,
             nix_build_group_name: _,
-            nix_build_group_id: _,
+            nix_build_group_id,
             nix_build_user_prefix: _,
             nix_build_user_id_base: _,
         } = self;
@@ -203,6 +214,15 @@ impl Action for CreateUsersAndGroups {
             },
         };
 
+        // Some backends (eg `synouser`) allocate their own IDs, keep the receipt consistent with what was recorded
+        *nix_build_group_id = create_group.action.gid;
+        for (create_user, add_user_to_group) in
+            create_users.iter().zip(add_users_to_groups.iter_mut())
+        {
+            add_user_to_group.action.uid = create_user.action.uid;
+            add_user_to_group.action.gid = create_user.action.gid;
+        }
+
         for add_user_to_group in add_users_to_groups.iter_mut() {
             add_user_to_group.try_execute().await.map_err(Self::error)?;
         }
@@ -287,4 +307,42 @@ impl Action for CreateUsersAndGroups {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let mut restored = self.create_group.try_repair().await.map_err(Self::error)?;
+        for create_user in self.create_users.iter_mut() {
+            restored.append(&mut create_user.try_repair().await.map_err(Self::error)?);
+        }
+
+        // Recreated users and groups may have been allocated new IDs, as in `execute`
+        self.nix_build_group_id = self.create_group.action.gid;
+        for (create_user, add_user_to_group) in self
+            .create_users
+            .iter()
+            .zip(self.add_users_to_groups.iter_mut())
+        {
+            add_user_to_group.action.uid = create_user.action.uid;
+            add_user_to_group.action.gid = create_user.action.gid;
+        }
+
+        for add_user_to_group in self.add_users_to_groups.iter_mut() {
+            restored.append(&mut add_user_to_group.try_repair().await.map_err(Self::error)?);
+        }
+
+        Ok(restored)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let mut verification = self.create_group.try_verify().await.map_err(Self::error)?;
+        for create_user in self.create_users.iter() {
+            verification = verification.merge(create_user.try_verify().await.map_err(Self::error)?);
+        }
+        for add_user_to_group in self.add_users_to_groups.iter() {
+            verification =
+                verification.merge(add_user_to_group.try_verify().await.map_err(Self::error)?);
+        }
+        Ok(verification)
+    }
 }
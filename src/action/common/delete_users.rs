@@ -1,6 +1,6 @@
 use crate::action::{
-    base::DeleteUser, Action, ActionDescription, ActionError, ActionErrorKind, ActionTag,
-    StatefulAction,
+    base::{DeleteUser, UserGroupBackend},
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
 };
 use tracing::{span, Span};
 
@@ -19,9 +19,10 @@ impl DeleteUsersInGroup {
         group_id: u32,
         users: Vec<String>,
     ) -> Result<StatefulAction<Self>, ActionError> {
+        let backend = UserGroupBackend::detect();
         let mut delete_users = vec![];
         for users in users {
-            delete_users.push(DeleteUser::plan(users).await?)
+            delete_users.push(DeleteUser::plan(users, backend.clone()).await?)
         }
 
         Ok(Self {
@@ -1,6 +1,7 @@
 //! [`Action`](crate::action::Action)s which only call other base plugins
 
 pub(crate) mod configure_determinate_nixd_init_service;
+pub(crate) mod configure_garbage_collection;
 pub(crate) mod configure_init_service;
 pub(crate) mod configure_nix;
 pub(crate) mod configure_shell_profile;
@@ -13,6 +14,9 @@ pub(crate) mod provision_determinate_nixd;
 pub(crate) mod provision_nix;
 
 pub use configure_determinate_nixd_init_service::ConfigureDeterminateNixdInitService;
+pub use configure_garbage_collection::{
+    ConfigureGarbageCollection, ConfigureGarbageCollectionError,
+};
 pub use configure_init_service::{ConfigureInitService, ConfigureNixDaemonServiceError};
 pub use configure_nix::ConfigureNix;
 pub use configure_shell_profile::ConfigureShellProfile;
@@ -1,13 +1,15 @@
 use tracing::{span, Span};
-use url::Url;
 
 use crate::action::base::create_or_merge_nix_config::CreateOrMergeNixConfigError;
+use crate::action::base::file_backup::BACKUP_DIR;
 use crate::action::base::{CreateDirectory, CreateOrMergeNixConfig};
+use crate::action::linux::kernel_features::CompatibilitySetting;
 use crate::action::{
     Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+    Verification,
 };
 use crate::parse_ssl_cert;
-use crate::settings::UrlOrPathOrString;
+use crate::settings::{rooted, CommonSettings, UrlOrPathOrString};
 use indexmap::map::Entry;
 use std::path::PathBuf;
 
@@ -22,20 +24,25 @@ Place the `/etc/nix.conf` file
 pub struct PlaceNixConfiguration {
     create_directory: StatefulAction<CreateDirectory>,
     create_or_merge_nix_config: StatefulAction<CreateOrMergeNixConfig>,
+    /// Settings chosen for the host's kernel, already included in `create_or_merge_nix_config`
+    #[serde(default)]
+    compatibility_settings: Vec<CompatibilitySetting>,
 }
 
 impl PlaceNixConfiguration {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(
-        nix_build_group_name: String,
-        proxy: Option<Url>,
-        ssl_cert_file: Option<PathBuf>,
+        settings: &CommonSettings,
         extra_internal_conf: Option<nix_config_parser::NixConfig>,
-        extra_conf: Vec<UrlOrPathOrString>,
-        force: bool,
     ) -> Result<StatefulAction<Self>, ActionError> {
+        let root = settings.root.as_deref();
+        let proxy = &settings.proxy;
+        let ssl_cert_file = settings.ssl_cert_file.clone();
+        // The kernel the root will boot is not known
+        let tune_nix_conf = settings.tune_nix_conf && root.is_none();
+
         let mut extra_conf_text = vec![];
-        for extra in extra_conf {
+        for extra in &settings.extra_conf {
             let buf = match &extra {
                 UrlOrPathOrString::Url(url) => match url.scheme() {
                     "https" | "http" => {
@@ -91,12 +98,39 @@ impl PlaceNixConfiguration {
             .map_err(CreateOrMergeNixConfigError::ParseNixConfig)
             .map_err(Self::error)?;
 
+        let nix_build_group_name = settings.nix_build_group_name.clone();
+        let force = settings.force;
         let settings = nix_config.settings_mut();
 
         if let Some(extra) = extra_internal_conf {
             settings.extend(extra.into_settings().into_iter());
         }
 
+        // Settings the user or planner chose explicitly take precedence over the probed ones
+        #[cfg(target_os = "linux")]
+        let mut compatibility_settings = if tune_nix_conf {
+            crate::action::linux::kernel_features::KernelFeatures::probe()
+                .await
+                .compatibility_settings()
+        } else {
+            vec![]
+        };
+        #[cfg(not(target_os = "linux"))]
+        let mut compatibility_settings: Vec<CompatibilitySetting> = {
+            let _ = tune_nix_conf;
+            vec![]
+        };
+        compatibility_settings.retain(|setting| !settings.contains_key(&setting.name));
+        for setting in &compatibility_settings {
+            tracing::warn!(
+                "Setting `{} = {}` in `{NIX_CONF}`, as {}",
+                setting.name,
+                setting.value,
+                setting.reason
+            );
+            settings.insert(setting.name.clone(), setting.value.clone());
+        }
+
         settings.insert("build-users-group".to_string(), nix_build_group_name);
         let experimental_features = ["nix-command", "flakes"];
         match settings.entry("experimental-features".to_string()) {
@@ -157,15 +191,21 @@ impl PlaceNixConfiguration {
             "https://install.determinate.systems/nix-upgrade/stable/universal".to_string(),
         );
 
-        let create_directory = CreateDirectory::plan(NIX_CONF_FOLDER, None, None, 0o0755, force)
-            .await
-            .map_err(Self::error)?;
-        let create_or_merge_nix_config = CreateOrMergeNixConfig::plan(NIX_CONF, nix_config)
-            .await
-            .map_err(Self::error)?;
+        let create_directory =
+            CreateDirectory::plan(rooted(root, NIX_CONF_FOLDER), None, None, 0o0755, force)
+                .await
+                .map_err(Self::error)?;
+        let create_or_merge_nix_config = CreateOrMergeNixConfig::plan(
+            rooted(root, NIX_CONF),
+            nix_config,
+            rooted(root, BACKUP_DIR),
+        )
+        .await
+        .map_err(Self::error)?;
         Ok(Self {
             create_directory,
             create_or_merge_nix_config,
+            compatibility_settings,
         }
         .into())
     }
@@ -189,6 +229,7 @@ impl Action for PlaceNixConfiguration {
         let Self {
             create_or_merge_nix_config,
             create_directory,
+            compatibility_settings,
         } = self;
 
         let mut explanation = vec![
@@ -203,7 +244,21 @@ impl Action for PlaceNixConfiguration {
             explanation.push(val.description.clone())
         }
 
-        vec![ActionDescription::new(self.tracing_synopsis(), explanation)]
+        let mut descriptions = vec![ActionDescription::new(self.tracing_synopsis(), explanation)];
+        for CompatibilitySetting {
+            name,
+            value,
+            reason,
+        } in compatibility_settings
+        {
+            descriptions.push(ActionDescription::new(
+                format!("Set `{name} = {value}` in `{NIX_CONF}`, as {reason}"),
+                vec![format!(
+                    "Probed from the running kernel, pass `--no-tune-nix-conf` to leave it out or set `{name}` with `--extra-conf` to override it"
+                )],
+            ));
+        }
+        descriptions
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
@@ -251,4 +306,37 @@ impl Action for PlaceNixConfiguration {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let mut restored = self
+            .create_directory
+            .try_repair()
+            .await
+            .map_err(Self::error)?;
+        restored.append(
+            &mut self
+                .create_or_merge_nix_config
+                .try_repair()
+                .await
+                .map_err(Self::error)?,
+        );
+
+        Ok(restored)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let verification = self
+            .create_directory
+            .try_verify()
+            .await
+            .map_err(Self::error)?;
+        Ok(verification.merge(
+            self.create_or_merge_nix_config
+                .try_verify()
+                .await
+                .map_err(Self::error)?,
+        ))
+    }
 }
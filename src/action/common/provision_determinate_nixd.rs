@@ -30,7 +30,9 @@ impl ProvisionDeterminateNixd {
             binary_location: match init {
                 InitSystem::Launchd => MACOS_DETERMINATE_NIXD_BINARY_PATH.into(),
                 InitSystem::Systemd => LINUX_DETERMINATE_NIXD_BINARY_PATH.into(),
-                InitSystem::None => LINUX_DETERMINATE_NIXD_BINARY_PATH.into(),
+                InitSystem::None | InitSystem::Upstart | InitSystem::SynologyRcD => {
+                    LINUX_DETERMINATE_NIXD_BINARY_PATH.into()
+                },
             },
         };
 
@@ -5,10 +5,10 @@ use crate::{
     action::{
         base::{FetchAndUnpackNix, MoveUnpackedNix},
         Action, ActionDescription, ActionError, ActionErrorKind, ActionTag, StatefulAction,
+        Verification,
     },
-    settings::{CommonSettings, SCRATCH_DIR},
+    settings::{rooted, CommonSettings, SCRATCH_DIR},
 };
-use std::path::PathBuf;
 
 /**
 Place Nix and it's requirements onto the target
@@ -24,18 +24,14 @@ pub struct ProvisionNix {
 impl ProvisionNix {
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn plan(settings: &CommonSettings) -> Result<StatefulAction<Self>, ActionError> {
-        let fetch_nix = FetchAndUnpackNix::plan(
-            settings.nix_package_url.clone(),
-            PathBuf::from(SCRATCH_DIR),
-            settings.proxy.clone(),
-            settings.ssl_cert_file.clone(),
-        )
-        .await?;
-
-        let create_nix_tree = CreateNixTree::plan().await.map_err(Self::error)?;
-        let move_unpacked_nix = MoveUnpackedNix::plan(PathBuf::from(SCRATCH_DIR))
-            .await
-            .map_err(Self::error)?;
+        let root = settings.root.as_deref();
+        let fetch_nix = FetchAndUnpackNix::plan(settings, rooted(root, SCRATCH_DIR)).await?;
+
+        let create_nix_tree = CreateNixTree::plan(root).await.map_err(Self::error)?;
+        let move_unpacked_nix =
+            MoveUnpackedNix::plan(rooted(root, SCRATCH_DIR), settings.root.clone())
+                .await
+                .map_err(Self::error)?;
         Ok(Self {
             fetch_nix,
             create_nix_tree,
@@ -143,4 +139,9 @@ impl Action for ProvisionNix {
             Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
         }
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        self.create_nix_tree.try_verify().await.map_err(Self::error)
+    }
 }
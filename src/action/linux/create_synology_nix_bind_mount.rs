@@ -0,0 +1,378 @@
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+use tracing::{span, Span};
+
+use crate::action::base::{create_directory::path_is_mountpoint, CreateBtrfsSubvolume, CreateFile};
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionErrorKind, ActionState, ActionTag,
+    StatefulAction, Verification,
+};
+use crate::{execute_command, shell_quote};
+
+/// DSM runs the executable `*.sh` scripts in this directory with `start` at boot, and it is not reset by DSM updates
+pub const SYNOLOGY_NIX_MOUNT_SCRIPT: &str = "/usr/local/etc/rc.d/nix-mount.sh";
+
+/**
+Bind mount a directory on a DSM data volume (eg `/volume1/nix`) on `/nix`
+
+DSM resets `/etc/fstab` at boot, so the mount is re-established at boot by a script
+in `/usr/local/etc/rc.d/`, which DSM preserves.
+ */
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+#[serde(tag = "action_name", rename = "create_synology_nix_bind_mount")]
+pub struct CreateSynologyNixBindMount {
+    persistence: PathBuf,
+    /// A [`CreateBtrfsSubvolume`], or a [`CreateDirectory`](crate::action::base::CreateDirectory) in older receipts
+    create_persistence_directory: StatefulAction<Box<dyn Action>>,
+    create_boot_script: StatefulAction<CreateFile>,
+}
+
+impl CreateSynologyNixBindMount {
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn plan(
+        persistence: impl AsRef<Path>,
+        quota: Option<String>,
+    ) -> Result<StatefulAction<Self>, ActionError> {
+        let persistence = persistence.as_ref().to_path_buf();
+
+        let mut create_persistence_directory =
+            CreateBtrfsSubvolume::plan(&persistence, 0o0755, quota, true)
+                .await
+                .map_err(Self::error)?;
+        // A directory the installer did not create is not deleted on uninstall, whatever it holds
+        if create_persistence_directory.state == ActionState::Completed {
+            create_persistence_directory.state = ActionState::Skipped;
+        }
+        let create_persistence_directory = create_persistence_directory.boxed();
+
+        let boot_script_buf = format!(
+            "\
+            #!/bin/sh\n\
+            # Bind mount the Nix store on `/nix`, created by `nix-installer`\n\
+            \n\
+            case \"$1\" in\n\
+            \x20   start)\n\
+            \x20       mkdir -p /nix\n\
+            \x20       if ! grep -q ' /nix ' /proc/mounts; then\n\
+            \x20           mount -o bind {quoted_persistence} /nix\n\
+            \x20       fi\n\
+            \x20       # Units symlinked from the Nix store could not be resolved before the mount\n\
+            \x20       if command -v systemctl > /dev/null && [ -e /etc/systemd/system/nix-daemon.socket ]; then\n\
+            \x20           systemctl daemon-reload\n\
+            \x20           systemctl start nix-daemon.socket\n\
+            \x20       fi\n\
+            \x20       ;;\n\
+            \x20   stop)\n\
+            \x20       ;;\n\
+            esac\n\
+            ",
+            quoted_persistence = shell_quote(&persistence.to_string_lossy()),
+        );
+        let create_boot_script = CreateFile::plan(
+            SYNOLOGY_NIX_MOUNT_SCRIPT,
+            None,
+            None,
+            0o0755,
+            boot_script_buf,
+            false,
+            crate::action::base::file_backup::BACKUP_DIR,
+        )
+        .await
+        .map_err(Self::error)?;
+
+        Ok(Self {
+            persistence,
+            create_persistence_directory,
+            create_boot_script,
+        }
+        .into())
+    }
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "create_synology_nix_bind_mount")]
+impl Action for CreateSynologyNixBindMount {
+    fn action_tag() -> ActionTag {
+        ActionTag("create_synology_nix_bind_mount")
+    }
+    fn tracing_synopsis(&self) -> String {
+        format!(
+            "Bind mount `{}` on `/nix` and remount it at boot",
+            self.persistence.display()
+        )
+    }
+
+    fn tracing_span(&self) -> Span {
+        span!(
+            tracing::Level::DEBUG,
+            "create_synology_nix_bind_mount",
+            persistence = tracing::field::display(self.persistence.display()),
+        )
+    }
+
+    fn execute_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            self.tracing_synopsis(),
+            vec![
+                self.create_persistence_directory.tracing_synopsis(),
+                self.create_boot_script.tracing_synopsis(),
+                format!("Run `mount -o bind {} /nix`", self.persistence.display()),
+                "The root volume on DSM is too small for the Nix store and `/etc/fstab` is reset at boot".to_string(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn execute(&mut self) -> Result<(), ActionError> {
+        self.create_persistence_directory
+            .try_execute()
+            .await
+            .map_err(Self::error)?;
+        self.create_boot_script
+            .try_execute()
+            .await
+            .map_err(Self::error)?;
+
+        if path_is_mountpoint(Path::new("/nix"))
+            .await
+            .map_err(Self::error)?
+        {
+            tracing::debug!("`/nix` is already mounted, not bind mounting");
+        } else {
+            execute_command(
+                Command::new("mount")
+                    .process_group(0)
+                    .args(["-o", "bind"])
+                    .arg(&self.persistence)
+                    .arg("/nix")
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .map_err(Self::error)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_description(&self) -> Vec<ActionDescription> {
+        vec![ActionDescription::new(
+            format!(
+                "Unmount `/nix` and remove the bind mount of `{}`",
+                self.persistence.display()
+            ),
+            vec![
+                "Run `umount /nix`".to_string(),
+                self.create_boot_script.tracing_synopsis(),
+                self.create_persistence_directory.tracing_synopsis(),
+            ],
+        )]
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn revert(&mut self) -> Result<(), ActionError> {
+        let mut errors = vec![];
+
+        match path_is_mountpoint(Path::new("/nix")).await {
+            Ok(true) => {
+                if let Err(e) = execute_command(
+                    Command::new("umount")
+                        .process_group(0)
+                        .arg("/nix")
+                        .stdin(std::process::Stdio::null()),
+                )
+                .await
+                .map_err(Self::error)
+                {
+                    errors.push(e);
+                }
+            },
+            Ok(false) => tracing::debug!("`/nix` is not mounted, not unmounting"),
+            Err(e) => errors.push(Self::error(e)),
+        }
+
+        if let Err(err) = self.create_boot_script.try_revert().await {
+            errors.push(err);
+        }
+
+        // Only remove the backing directory once it is no longer mounted on `/nix`
+        if errors.is_empty() {
+            if let Err(err) = self.create_persistence_directory.try_revert().await {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else if errors.len() == 1 {
+            Err(errors
+                .into_iter()
+                .next()
+                .expect("Expected 1 len Vec to have at least 1 item"))
+        } else {
+            Err(Self::error(ActionErrorKind::MultipleChildren(errors)))
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let mut restored = self
+            .create_persistence_directory
+            .try_repair()
+            .await
+            .map_err(Self::error)?;
+        restored.append(
+            &mut self
+                .create_boot_script
+                .try_repair()
+                .await
+                .map_err(Self::error)?,
+        );
+
+        if !path_is_mountpoint(Path::new("/nix"))
+            .await
+            .map_err(Self::error)?
+        {
+            execute_command(
+                Command::new("mount")
+                    .process_group(0)
+                    .args(["-o", "bind"])
+                    .arg(&self.persistence)
+                    .arg("/nix")
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .map_err(Self::error)?;
+            restored.push(format!(
+                "Run `mount -o bind {} /nix`",
+                self.persistence.display()
+            ));
+        }
+
+        Ok(restored)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        let mut verification = self
+            .create_persistence_directory
+            .try_verify()
+            .await
+            .map_err(Self::error)?;
+        verification = verification.merge(
+            self.create_boot_script
+                .try_verify()
+                .await
+                .map_err(Self::error)?,
+        );
+
+        if path_is_mountpoint(Path::new("/nix"))
+            .await
+            .map_err(Self::error)?
+        {
+            Ok(verification.merge(Verification::Pass))
+        } else {
+            Ok(verification.merge(Verification::Drift(vec![format!(
+                "`{}` is not bind mounted on `/nix`",
+                self.persistence.display()
+            )])))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn quotes_persistence_in_boot_script() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let persistence = temp_dir.path().join("nix 'store'\nreboot");
+        let mut action = CreateSynologyNixBindMount::plan(&persistence, None).await?;
+
+        let buf = action.action.create_boot_script.action.buf.clone();
+        assert!(buf.starts_with("#!/bin/sh\n"));
+        assert!(
+            buf.contains(&format!(
+                "mount -o bind '{}/nix '\\''store'\\''\nreboot' /nix",
+                temp_dir.path().display()
+            )),
+            "{buf}"
+        );
+        // The path must only appear quoted, a newline in it anywhere else would start a command
+        assert_eq!(buf.matches("reboot").count(), 1, "{buf}");
+
+        // Anything mounted on the host's `/nix` must be left alone
+        if path_is_mountpoint(Path::new("/nix")).await? {
+            return Ok(());
+        }
+        // Keep the boot script in the temp dir rather than in the host's `/usr/local/etc/rc.d`
+        let boot_script = temp_dir.path().join("nix-mount.sh");
+        action.action.create_boot_script = CreateFile::plan(
+            &boot_script,
+            None,
+            None,
+            0o0755,
+            buf,
+            false,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+        action
+            .action
+            .create_persistence_directory
+            .try_execute()
+            .await?;
+        action.action.create_boot_script.try_execute().await?;
+        assert!(boot_script.exists());
+        action.state = ActionState::Completed;
+
+        action.try_revert().await?;
+
+        assert!(!boot_script.exists());
+        assert!(!persistence.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leaves_existing_persistence() -> eyre::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let persistence = temp_dir.path().join("nix");
+        tokio::fs::create_dir(&persistence).await?;
+        tokio::fs::write(persistence.join("other"), "Some other content\n").await?;
+        let mut action = CreateSynologyNixBindMount::plan(&persistence, None).await?;
+        assert_eq!(
+            action.action.create_persistence_directory.state,
+            ActionState::Skipped
+        );
+
+        // Anything mounted on the host's `/nix` must be left alone
+        if path_is_mountpoint(Path::new("/nix")).await? {
+            return Ok(());
+        }
+        let boot_script = temp_dir.path().join("nix-mount.sh");
+        action.action.create_boot_script = CreateFile::plan(
+            &boot_script,
+            None,
+            None,
+            0o0755,
+            action.action.create_boot_script.action.buf.clone(),
+            false,
+            temp_dir.path().join("backups"),
+        )
+        .await?;
+        action.action.create_boot_script.try_execute().await?;
+        action.state = ActionState::Completed;
+
+        action.try_revert().await?;
+
+        assert!(!boot_script.exists());
+        assert_eq!(
+            tokio::fs::read_to_string(persistence.join("other")).await?,
+            "Some other content\n"
+        );
+
+        Ok(())
+    }
+}
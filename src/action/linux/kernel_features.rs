@@ -0,0 +1,157 @@
+/*! Probing the kernel for the features Nix's build isolation relies on */
+use std::path::Path;
+
+/// Nix's seccomp syscall filter fails to load on older kernels, such as the 4.4 kernel DSM ships
+pub const FILTER_SYSCALLS_MIN_KERNEL: (u32, u32) = (4, 14);
+
+const KERNEL_OSRELEASE: &str = "/proc/sys/kernel/osrelease";
+const SELF_STATUS: &str = "/proc/self/status";
+const MAX_USER_NAMESPACES: &str = "/proc/sys/user/max_user_namespaces";
+
+/// A `nix.conf` setting chosen to suit the host, with the reason it was chosen
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct CompatibilitySetting {
+    pub name: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// What the running kernel supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelFeatures {
+    /// The major and minor version, if it could be determined
+    pub version: Option<(u32, u32)>,
+    pub seccomp_filter: bool,
+    pub user_namespaces: bool,
+    pub mount_namespaces: bool,
+}
+
+impl KernelFeatures {
+    /// Probe the running kernel, anything which cannot be determined is assumed to be unsupported
+    pub async fn probe() -> Self {
+        let version = tokio::fs::read_to_string(KERNEL_OSRELEASE)
+            .await
+            .ok()
+            .and_then(|osrelease| parse_kernel_version(&osrelease));
+        let seccomp_filter = tokio::fs::read_to_string(SELF_STATUS)
+            .await
+            .is_ok_and(|status| status_has_seccomp(&status));
+        let user_namespaces = Path::new("/proc/self/ns/user").exists()
+            && match tokio::fs::read_to_string(MAX_USER_NAMESPACES).await {
+                Ok(max) => max.trim() != "0",
+                // Kernels before 4.9 have no limit to read
+                Err(_) => true,
+            };
+        let mount_namespaces = Path::new("/proc/self/ns/mnt").exists();
+
+        let features = Self {
+            version,
+            seccomp_filter,
+            user_namespaces,
+            mount_namespaces,
+        };
+        tracing::debug!(?features, "Probed kernel features");
+        features
+    }
+
+    /// The `nix.conf` settings this kernel needs to differ from Nix's defaults
+    pub fn compatibility_settings(&self) -> Vec<CompatibilitySetting> {
+        let mut settings = vec![];
+
+        let filter_syscalls_reason = match self.version {
+            _ if !self.seccomp_filter => Some("the kernel does not support seccomp".to_string()),
+            Some((major, minor)) if (major, minor) < FILTER_SYSCALLS_MIN_KERNEL => Some(format!(
+                "Nix's seccomp filter does not load on Linux {major}.{minor}, it needs {}.{} or newer",
+                FILTER_SYSCALLS_MIN_KERNEL.0, FILTER_SYSCALLS_MIN_KERNEL.1
+            )),
+            _ => None,
+        };
+        if let Some(reason) = filter_syscalls_reason {
+            settings.push(CompatibilitySetting {
+                name: "filter-syscalls".into(),
+                value: "false".into(),
+                reason,
+            });
+        }
+
+        let missing_namespaces = [
+            (!self.user_namespaces).then_some("user"),
+            (!self.mount_namespaces).then_some("mount"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if !missing_namespaces.is_empty() {
+            settings.push(CompatibilitySetting {
+                name: "sandbox".into(),
+                value: "false".into(),
+                reason: format!(
+                    "the kernel does not support {} namespaces, which the build sandbox needs",
+                    missing_namespaces.join(" or ")
+                ),
+            });
+        }
+
+        settings
+    }
+}
+
+/// Parse the major and minor version out of a kernel release like `4.4.302+` or `6.8.0-45-generic`
+fn parse_kernel_version(osrelease: &str) -> Option<(u32, u32)> {
+    let mut parts = osrelease.trim().split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Whether `/proc/self/status` reports a seccomp mode, which it only does on kernels built with seccomp
+fn status_has_seccomp(status: &str) -> bool {
+    status.lines().any(|line| line.starts_with("Seccomp:"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_kernel_version, KernelFeatures};
+
+    #[test]
+    fn parses_kernel_version() {
+        assert_eq!(parse_kernel_version("4.4.302+\n"), Some((4, 4)));
+        assert_eq!(parse_kernel_version("6.8.0-45-generic\n"), Some((6, 8)));
+        assert_eq!(parse_kernel_version("garbage"), None);
+    }
+
+    #[test]
+    fn tunes_for_dsm_kernel() {
+        let features = KernelFeatures {
+            version: Some((4, 4)),
+            seccomp_filter: true,
+            user_namespaces: false,
+            mount_namespaces: true,
+        };
+        let settings = features
+            .compatibility_settings()
+            .into_iter()
+            .map(|setting| (setting.name, setting.value))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            settings,
+            vec![
+                ("filter-syscalls".to_string(), "false".to_string()),
+                ("sandbox".to_string(), "false".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_modern_kernel_alone() {
+        let features = KernelFeatures {
+            version: Some((6, 8)),
+            seccomp_filter: true,
+            user_namespaces: true,
+            mount_namespaces: true,
+        };
+
+        assert!(features.compatibility_settings().is_empty());
+    }
+}
@@ -1,9 +1,13 @@
+pub(crate) mod create_synology_nix_bind_mount;
 pub(crate) mod ensure_steamos_nix_directory;
+pub(crate) mod kernel_features;
 pub(crate) mod provision_selinux;
 pub(crate) mod revert_clean_steamos_nix_offload;
 pub(crate) mod start_systemd_unit;
 pub(crate) mod systemctl_daemon_reload;
+pub(crate) mod systemd_version;
 
+pub use create_synology_nix_bind_mount::CreateSynologyNixBindMount;
 pub use ensure_steamos_nix_directory::EnsureSteamosNixDirectory;
 pub use provision_selinux::ProvisionSelinux;
 pub use revert_clean_steamos_nix_offload::RevertCleanSteamosNixOffload;
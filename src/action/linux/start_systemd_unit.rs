@@ -1,6 +1,7 @@
 use tokio::process::Command;
 use tracing::{span, Span};
 
+use crate::action::linux::systemd_version::systemctl_supports_now;
 use crate::action::{ActionError, ActionErrorKind, ActionState, ActionTag, StatefulAction};
 use crate::execute_command;
 
@@ -74,32 +75,32 @@ impl Action for StartSystemdUnit {
     async fn execute(&mut self) -> Result<(), ActionError> {
         let Self { unit, enable } = self;
 
-        match enable {
-            true => {
-                // TODO(@Hoverbear): Handle proxy vars
-                execute_command(
-                    Command::new("systemctl")
-                        .process_group(0)
-                        .arg("enable")
-                        //.arg("--now") // TODO
-                        .arg(&unit)
-                        .stdin(std::process::Stdio::null()),
-                )
-                .await
-                .map_err(Self::error)?;
-            },
-            false => {
-                // TODO(@Hoverbear): Handle proxy vars
-                execute_command(
-                    Command::new("systemctl")
-                        .process_group(0)
-                        .arg("start")
-                        .arg(&unit)
-                        .stdin(std::process::Stdio::null()),
-                )
+        let now_supported = *enable && systemctl_supports_now().await.map_err(Self::error)?;
+
+        if *enable {
+            // TODO(@Hoverbear): Handle proxy vars
+            let mut command = Command::new("systemctl");
+            command.process_group(0).arg("enable");
+            if now_supported {
+                command.arg("--now");
+            }
+            execute_command(command.arg(&unit).stdin(std::process::Stdio::null()))
                 .await
                 .map_err(Self::error)?;
-            },
+        }
+
+        // Without `--now` (or on systemd before 220, eg on DSM 7, which lacks it) start the unit separately
+        if !now_supported {
+            // TODO(@Hoverbear): Handle proxy vars
+            execute_command(
+                Command::new("systemctl")
+                    .process_group(0)
+                    .arg("start")
+                    .arg(&unit)
+                    .stdin(std::process::Stdio::null()),
+            )
+            .await
+            .map_err(Self::error)?;
         }
 
         Ok(())
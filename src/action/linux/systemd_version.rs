@@ -0,0 +1,63 @@
+use tokio::process::Command;
+
+use crate::action::ActionErrorKind;
+
+/// The first systemd release where `systemctl enable` and `systemctl disable` accept `--now`
+///
+/// DSM 7 ships systemd 219, so on it `--now` is emulated with separate `systemctl start` and `systemctl stop` calls.
+pub const SYSTEMCTL_NOW_MIN_VERSION: u32 = 220;
+
+/// Detect the systemd version from `systemctl --version`, returning `None` if it could not be parsed
+pub(crate) async fn systemd_version() -> Result<Option<u32>, ActionErrorKind> {
+    let mut command = Command::new("systemctl");
+    command.arg("--version");
+    command.stdin(std::process::Stdio::null());
+    let output = command
+        .output()
+        .await
+        .map_err(|e| ActionErrorKind::command(&command, e))?;
+    if !output.status.success() {
+        return Err(ActionErrorKind::command_output(&command, output));
+    }
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let version = parse_systemd_version(&stdout);
+    tracing::trace!(?version, "Detected systemd version");
+    Ok(version)
+}
+
+/// Whether `systemctl enable --now` and `systemctl disable --now` can be used
+///
+/// If the version can't be determined, `--now` is emulated, which has the same effect on every version.
+pub(crate) async fn systemctl_supports_now() -> Result<bool, ActionErrorKind> {
+    Ok(systemd_version()
+        .await?
+        .is_some_and(|version| version >= SYSTEMCTL_NOW_MIN_VERSION))
+}
+
+/// Parse the output of `systemctl --version`, which starts with a line like `systemd 219` or `systemd 255 (255.4-1ubuntu8)`
+fn parse_systemd_version(output: &str) -> Option<u32> {
+    let mut words = output.lines().next()?.split_whitespace();
+    if words.next()? != "systemd" {
+        return None;
+    }
+    words.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_systemd_version;
+
+    #[test]
+    fn parses_systemd_version() {
+        assert_eq!(
+            parse_systemd_version("systemd 219\n+PAM +AUDIT +SELINUX +IMA -APPARMOR +SMACK\n"),
+            Some(219)
+        );
+        assert_eq!(
+            parse_systemd_version("systemd 255 (255.4-1ubuntu8)\n+PAM +AUDIT +SELINUX\n"),
+            Some(255)
+        );
+        assert_eq!(parse_systemd_version("not systemd\n"), None);
+    }
+}
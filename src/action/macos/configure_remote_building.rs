@@ -1,5 +1,7 @@
 use crate::action::base::{create_or_insert_into_file, CreateOrInsertIntoFile};
-use crate::action::{Action, ActionDescription, ActionError, ActionTag, StatefulAction};
+use crate::action::{
+    Action, ActionDescription, ActionError, ActionTag, StatefulAction, Verification,
+};
 
 use std::path::Path;
 use tracing::{span, Instrument, Span};
@@ -41,6 +43,7 @@ fi
                     0o644,
                     shell_buf.to_string(),
                     create_or_insert_into_file::Position::Beginning,
+                    crate::action::base::file_backup::BACKUP_DIR,
                 )
                 .await
                 .map_err(Self::error)?,
@@ -110,4 +113,26 @@ impl Action for ConfigureRemoteBuilding {
 
         Ok(())
     }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        match &mut self.create_or_insert_into_file {
+            Some(create_or_insert_into_file) => create_or_insert_into_file
+                .try_repair()
+                .await
+                .map_err(Self::error),
+            None => Ok(vec![]),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        match &self.create_or_insert_into_file {
+            Some(create_or_insert_into_file) => create_or_insert_into_file
+                .try_verify()
+                .await
+                .map_err(Self::error),
+            None => Ok(Verification::Unverified),
+        }
+    }
 }
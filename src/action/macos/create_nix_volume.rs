@@ -53,6 +53,7 @@ impl CreateNixVolume {
             None,
             "nix\n".into(), /* The newline is required otherwise it segfaults */
             create_or_insert_into_file::Position::End,
+            crate::action::base::file_backup::BACKUP_DIR,
         )
         .await
         .map_err(Self::error)?;
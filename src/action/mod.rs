@@ -215,7 +215,11 @@ use std::{error::Error, process::Output};
 use tokio::task::JoinError;
 use tracing::Span;
 
-use crate::{error::HasExpectedErrors, settings::UrlOrPathError, CertificateError};
+use crate::{
+    error::HasExpectedErrors,
+    settings::{NixPackagePublicKey, NixPackageSha256, UrlOrPathError},
+    CertificateError,
+};
 
 /// An action which can be reverted or completed, with an action state
 ///
@@ -262,6 +266,22 @@ pub trait Action: Send + Sync + std::fmt::Debug + dyn_clone::DynClone {
     ///
     /// This is called by [`InstallPlan::uninstall`](crate::InstallPlan::uninstall) through [`StatefulAction::try_revert`] which handles tracing as well as if the action needs to revert based on its `action_state`.
     async fn revert(&mut self) -> Result<(), ActionError>;
+    /// Restore any effects of a previous execution which have since disappeared, returning a description of each effect restored
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to call [`try_repair`][StatefulAction::try_repair], not [`repair`][Action::repair], so that only completed actions are repaired.
+    ///
+    /// This is called by [`InstallPlan::repair`](crate::InstallPlan::repair) through [`StatefulAction::try_repair`], for example after an OS update has reset `/etc`. Actions which cannot tell if their effects are still present restore nothing.
+    async fn repair(&mut self) -> Result<Vec<String>, ActionError> {
+        Ok(vec![])
+    }
+    /// Check whether the effects of a previous execution are still present, without changing anything
+    ///
+    /// If this action calls sub-[`Action`]s, care should be taken to call [`try_verify`][StatefulAction::try_verify], not [`verify`][Action::verify], so that only completed actions are checked.
+    ///
+    /// This is called by [`InstallPlan::verify`](crate::InstallPlan::verify) through [`StatefulAction::try_verify`]. Actions which cannot tell if their effects are still present are [`Verification::Unverified`].
+    async fn verify(&self) -> Result<Verification, ActionError> {
+        Ok(Verification::Unverified)
+    }
 
     fn stateful(self) -> StatefulAction<Self>
     where
@@ -303,6 +323,42 @@ impl ActionDescription {
     }
 }
 
+/// Whether the effects of a completed [`Action`] are still present, see [`Action::verify`]
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    /// Every effect is still present
+    Pass,
+    /// Some effects are missing or were changed since, with a description of each
+    Drift(Vec<String>),
+    /// The action cannot tell if its effects are still present
+    Unverified,
+}
+
+impl Verification {
+    /// [`Pass`](Verification::Pass) if nothing drifted, otherwise [`Drift`](Verification::Drift)
+    pub fn from_drift(drift: Vec<String>) -> Self {
+        if drift.is_empty() {
+            Self::Pass
+        } else {
+            Self::Drift(drift)
+        }
+    }
+
+    /// Combine the verifications of several effects, any drift wins over a pass, which wins over being unverified
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Drift(mut drift), Self::Drift(mut other)) => {
+                drift.append(&mut other);
+                Self::Drift(drift)
+            },
+            (Self::Drift(drift), _) | (_, Self::Drift(drift)) => Self::Drift(drift),
+            (Self::Pass, _) | (_, Self::Pass) => Self::Pass,
+            (Self::Unverified, Self::Unverified) => Self::Unverified,
+        }
+    }
+}
+
 /// A 'tag' name an action has that corresponds to the one we serialize in [`typetag]`
 pub struct ActionTag(&'static str);
 
@@ -484,6 +540,8 @@ pub enum ActionErrorKind {
     UserUidMismatch(String, u32, u32),
     #[error("User `{0}` existed but had a different gid ({1}) than planned ({2})")]
     UserGidMismatch(String, u32, u32),
+    #[error("Build user `{0}` is able to log in, it should be disabled. If it was created by a previous install, run `nix-installer uninstall` first")]
+    BuildUserCanAuthenticate(String),
     #[error("Getting user `{0}`")]
     NoUser(String),
     #[error("Getting gid for group `{0}`")]
@@ -553,6 +611,10 @@ pub enum ActionErrorKind {
     Plist(#[from] plist::Error),
     #[error("Unexpected binary tarball contents found, the build result from `https://releases.nixos.org/?prefix=nix/` or `nix build nix#hydraJobs.binaryTarball.$SYSTEM` is expected")]
     MalformedBinaryTarball,
+    #[error(transparent)]
+    NixPackageSha256Mismatch(Box<Sha256Mismatch>),
+    #[error(transparent)]
+    NixPackageSignatureMismatch(Box<SignatureMismatch>),
     #[error("Could not find `{0}` in PATH; This action only works on SteamOS, which should have this present in PATH.")]
     MissingSteamosBinary(String),
     #[error(
@@ -583,11 +645,17 @@ pub enum ActionErrorKind {
     MissingSynologyGroupDeletionCommand,
     #[error("Could not find a supported command to remove users from groups in PATH; please install `gpasswd` or `deluser`")]
     MissingRemoveUserFromGroupCommand,
+    #[error("Could not find a supported command to add users to groups in PATH; please add `synogroup` to PATH")]
+    MissingSynologyAddUserToGroupCommand,
+    #[error("Could not find a supported command to remove users from groups in PATH; please add `synogroup` to PATH")]
+    MissingSynologyRemoveUserFromGroupCommand,
     #[error("\
         Could not detect systemd; you may be able to get up and running without systemd with `nix-installer install linux --init none`.\n\
         See https://github.com/DeterminateSystems/nix-installer#without-systemd-linux-only for documentation on usage and drawbacks.\
         ")]
     SystemdMissing,
+    #[error("Could not find `initctl` in PATH, which upstart provides; pass `--init none` to install without a daemon")]
+    UpstartMissing,
     #[error("`{command}` failed, message: {message}")]
     DiskUtilInfoError { command: String, message: String },
     #[error(transparent)]
@@ -602,6 +670,24 @@ pub enum ActionErrorKind {
     UnknownUrlScheme,
 }
 
+/// The Nix package did not have the SHA-256 it was expected to have
+#[derive(thiserror::Error, Debug)]
+#[error("The Nix package `{package}` has the SHA-256 `{actual}`, not `{expected}`, it may have been altered")]
+pub struct Sha256Mismatch {
+    pub package: String,
+    pub expected: NixPackageSha256,
+    pub actual: NixPackageSha256,
+}
+
+/// The Nix package was not signed by the expected key
+#[derive(thiserror::Error, Debug)]
+#[error("The Nix package `{package}` was not signed by `{public_key}` (checked against the signature `{signature}`), it may have been altered")]
+pub struct SignatureMismatch {
+    pub package: String,
+    pub signature: String,
+    pub public_key: NixPackagePublicKey,
+}
+
 impl ActionErrorKind {
     pub fn command(command: &tokio::process::Command, error: std::io::Error) -> Self {
         Self::Command {
@@ -628,6 +714,11 @@ impl HasExpectedErrors for ActionErrorKind {
             | Self::PathGroupMismatch(_, _, _)
             | Self::PathModeMismatch(_, _, _) => Some(Box::new(self)),
             Self::SystemdMissing => Some(Box::new(self)),
+            Self::UpstartMissing => Some(Box::new(self)),
+            Self::BuildUserCanAuthenticate(_) => Some(Box::new(self)),
+            Self::NixPackageSha256Mismatch(_) | Self::NixPackageSignatureMismatch(_) => {
+                Some(Box::new(self))
+            },
             _ => None,
         }
     }
@@ -661,9 +752,11 @@ impl crate::diagnostics::ErrorDiagnostic for ActionErrorKind {
                     second_path.to_string_lossy().to_string(),
                 ]
             },
-            Self::NoGroup(name) | Self::NoUser(name) => {
+            Self::NoGroup(name) | Self::NoUser(name) | Self::BuildUserCanAuthenticate(name) => {
                 vec![name.clone()]
             },
+            Self::NixPackageSha256Mismatch(mismatch) => vec![mismatch.package.clone()],
+            Self::NixPackageSignatureMismatch(mismatch) => vec![mismatch.package.clone()],
             Self::Command {
                 program,
                 command: _,
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use tracing::{Instrument, Span};
 
-use super::{Action, ActionDescription, ActionError, ActionTag};
+use super::{Action, ActionDescription, ActionError, ActionTag, Verification};
 
 /// A wrapper around an [`Action`](crate::action::Action) which tracks the [`ActionState`] and
 /// handles some tracing output
@@ -106,6 +106,36 @@ impl StatefulAction<Box<dyn Action>> {
             },
         }
     }
+    /// Restore any effects of a completed action which have since disappeared
+    ///
+    /// You should prefer this ([`try_repair`][StatefulAction::try_repair]) over [`repair`][Action::repair] as it only repairs completed actions and does tracing
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn try_repair(&mut self) -> Result<Vec<String>, ActionError> {
+        if self.state != ActionState::Completed {
+            tracing::trace!("Not completed: {}", self.action.tracing_synopsis());
+            return Ok(vec![]);
+        }
+        let restored = self.action.repair().await?;
+        if !restored.is_empty() {
+            tracing::debug!("Repaired: {}", self.action.tracing_synopsis());
+        }
+        Ok(restored)
+    }
+    /// Check whether the effects of a completed action are still present
+    ///
+    /// You should prefer this ([`try_verify`][StatefulAction::try_verify]) over [`verify`][Action::verify] as it only checks completed actions and does tracing
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn try_verify(&self) -> Result<Verification, ActionError> {
+        if self.state != ActionState::Completed {
+            tracing::trace!("Not completed: {}", self.action.tracing_synopsis());
+            return Ok(Verification::Unverified);
+        }
+        let verification = self.action.verify().await?;
+        if let Verification::Drift(_) = verification {
+            tracing::debug!("Drifted: {}", self.action.tracing_synopsis());
+        }
+        Ok(verification)
+    }
 }
 
 impl<A> StatefulAction<A>
@@ -226,6 +256,52 @@ where
             },
         }
     }
+    /// Restore any effects of a completed action which have since disappeared
+    ///
+    /// You should prefer this ([`try_repair`][StatefulAction::try_repair]) over [`repair`][Action::repair] as it only repairs completed actions and does tracing
+    pub async fn try_repair(&mut self) -> Result<Vec<String>, ActionError> {
+        let span = self.action.tracing_span();
+        if self.state != ActionState::Completed {
+            tracing::trace!(
+                parent: &span,
+                "Not completed: {}",
+                self.action.tracing_synopsis()
+            );
+            return Ok(vec![]);
+        }
+        let restored = self.action.repair().instrument(span.clone()).await?;
+        if !restored.is_empty() {
+            tracing::debug!(
+                parent: &span,
+                "Repaired: {}",
+                self.action.tracing_synopsis()
+            );
+        }
+        Ok(restored)
+    }
+    /// Check whether the effects of a completed action are still present
+    ///
+    /// You should prefer this ([`try_verify`][StatefulAction::try_verify]) over [`verify`][Action::verify] as it only checks completed actions and does tracing
+    pub async fn try_verify(&self) -> Result<Verification, ActionError> {
+        let span = self.action.tracing_span();
+        if self.state != ActionState::Completed {
+            tracing::trace!(
+                parent: &span,
+                "Not completed: {}",
+                self.action.tracing_synopsis()
+            );
+            return Ok(Verification::Unverified);
+        }
+        let verification = self.action.verify().instrument(span.clone()).await?;
+        if let Verification::Drift(_) = verification {
+            tracing::debug!(
+                parent: &span,
+                "Drifted: {}",
+                self.action.tracing_synopsis()
+            );
+        }
+        Ok(verification)
+    }
 
     pub fn completed(action: A) -> Self {
         Self {
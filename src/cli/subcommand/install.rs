@@ -14,7 +14,7 @@ use crate::{
     error::HasExpectedErrors,
     plan::RECEIPT_LOCATION,
     planner::Planner,
-    settings::CommonSettings,
+    settings::{rooted, CommonSettings},
     BuiltinPlanner, InstallPlan, NixInstallerError,
 };
 use clap::{ArgAction, Parser};
@@ -63,6 +63,16 @@ pub struct Install {
     )]
     pub explain: bool,
 
+    /// If the install fails, immediately revert what it did instead of offering to
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_ROLLBACK_ON_FAILURE",
+        action(ArgAction::SetTrue),
+        default_value = "false",
+        global = true
+    )]
+    pub rollback_on_failure: bool,
+
     /// A path to a non-default installer plan
     #[clap(env = "NIX_INSTALLER_PLAN")]
     pub plan: Option<PathBuf>,
@@ -81,26 +91,34 @@ impl CommandExecute for Install {
             planner,
             settings,
             explain,
+            rollback_on_failure,
         } = self;
 
         ensure_root()?;
 
-        let existing_receipt: Option<InstallPlan> = match Path::new(RECEIPT_LOCATION).exists() {
+        let receipt_location = rooted(settings.root.as_deref(), RECEIPT_LOCATION);
+        let receipt = receipt_location.display();
+        let installer_location = rooted(settings.root.as_deref(), "/nix/nix-installer");
+
+        let existing_receipt: Option<InstallPlan> = match receipt_location.exists() {
             true => {
                 tracing::trace!("Reading existing receipt");
-                let install_plan_string = tokio::fs::read_to_string(&RECEIPT_LOCATION)
+                let install_plan_string = tokio::fs::read_to_string(&receipt_location)
                     .await
                     .wrap_err("Reading plan")?;
                 Some(
-                    serde_json::from_str(&install_plan_string).wrap_err_with(|| {
-                        format!("Unable to parse existing receipt `{RECEIPT_LOCATION}`, it may be from an incompatible version of `nix-installer`. Try running `/nix/nix-installer uninstall`, then installing again.")
+                    InstallPlan::from_receipt(&install_plan_string).wrap_err_with(|| {
+                        format!("Unable to parse existing receipt `{receipt}`, it may be from an incompatible version of `nix-installer`. Try running `/nix/nix-installer uninstall`, then installing again.")
                     })?,
                 )
             },
             false => None,
         };
 
-        let uninstall_command = match Path::new("/nix/nix-installer").exists() {
+        let uninstall_command = match installer_location.exists() {
+            true if settings.root.is_some() => {
+                format!("{} uninstall {receipt}", installer_location.display())
+            },
             true => "/nix/nix-installer uninstall".into(),
             false => format!("curl --proto '=https' --tlsv1.2 -sSf -L https://install.determinate.systems/nix/tag/v{} | sh -s -- uninstall", env!("CARGO_PKG_VERSION")),
         };
@@ -117,21 +135,21 @@ impl CommandExecute for Install {
                                 format!("\
                                     {e}\n\
                                     \n\
-                                    Found existing plan in `{RECEIPT_LOCATION}` which was created by a version incompatible `nix-installer`.\n\
+                                    Found existing plan in `{receipt}` which was created by a version incompatible `nix-installer`.\n\
                                     {EXISTING_INCOMPATIBLE_PLAN_GUIDANCE}\n\
                                 ").red()
                             );
                             return Ok(ExitCode::FAILURE)
                         }
                         if existing_receipt.planner.typetag_name() != chosen_planner.typetag_name() {
-                            eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
+                            eprintln!("{}", format!("Found existing plan in `{receipt}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
                             return Ok(ExitCode::FAILURE)
                         }
                         if existing_receipt.planner.settings().map_err(|e| eyre!(e))? != chosen_planner.settings().map_err(|e| eyre!(e))? {
-                            eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
+                            eprintln!("{}", format!("Found existing plan in `{receipt}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
                             return Ok(ExitCode::FAILURE)
                         }
-                        eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").red());
+                        eprintln!("{}", format!("Found existing plan in `{receipt}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").red());
                         return Ok(ExitCode::SUCCESS)
                     },
                     None => {
@@ -153,7 +171,7 @@ impl CommandExecute for Install {
                 let install_plan_string = tokio::fs::read_to_string(&plan_path)
                 .await
                 .wrap_err("Reading plan")?;
-                serde_json::from_str(&install_plan_string)?
+                InstallPlan::from_receipt(&install_plan_string)?
             },
             (None, None) => {
                 let builtin_planner = BuiltinPlanner::from_common_settings(settings.clone())
@@ -168,22 +186,22 @@ impl CommandExecute for Install {
                                 format!("\
                                     {e}\n\
                                     \n\
-                                    Found existing plan in `{RECEIPT_LOCATION}` which was created by a version incompatible `nix-installer`.\n\
+                                    Found existing plan in `{receipt}` which was created by a version incompatible `nix-installer`.\n\
                                     {EXISTING_INCOMPATIBLE_PLAN_GUIDANCE}\n\
                                 ").red()
                             );
                             return Ok(ExitCode::FAILURE)
                         }
                         if existing_receipt.planner.typetag_name() != builtin_planner.typetag_name() {
-                            eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
+                            eprintln!("{}", format!("Found existing plan in `{receipt}` which used a different planner, try uninstalling the existing install with `{uninstall_command}`").red());
                             return Ok(ExitCode::FAILURE)
                         }
                         if existing_receipt.planner.settings().map_err(|e| eyre!(e))? != builtin_planner.settings().map_err(|e| eyre!(e))? {
-                            eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
+                            eprintln!("{}", format!("Found existing plan in `{receipt}` which used different planner settings, try uninstalling the existing install with `{uninstall_command}`").red());
                             return Ok(ExitCode::FAILURE)
                         }
                         if existing_receipt.actions.iter().all(|v| v.state == ActionState::Completed) {
-                            eprintln!("{}", format!("Found existing plan in `{RECEIPT_LOCATION}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").yellow());
+                            eprintln!("{}", format!("Found existing plan in `{receipt}`, with the same settings, already completed. Try uninstalling (`{uninstall_command}`) and reinstalling if Nix isn't working").yellow());
                             return Ok(ExitCode::SUCCESS)
                         }
                         existing_receipt
@@ -238,12 +256,29 @@ impl CommandExecute for Install {
 
         let (tx, rx1) = signal_channel().await?;
 
-        match install_plan.install(rx1).await {
+        let res = if rollback_on_failure {
+            install_plan.install_or_rollback(rx1).await
+        } else {
+            install_plan.install(rx1).await
+        };
+
+        match res {
+            Err(err @ NixInstallerError::InstallRolledBack { .. }) => {
+                // Whatever rolling back could not remove is uninstalled with the copy in `/nix`
+                if let NixInstallerError::InstallRolledBack {
+                    rollback: Some(_), ..
+                } = err
+                {
+                    copy_self_to_nix_dir(&installer_location).await.ok();
+                }
+
+                return Err(eyre!(err))?;
+            },
             Err(err) => {
                 // Attempt to copy self to the store if possible, but since the install failed, this might not work, that's ok.
-                copy_self_to_nix_dir().await.ok();
+                copy_self_to_nix_dir(&installer_location).await.ok();
 
-                if !no_confirm {
+                if !no_confirm && !rollback_on_failure {
                     let mut was_expected = false;
                     if let Some(expected) = err.expected() {
                         was_expected = true;
@@ -316,9 +351,14 @@ impl CommandExecute for Install {
                 }
             },
             Ok(_) => {
-                copy_self_to_nix_dir()
+                copy_self_to_nix_dir(&installer_location)
                     .await
-                    .wrap_err("Copying `nix-installer` to `/nix/nix-installer`")?;
+                    .wrap_err_with(|| {
+                        format!(
+                            "Copying `nix-installer` to `{}`",
+                            installer_location.display()
+                        )
+                    })?;
                 println!(
                     "\
                     {success}\n\
@@ -340,9 +380,9 @@ impl CommandExecute for Install {
 }
 
 #[tracing::instrument(level = "debug")]
-async fn copy_self_to_nix_dir() -> Result<(), std::io::Error> {
+async fn copy_self_to_nix_dir(dest: &Path) -> Result<(), std::io::Error> {
     let path = std::env::current_exe()?;
-    tokio::fs::copy(path, "/nix/nix-installer").await?;
-    tokio::fs::set_permissions("/nix/nix-installer", PermissionsExt::from_mode(0o0755)).await?;
+    tokio::fs::copy(path, dest).await?;
+    tokio::fs::set_permissions(dest, PermissionsExt::from_mode(0o0755)).await?;
     Ok(())
 }
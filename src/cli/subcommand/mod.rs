@@ -8,6 +8,8 @@ mod uninstall;
 use uninstall::Uninstall;
 mod self_test;
 use self_test::SelfTest;
+mod verify;
+use verify::Verify;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, clap::Subcommand)]
@@ -15,6 +17,7 @@ pub enum NixInstallerSubcommand {
     Install(Install),
     Repair(Repair),
     Uninstall(Uninstall),
+    Verify(Verify),
     SelfTest(SelfTest),
     Plan(Plan),
 }
@@ -1,14 +1,20 @@
-use std::process::ExitCode;
+use std::{path::PathBuf, process::ExitCode};
 
 use crate::{
-    action::common::ConfigureShellProfile,
     cli::{ensure_root, CommandExecute},
-    planner::{PlannerError, ShellProfileLocations},
+    error::HasExpectedErrors,
+    plan::RECEIPT_LOCATION,
+    InstallPlan,
 };
 use clap::{ArgAction, Parser};
+use color_eyre::eyre::WrapErr;
+use owo_colors::OwoColorize;
 
 /**
-Update the shell profiles to make Nix usable after system upgrades.
+Restore the parts of a `nix-installer` installed Nix which system upgrades removed.
+
+Every completed step in the install receipt is checked, and only those whose effects
+have disappeared (eg. `/etc/nix/nix.conf`, systemd units, shell profiles, build users) are redone.
 */
 #[derive(Debug, Parser)]
 #[command(args_conflicts_with_subcommands = true)]
@@ -21,37 +27,45 @@ pub struct Repair {
         global = true
     )]
     pub no_confirm: bool,
+
+    #[clap(default_value = RECEIPT_LOCATION)]
+    pub receipt: PathBuf,
 }
 
 #[async_trait::async_trait]
 impl CommandExecute for Repair {
     #[tracing::instrument(level = "trace", skip_all)]
     async fn execute(self) -> eyre::Result<ExitCode> {
-        let Self { no_confirm: _ } = self;
+        let Self {
+            no_confirm: _,
+            receipt,
+        } = self;
 
         ensure_root()?;
 
-        let mut reconfigure = ConfigureShellProfile::plan(ShellProfileLocations::default())
+        let install_receipt_string = tokio::fs::read_to_string(&receipt)
             .await
-            .map_err(PlannerError::Action)?
-            .boxed();
+            .wrap_err_with(|| format!("Reading receipt `{}`", receipt.display()))?;
+        let mut plan =
+            InstallPlan::from_receipt(&install_receipt_string).wrap_err("Parsing receipt")?;
 
-        if let Err(err) = reconfigure.try_execute().await {
-            println!("{:#?}", err);
-            return Ok(ExitCode::FAILURE);
-        }
-        // TODO: Using `cfg` based on OS is not a long term solution.
-        // Make this read the planner from the `/nix/receipt.json` to determine which tasks to run.
-        #[cfg(target_os = "macos")]
-        {
-            let mut reconfigure = crate::action::macos::ConfigureRemoteBuilding::plan()
-                .await
-                .map_err(PlannerError::Action)?
-                .boxed();
-
-            if let Err(err) = reconfigure.try_execute().await {
-                println!("{:#?}", err);
-                return Ok(ExitCode::FAILURE);
+        let restored = match plan.repair().await {
+            Ok(restored) => restored,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(ExitCode::FAILURE);
+                }
+                return Err(err)?;
+            },
+        };
+
+        if restored.is_empty() {
+            println!("{}", "Nothing needed repairing".green().bold());
+        } else {
+            println!("{}", "Nix was repaired successfully!".green().bold());
+            for description in restored {
+                println!("* {description}");
             }
         }
 
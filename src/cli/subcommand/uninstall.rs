@@ -112,7 +112,7 @@ impl CommandExecute for Uninstall {
             .await
             .wrap_err("Reading receipt")?;
 
-        let mut plan: InstallPlan = match serde_json::from_str(&install_receipt_string) {
+        let mut plan = match InstallPlan::from_receipt(&install_receipt_string) {
             Ok(plan) => plan,
             Err(plan_err) => {
                 #[derive(serde::Deserialize)]
@@ -0,0 +1,88 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use crate::{
+    cli::CommandExecute, error::HasExpectedErrors, plan::RECEIPT_LOCATION, InstallPlan,
+    VerificationStatus,
+};
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+/**
+Check that a `nix-installer` installed Nix is still intact, without changing anything.
+
+Every completed step in the install receipt is checked (eg. files still have the right content and
+mode, build users have the right UID, the daemon's units are enabled), and reported as passing,
+drifted or failed. Exits unsuccessfully if anything drifted or failed, so it can be run from
+monitoring, for example after an OS update.
+*/
+#[derive(Debug, Parser)]
+pub struct Verify {
+    /// Print the results as JSON instead of a table
+    #[clap(
+        long,
+        env = "NIX_INSTALLER_JSON",
+        action(ArgAction::SetTrue),
+        default_value = "false"
+    )]
+    pub json: bool,
+
+    #[clap(default_value = RECEIPT_LOCATION)]
+    pub receipt: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for Verify {
+    #[tracing::instrument(level = "trace", skip_all)]
+    async fn execute(self) -> eyre::Result<ExitCode> {
+        let Self { json, receipt } = self;
+
+        let install_receipt_string = tokio::fs::read_to_string(&receipt)
+            .await
+            .wrap_err_with(|| format!("Reading receipt `{}`", receipt.display()))?;
+        let plan =
+            InstallPlan::from_receipt(&install_receipt_string).wrap_err("Parsing receipt")?;
+
+        let verifications = match plan.verify().await {
+            Ok(verifications) => verifications,
+            Err(err) => {
+                if let Some(expected) = err.expected() {
+                    eprintln!("{}", expected.red());
+                    return Ok(ExitCode::FAILURE);
+                }
+                return Err(err)?;
+            },
+        };
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&verifications)?);
+        } else {
+            println!("{}", format!("{:<10}  STEP", "STATUS").bold());
+            for verification in verifications.iter() {
+                let status = format!("{:<10}", verification.status);
+                let status = match verification.status {
+                    VerificationStatus::Pass => status.green().to_string(),
+                    VerificationStatus::Drift => status.yellow().to_string(),
+                    VerificationStatus::Fail => status.red().to_string(),
+                    VerificationStatus::Unverified => status.dimmed().to_string(),
+                };
+                println!("{status}  {}", verification.description);
+                for detail in verification.details.iter() {
+                    println!("{:<10}    * {detail}", "");
+                }
+            }
+        }
+
+        let intact = verifications.iter().all(|verification| {
+            matches!(
+                verification.status,
+                VerificationStatus::Pass | VerificationStatus::Unverified
+            )
+        });
+        if intact {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
@@ -98,6 +98,13 @@ impl DiagnosticData {
         })
     }
 
+    /// Override the OS name and version detected from `os-release`
+    pub fn os(mut self, os_name: impl Into<String>, os_version: impl Into<String>) -> Self {
+        self.os_name = os_name.into();
+        self.os_version = os_version.into();
+        self
+    }
+
     pub fn failure(mut self, err: &NixInstallerError) -> Self {
         let mut failure_chain = vec![];
         let diagnostic = err.diagnostic();
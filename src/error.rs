@@ -1,7 +1,5 @@
 use std::{error::Error, path::PathBuf};
 
-use semver::Version;
-
 use crate::{
     action::ActionError, planner::PlannerError, self_test::SelfTestError,
     settings::InstallSettingsError,
@@ -32,6 +30,18 @@ pub enum NixInstallerError {
         }
     }).collect::<Vec<_>>().join("\n"))]
     ActionRevert(Vec<ActionError>),
+    /// An install failed, and was rolled back by [`InstallPlan::install_or_rollback`](crate::InstallPlan::install_or_rollback)
+    ///
+    /// `rollback` is the error while rolling back, if the system could not be fully restored.
+    #[error("Install failed, {}", match .rollback {
+        None => "it was rolled back and the system is as it was before the install".to_string(),
+        Some(rollback) => format!("rolling it back was incomplete and part of the install is still in place\n{rollback}"),
+    })]
+    InstallRolledBack {
+        #[source]
+        install: Box<NixInstallerError>,
+        rollback: Option<Box<NixInstallerError>>,
+    },
     /// An error while writing the [`InstallPlan`](crate::InstallPlan)
     #[error("Recording install receipt")]
     RecordingReceipt(PathBuf, #[source] std::io::Error),
@@ -82,15 +92,18 @@ pub enum NixInstallerError {
         #[source]
         crate::diagnostics::DiagnosticError,
     ),
-    /// Could not parse the value as a version requirement in order to ensure it's compatible
-    #[error("Could not parse `{0}` as a version requirement in order to ensure it's compatible")]
-    InvalidVersionRequirement(String, semver::Error),
     /// Could not parse `nix-installer`'s version as a valid version according to Semantic Versioning, therefore the plan version compatibility cannot be checked
     #[error("Could not parse `nix-installer`'s version `{0}` as a valid version according to Semantic Versioning, therefore the plan version compatibility cannot be checked")]
     InvalidCurrentVersion(String, semver::Error),
-    /// This version of `nix-installer` is not compatible with this plan's version
-    #[error("`nix-installer` version `{}` is not compatible with this plan's version `{}`", .binary, .plan)]
-    IncompatibleVersion { binary: Version, plan: Version },
+    /// An error while parsing an [`InstallPlan`](crate::InstallPlan) from a receipt
+    #[error("Parsing receipt")]
+    ParsingReceipt(#[source] serde_json::Error),
+    /// The receipt could not be migrated to the current schema
+    #[error("Invalid receipt, {0}")]
+    InvalidReceipt(String),
+    /// The receipt was written by a newer `nix-installer`, with a schema this one does not understand
+    #[error("This `nix-installer` understands receipts up to schema version `{binary}`, but this receipt has schema version `{plan}`, it was written by a newer `nix-installer`")]
+    IncompatibleSchema { binary: u32, plan: u32 },
 }
 
 pub(crate) trait HasExpectedErrors: std::error::Error + Sized + Send + Sync {
@@ -102,6 +115,7 @@ impl HasExpectedErrors for NixInstallerError {
         match self {
             NixInstallerError::Action(action_error) => action_error.kind().expected(),
             NixInstallerError::ActionRevert(_) => None,
+            NixInstallerError::InstallRolledBack { .. } => None,
             this @ NixInstallerError::SelfTest(_) => Some(Box::new(this)),
             NixInstallerError::RecordingReceipt(_, _) => None,
             NixInstallerError::CopyingSelf(_) => None,
@@ -110,9 +124,10 @@ impl HasExpectedErrors for NixInstallerError {
             NixInstallerError::SemVer(_) => None,
             NixInstallerError::Planner(planner_error) => planner_error.expected(),
             NixInstallerError::InstallSettings(_) => None,
-            this @ NixInstallerError::InvalidVersionRequirement(_, _) => Some(Box::new(this)),
             this @ NixInstallerError::InvalidCurrentVersion(_, _) => Some(Box::new(this)),
-            this @ NixInstallerError::IncompatibleVersion { binary: _, plan: _ } => {
+            NixInstallerError::ParsingReceipt(_) => None,
+            this @ NixInstallerError::InvalidReceipt(_) => Some(Box::new(this)),
+            this @ NixInstallerError::IncompatibleSchema { binary: _, plan: _ } => {
                 Some(Box::new(this))
             },
             #[cfg(feature = "diagnostics")]
@@ -135,6 +150,10 @@ impl crate::diagnostics::ErrorDiagnostic for NixInstallerError {
                 .iter()
                 .map(|action_error| action_error.diagnostic())
                 .collect(),
+            Self::InstallRolledBack { install, rollback } => std::iter::once(install)
+                .chain(rollback)
+                .map(|err| err.diagnostic())
+                .collect(),
             _ => vec![],
         };
         format!(
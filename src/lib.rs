@@ -75,6 +75,7 @@ pub mod cli;
 #[cfg(feature = "diagnostics")]
 pub mod diagnostics;
 mod error;
+mod migration;
 mod os;
 mod plan;
 pub mod planner;
@@ -84,7 +85,8 @@ pub mod settings;
 use std::{ffi::OsStr, path::Path, process::Output};
 
 pub use error::NixInstallerError;
-pub use plan::InstallPlan;
+pub use migration::RECEIPT_SCHEMA_VERSION;
+pub use plan::{ActionVerification, InstallPlan, VerificationStatus};
 use planner::BuiltinPlanner;
 
 use reqwest::Certificate;
@@ -121,6 +123,11 @@ fn set_env(k: impl AsRef<OsStr>, v: impl AsRef<OsStr>) {
     std::env::set_var(k.as_ref(), v.as_ref());
 }
 
+/// Quote `s` for a POSIX shell script, as a single word
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
 async fn parse_ssl_cert(ssl_cert_file: &Path) -> Result<Certificate, CertificateError> {
     let cert_buf = tokio::fs::read(ssl_cert_file)
         .await
@@ -0,0 +1,97 @@
+/*! Upgrades of receipts written by older `nix-installer`s to the current [`RECEIPT_SCHEMA_VERSION`]
+
+Receipts are migrated in memory, as JSON, before they are deserialized into an [`InstallPlan`](crate::InstallPlan),
+so any `nix-installer` can uninstall or repair an install made by an older one.
+
+Changes which only add fields with a `#[serde(default)]` do not need a migration. Any other change to the layout
+of a receipt should bump [`RECEIPT_SCHEMA_VERSION`], append a migration from the previous schema to [`MIGRATIONS`],
+and add a receipt written at the previous schema to `tests/fixtures`.
+*/
+
+use serde_json::{Map, Value};
+
+use crate::NixInstallerError;
+
+/// The schema of the receipts written by this `nix-installer`
+pub const RECEIPT_SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), NixInstallerError>;
+
+/// `MIGRATIONS[n]` upgrades a receipt at schema `n` to schema `n + 1`
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// Receipts from before `schema_version` was recorded, which were only accepted by the exact `version` which wrote them
+///
+/// They already have the layout of schema 1, every change to it until then only added fields with defaults.
+fn v0_to_v1(_receipt: &mut Map<String, Value>) -> Result<(), NixInstallerError> {
+    Ok(())
+}
+
+/// Upgrade `receipt` to [`RECEIPT_SCHEMA_VERSION`], returning the schema it was at
+pub(crate) fn migrate(receipt: &mut Value) -> Result<u32, NixInstallerError> {
+    let receipt = receipt.as_object_mut().ok_or_else(|| {
+        NixInstallerError::InvalidReceipt("the receipt is not a JSON object".into())
+    })?;
+    let schema_version = match receipt.get("schema_version") {
+        None => 0,
+        Some(value) => value
+            .as_u64()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| {
+                NixInstallerError::InvalidReceipt(format!(
+                    "`schema_version` is `{value}`, not a schema version"
+                ))
+            })?,
+    };
+    if schema_version > RECEIPT_SCHEMA_VERSION {
+        return Err(NixInstallerError::IncompatibleSchema {
+            binary: RECEIPT_SCHEMA_VERSION,
+            plan: schema_version,
+        });
+    }
+
+    for (from, migration) in (schema_version..).zip(&MIGRATIONS[schema_version as usize..]) {
+        tracing::debug!(from, to = from + 1, "Migrating receipt");
+        migration(receipt)?;
+        receipt.insert("schema_version".into(), (from + 1).into());
+    }
+
+    Ok(schema_version)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_schema_has_a_migration() {
+        assert_eq!(MIGRATIONS.len(), RECEIPT_SCHEMA_VERSION as usize);
+    }
+
+    #[test]
+    fn migrates_unversioned_receipts() -> Result<(), NixInstallerError> {
+        let mut receipt = serde_json::json!({ "version": "0.20.2", "actions": [] });
+
+        assert_eq!(migrate(&mut receipt)?, 0);
+        assert_eq!(
+            receipt["schema_version"],
+            serde_json::json!(RECEIPT_SCHEMA_VERSION)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_newer_schemas() {
+        let mut receipt = serde_json::json!({
+            "version": "9999.0.0",
+            "schema_version": RECEIPT_SCHEMA_VERSION + 1,
+            "actions": [],
+        });
+
+        assert!(matches!(
+            migrate(&mut receipt),
+            Err(NixInstallerError::IncompatibleSchema { .. })
+        ));
+    }
+}
@@ -1,12 +1,14 @@
 use std::{path::PathBuf, str::FromStr};
 
 use crate::{
-    action::{Action, ActionDescription, StatefulAction},
+    action::{Action, ActionDescription, ActionState, StatefulAction, Verification},
+    migration::{migrate, RECEIPT_SCHEMA_VERSION},
     planner::{BuiltinPlanner, Planner},
+    settings::rooted,
     NixInstallerError,
 };
 use owo_colors::OwoColorize;
-use semver::{Version, VersionReq};
+use semver::Version;
 use tokio::sync::broadcast::Receiver;
 
 pub const RECEIPT_LOCATION: &str = "/nix/receipt.json";
@@ -17,8 +19,13 @@ revert
 */
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct InstallPlan {
+    /// The version of `nix-installer` which created the plan
     pub(crate) version: Version,
 
+    /// The layout of the plan, see [`RECEIPT_SCHEMA_VERSION`]
+    #[serde(default)]
+    pub(crate) schema_version: u32,
+
     pub(crate) actions: Vec<StatefulAction<Box<dyn Action>>>,
 
     pub(crate) planner: Box<dyn Planner>,
@@ -41,6 +48,7 @@ impl InstallPlan {
             planner,
             actions,
             version: current_version()?,
+            schema_version: RECEIPT_SCHEMA_VERSION,
             #[cfg(feature = "diagnostics")]
             diagnostic_data,
         })
@@ -63,11 +71,27 @@ impl InstallPlan {
             planner: planner.boxed(),
             actions,
             version: current_version()?,
+            schema_version: RECEIPT_SCHEMA_VERSION,
             #[cfg(feature = "diagnostics")]
             diagnostic_data,
         })
     }
 
+    /// Parse a receipt (or a plan) written by this or any older `nix-installer`, migrating it to the current [`RECEIPT_SCHEMA_VERSION`]
+    pub fn from_receipt(receipt: &str) -> Result<Self, NixInstallerError> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(receipt).map_err(NixInstallerError::ParsingReceipt)?;
+        let schema_version = migrate(&mut value)?;
+        if schema_version != RECEIPT_SCHEMA_VERSION {
+            tracing::debug!(
+                from = schema_version,
+                to = RECEIPT_SCHEMA_VERSION,
+                "Migrated receipt"
+            );
+        }
+        serde_json::from_value(value).map_err(NixInstallerError::ParsingReceipt)
+    }
+
     pub async fn pre_uninstall_check(&self) -> Result<(), NixInstallerError> {
         self.planner.platform_check().await?;
         self.planner.pre_uninstall_check().await?;
@@ -249,6 +273,45 @@ impl InstallPlan {
         Ok(())
     }
 
+    /// [`install`](InstallPlan::install), and if that fails, immediately [`uninstall`](InstallPlan::uninstall) whatever it did, in reverse order
+    ///
+    /// A failed install is reported as [`NixInstallerError::InstallRolledBack`], which holds both the install error and any error while rolling back.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn install_or_rollback(
+        &mut self,
+        cancel_channel: impl Into<Option<Receiver<()>>>,
+    ) -> Result<(), NixInstallerError> {
+        let install = match self.install(cancel_channel).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        // Nothing to roll back if the install was refused before any step ran
+        if self
+            .actions
+            .iter()
+            .all(|action| action.state == ActionState::Uncompleted)
+        {
+            return Err(install);
+        }
+
+        tracing::warn!("Install failed, rolling back");
+        let rollback = match self.uninstall(None).await {
+            Ok(()) => None,
+            Err(err) => {
+                // Record what is left, so it can be removed with `nix-installer uninstall`
+                if let Err(err) = write_receipt(self.clone()).await {
+                    tracing::error!("Error saving receipt: {:?}", err);
+                }
+                Some(Box::new(err))
+            },
+        };
+        Err(NixInstallerError::InstallRolledBack {
+            install: Box::new(install),
+            rollback,
+        })
+    }
+
     #[tracing::instrument(level = "debug", skip_all)]
     pub async fn describe_uninstall(&self, explain: bool) -> Result<String, NixInstallerError> {
         let Self {
@@ -400,27 +463,104 @@ impl InstallPlan {
         }
     }
 
+    /// Restore the effects of completed actions which have since disappeared, for example after an OS update reset `/etc`
+    ///
+    /// Returns a description of each effect which was restored, the receipt is rewritten if anything was.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn repair(&mut self) -> Result<Vec<String>, NixInstallerError> {
+        self.check_compatible()?;
+        self.planner.platform_check().await?;
+
+        let Self { actions, .. } = self;
+        let mut restored = vec![];
+
+        // This is **deliberately sequential**, later actions may depend on what earlier ones restore
+        for action in actions {
+            tracing::debug!("Checking: {}", action.tracing_synopsis());
+            match action.try_repair().await {
+                Ok(mut action_restored) => restored.append(&mut action_restored),
+                Err(err) => {
+                    if let Err(err) = write_receipt(self.clone()).await {
+                        tracing::error!("Error saving receipt: {:?}", err);
+                    }
+                    return Err(NixInstallerError::Action(err));
+                },
+            }
+        }
+
+        if !restored.is_empty() {
+            write_receipt(self.clone()).await?;
+        }
+
+        Ok(restored)
+    }
+
+    /// Check whether the effects of each completed action are still present, without changing anything
+    ///
+    /// An action which errors while being checked is reported as [`VerificationStatus::Fail`] rather than stopping the others being checked.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn verify(&self) -> Result<Vec<ActionVerification>, NixInstallerError> {
+        self.check_compatible()?;
+
+        let mut verifications = vec![];
+        for action in self.actions.iter() {
+            let (status, details) = match action.state {
+                ActionState::Skipped => continue,
+                ActionState::Uncompleted | ActionState::Progress => (
+                    VerificationStatus::Drift,
+                    vec!["The install never completed this step".to_string()],
+                ),
+                ActionState::Completed => match action.try_verify().await {
+                    Ok(Verification::Pass) => (VerificationStatus::Pass, vec![]),
+                    Ok(Verification::Drift(drift)) => (VerificationStatus::Drift, drift),
+                    Ok(Verification::Unverified) => (VerificationStatus::Unverified, vec![]),
+                    Err(err) => {
+                        let mut details = vec![];
+                        let mut source: Option<&dyn std::error::Error> = Some(&err);
+                        while let Some(err) = source {
+                            details.push(err.to_string());
+                            source = err.source();
+                        }
+                        (VerificationStatus::Fail, details)
+                    },
+                },
+            };
+            verifications.push(ActionVerification {
+                action: action.inner_typetag_name().to_string(),
+                description: action.tracing_synopsis(),
+                status,
+                details,
+            });
+        }
+
+        Ok(verifications)
+    }
+
+    /// Whether this `nix-installer` understands the plan's schema, plans from [`from_receipt`](InstallPlan::from_receipt) always are
     pub fn check_compatible(&self) -> Result<(), NixInstallerError> {
-        let self_version_string = self.version.to_string();
-        let req = VersionReq::parse(&self_version_string)
-            .map_err(|e| NixInstallerError::InvalidVersionRequirement(self_version_string, e))?;
-        let nix_installer_version = current_version()?;
-        if req.matches(&nix_installer_version) {
-            Ok(())
-        } else {
-            Err(NixInstallerError::IncompatibleVersion {
-                binary: nix_installer_version,
-                plan: self.version.clone(),
-            })
+        if self.schema_version > RECEIPT_SCHEMA_VERSION {
+            return Err(NixInstallerError::IncompatibleSchema {
+                binary: RECEIPT_SCHEMA_VERSION,
+                plan: self.schema_version,
+            });
         }
+        Ok(())
     }
 }
 
+/// The `--root` the plan installs into, if any
+fn plan_root(plan: &InstallPlan) -> Option<PathBuf> {
+    let settings = plan.planner.settings().ok()?;
+    settings.get("root")?.as_str().map(PathBuf::from)
+}
+
 async fn write_receipt(plan: InstallPlan) -> Result<(), NixInstallerError> {
-    tokio::fs::create_dir_all("/nix")
+    let root = plan_root(&plan);
+    let nix_dir = rooted(root.as_deref(), "/nix");
+    tokio::fs::create_dir_all(&nix_dir)
         .await
-        .map_err(|e| NixInstallerError::RecordingReceipt(PathBuf::from("/nix"), e))?;
-    let install_receipt_path = PathBuf::from(RECEIPT_LOCATION);
+        .map_err(|e| NixInstallerError::RecordingReceipt(nix_dir, e))?;
+    let install_receipt_path = rooted(root.as_deref(), RECEIPT_LOCATION);
     let self_json =
         serde_json::to_string_pretty(&plan).map_err(NixInstallerError::SerializingReceipt)?;
     tokio::fs::write(&install_receipt_path, format!("{self_json}\n"))
@@ -429,6 +569,43 @@ async fn write_receipt(plan: InstallPlan) -> Result<(), NixInstallerError> {
     Result::<(), NixInstallerError>::Ok(())
 }
 
+/// Whether a completed step of an [`InstallPlan`] still holds, see [`InstallPlan::verify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    /// Every effect of the step is still present
+    Pass,
+    /// Some effects of the step are missing or were changed since
+    Drift,
+    /// The step could not be checked
+    Fail,
+    /// The step cannot tell if its effects are still present
+    Unverified,
+}
+
+impl std::fmt::Display for VerificationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `pad` rather than `write_str`, so the statuses line up in a table
+        f.pad(match self {
+            Self::Pass => "pass",
+            Self::Drift => "drift",
+            Self::Fail => "fail",
+            Self::Unverified => "unverified",
+        })
+    }
+}
+
+/// The result of verifying one step of an [`InstallPlan`]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ActionVerification {
+    /// The `action_name` of the step in the receipt
+    pub action: String,
+    pub description: String,
+    pub status: VerificationStatus,
+    /// What drifted, or the chain of errors which failed the check
+    pub details: Vec<String>,
+}
+
 pub fn current_version() -> Result<Version, NixInstallerError> {
     let nix_installer_version_str = env!("CARGO_PKG_VERSION");
     Version::from_str(nix_installer_version_str).map_err(|e| {
@@ -438,9 +615,19 @@ pub fn current_version() -> Result<Version, NixInstallerError> {
 
 #[cfg(test)]
 mod test {
+    use std::sync::{Arc, Mutex};
+
     use semver::Version;
+    use tracing::{span, Span};
 
-    use crate::{planner::BuiltinPlanner, InstallPlan, NixInstallerError};
+    use crate::{
+        action::{
+            Action, ActionDescription, ActionError, ActionErrorKind, ActionState, ActionTag,
+            StatefulAction,
+        },
+        planner::{linux::Linux, BuiltinPlanner, Planner},
+        InstallPlan, NixInstallerError, RECEIPT_SCHEMA_VERSION,
+    };
 
     #[tokio::test]
     async fn ensure_version_allows_compatible() -> Result<(), NixInstallerError> {
@@ -451,8 +638,23 @@ mod test {
             "version": good_version,
             "actions": [],
         });
-        let maybe_plan: InstallPlan = serde_json::from_value(value)?;
+        let maybe_plan = InstallPlan::from_receipt(&value.to_string())?;
+        maybe_plan.check_compatible()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ensure_version_allows_older() -> Result<(), NixInstallerError> {
+        let planner = BuiltinPlanner::default().await?;
+        let old_version = Version::parse("0.1.0")?;
+        let value = serde_json::json!({
+            "planner": planner.boxed(),
+            "version": old_version,
+            "actions": [],
+        });
+        let maybe_plan = InstallPlan::from_receipt(&value.to_string())?;
         maybe_plan.check_compatible()?;
+        assert_eq!(maybe_plan.schema_version, RECEIPT_SCHEMA_VERSION);
         Ok(())
     }
 
@@ -463,10 +665,178 @@ mod test {
         let value = serde_json::json!({
             "planner": planner.boxed(),
             "version": bad_version,
+            "schema_version": RECEIPT_SCHEMA_VERSION + 1,
             "actions": [],
         });
+        assert!(InstallPlan::from_receipt(&value.to_string()).is_err());
         let maybe_plan: InstallPlan = serde_json::from_value(value)?;
         assert!(maybe_plan.check_compatible().is_err());
         Ok(())
     }
+
+    /// A step which records what it did in `log`, and fails if told to
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    struct Step {
+        name: String,
+        fail_execute: bool,
+        fail_revert: bool,
+        #[serde(skip)]
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    #[typetag::serde(name = "test_step")]
+    impl Action for Step {
+        fn action_tag() -> ActionTag {
+            "test_step".into()
+        }
+        fn tracing_synopsis(&self) -> String {
+            format!("Step `{}`", self.name)
+        }
+        fn tracing_span(&self) -> Span {
+            span!(tracing::Level::DEBUG, "test_step", name = self.name)
+        }
+        fn execute_description(&self) -> Vec<ActionDescription> {
+            vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+        }
+        async fn execute(&mut self) -> Result<(), ActionError> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("execute {}", self.name));
+            if self.fail_execute {
+                return Err(Self::error(ActionErrorKind::Custom(
+                    "execute failed".into(),
+                )));
+            }
+            Ok(())
+        }
+        fn revert_description(&self) -> Vec<ActionDescription> {
+            vec![ActionDescription::new(self.tracing_synopsis(), vec![])]
+        }
+        async fn revert(&mut self) -> Result<(), ActionError> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("revert {}", self.name));
+            if self.fail_revert {
+                return Err(Self::error(ActionErrorKind::Custom("revert failed".into())));
+            }
+            Ok(())
+        }
+    }
+
+    /// A plan installing into `root` which runs `a`, `b` and then `c`, which fails
+    async fn failing_plan(
+        root: &std::path::Path,
+        fail_revert_b: bool,
+    ) -> Result<(InstallPlan, Arc<Mutex<Vec<String>>>), NixInstallerError> {
+        let mut planner = Linux::default().await?;
+        planner.settings.root = Some(root.to_path_buf());
+        let log = Arc::new(Mutex::new(vec![]));
+        let step = |name: &str, fail_execute, fail_revert| {
+            StatefulAction::uncompleted(Step {
+                name: name.to_string(),
+                fail_execute,
+                fail_revert,
+                log: log.clone(),
+            })
+            .boxed()
+        };
+        let plan = InstallPlan {
+            version: super::current_version()?,
+            schema_version: RECEIPT_SCHEMA_VERSION,
+            actions: vec![
+                step("a", false, false),
+                step("b", false, fail_revert_b),
+                step("c", true, false),
+            ],
+            planner: planner.boxed(),
+            #[cfg(feature = "diagnostics")]
+            diagnostic_data: None,
+        };
+        Ok((plan, log))
+    }
+
+    #[tokio::test]
+    async fn rolls_back_in_reverse_order() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+        let (mut plan, log) = failing_plan(root.path(), false).await?;
+
+        let err = plan.install_or_rollback(None).await.unwrap_err();
+
+        assert!(
+            matches!(
+                &err,
+                NixInstallerError::InstallRolledBack { install, rollback: None }
+                    if matches!(**install, NixInstallerError::Action(_))
+            ),
+            "{err:?}"
+        );
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "execute a",
+                "execute b",
+                "execute c",
+                "revert c",
+                "revert b",
+                "revert a"
+            ]
+        );
+        assert!(plan
+            .actions
+            .iter()
+            .all(|action| action.state == ActionState::Uncompleted));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn records_what_a_failed_rollback_left() -> eyre::Result<()> {
+        let root = tempfile::tempdir()?;
+        let (mut plan, log) = failing_plan(root.path(), true).await?;
+
+        let err = plan.install_or_rollback(None).await.unwrap_err();
+
+        assert!(
+            matches!(
+                &err,
+                NixInstallerError::InstallRolledBack { install, rollback: Some(rollback) }
+                    if matches!(**install, NixInstallerError::Action(_))
+                        && matches!(**rollback, NixInstallerError::ActionRevert(ref errs) if errs.len() == 1)
+            ),
+            "{err:?}"
+        );
+        // A failed revert doesn't stop the rest of the rollback
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "execute a",
+                "execute b",
+                "execute c",
+                "revert c",
+                "revert b",
+                "revert a"
+            ]
+        );
+
+        let receipt = tokio::fs::read_to_string(root.path().join("nix/receipt.json")).await?;
+        let receipt = InstallPlan::from_receipt(&receipt)?;
+        let states = receipt
+            .actions
+            .iter()
+            .map(|action| action.state)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            [
+                ActionState::Uncompleted,
+                ActionState::Progress,
+                ActionState::Uncompleted
+            ]
+        );
+
+        Ok(())
+    }
 }
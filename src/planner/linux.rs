@@ -8,8 +8,9 @@ use crate::{
     action::{
         base::{CreateDirectory, RemoveDirectory},
         common::{
-            ConfigureDeterminateNixdInitService, ConfigureNix, ConfigureUpstreamInitService,
-            CreateUsersAndGroups, ProvisionDeterminateNixd, ProvisionNix,
+            ConfigureDeterminateNixdInitService, ConfigureGarbageCollection, ConfigureNix,
+            ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionDeterminateNixd,
+            ProvisionNix,
         },
         linux::ProvisionSelinux,
         StatefulAction,
@@ -17,7 +18,10 @@ use crate::{
     error::HasExpectedErrors,
     planner::{Planner, PlannerError},
     settings::CommonSettings,
-    settings::{determinate_nix_settings, InitSettings, InitSystem, InstallSettingsError},
+    settings::{
+        determinate_nix_settings, rooted, InitSettings, InitSystem, InstallSettingsError,
+        SCRATCH_DIR,
+    },
     Action, BuiltinPlanner,
 };
 
@@ -42,12 +46,20 @@ impl Planner for Linux {
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
-        let has_selinux = detect_selinux().await?;
+        let root = self.settings.root.as_deref();
+        if root.is_some() && self.settings.determinate_nix {
+            return Err(PlannerError::RootUnsupported("Determinate Nix"));
+        }
+        // Nothing can be started in a root which is not running
+        let start_daemon = self.init.start_daemon && root.is_none();
+
+        // The host's SELinux policy says nothing about the root's
+        let has_selinux = root.is_none() && detect_selinux().await?;
 
         let mut plan = vec![];
 
         plan.push(
-            CreateDirectory::plan("/nix", None, None, 0o0755, true)
+            CreateDirectory::plan(rooted(root, "/nix"), None, None, 0o0755, true)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -95,7 +107,7 @@ impl Planner for Linux {
         }
 
         plan.push(
-            CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
+            CreateDirectory::plan(rooted(root, "/etc/tmpfiles.d"), None, None, 0o0755, false)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -110,14 +122,28 @@ impl Planner for Linux {
             );
         } else {
             plan.push(
-                ConfigureUpstreamInitService::plan(self.init.init, self.init.start_daemon)
+                ConfigureUpstreamInitService::plan(self.init.init, start_daemon, root)
                     .await
                     .map_err(PlannerError::Action)?
                     .boxed(),
             );
         }
+        if let Some(gc_schedule) = self.init.gc_schedule {
+            plan.push(
+                ConfigureGarbageCollection::plan(
+                    self.init.init,
+                    gc_schedule,
+                    self.init.gc_older_than.clone(),
+                    start_daemon,
+                    root,
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        }
         plan.push(
-            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+            RemoveDirectory::plan(rooted(root, SCRATCH_DIR))
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -178,6 +204,10 @@ impl Planner for Linux {
     }
 
     async fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
+        if let Some(root) = &self.settings.root {
+            return check_root_is_directory(root);
+        }
+
         check_not_wsl1()?;
 
         if self.init.init == InitSystem::Systemd && self.init.start_daemon {
@@ -188,6 +218,11 @@ impl Planner for Linux {
     }
 
     async fn pre_install_check(&self) -> Result<(), PlannerError> {
+        // The host is not being installed onto, so none of its state matters
+        if let Some(root) = &self.settings.root {
+            return check_root_is_directory(root);
+        }
+
         check_not_nixos()?;
 
         check_nix_not_already_installed().await?;
@@ -217,6 +252,13 @@ pub(crate) fn check_not_nixos() -> Result<(), PlannerError> {
     Ok(())
 }
 
+pub(crate) fn check_root_is_directory(root: &Path) -> Result<(), PlannerError> {
+    if !root.is_dir() {
+        return Err(PlannerError::RootNotDirectory(root.to_path_buf()));
+    }
+    Ok(())
+}
+
 pub(crate) fn check_not_wsl1() -> Result<(), PlannerError> {
     // Detection strategies: https://patrickwu.space/wslconf/
     if std::env::var("WSL_DISTRO_NAME").is_ok() && std::env::var("WSL_INTEROP").is_err() {
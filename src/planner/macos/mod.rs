@@ -102,6 +102,10 @@ impl Planner for Macos {
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        if self.settings.root.is_some() {
+            return Err(PlannerError::RootUnsupported("The `macos` planner"));
+        }
+
         let root_disk = match &self.root_disk {
             root_disk @ Some(_) => root_disk.clone(),
             None => {
@@ -234,7 +238,7 @@ impl Planner for Macos {
             );
         } else {
             plan.push(
-                ConfigureUpstreamInitService::plan(InitSystem::Launchd, true)
+                ConfigureUpstreamInitService::plan(InitSystem::Launchd, true, None)
                     .await
                     .map_err(PlannerError::Action)?
                     .boxed(),
@@ -38,7 +38,7 @@ impl Planner for MyPlanner {
         Ok(vec![
             // ...
 
-                CreateFile::plan("/example", None, None, None, "Example".to_string(), false)
+                CreateFile::plan("/example", None, None, None, "Example".to_string(), false, "/nix/.installer-backups")
                     .await
                     .map_err(PlannerError::Action)?.boxed(),
         ])
@@ -118,15 +118,20 @@ pub mod linux;
 pub mod macos;
 pub mod ostree;
 pub mod steam_deck;
+pub mod synology;
 
-use std::{collections::HashMap, path::PathBuf, string::FromUtf8Error};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     action::{ActionError, StatefulAction},
     error::HasExpectedErrors,
-    settings::{CommonSettings, InstallSettingsError},
+    settings::{rooted, CommonSettings, InstallSettingsError},
     Action, InstallPlan, NixInstallerError,
 };
 
@@ -181,6 +186,9 @@ pub enum BuiltinPlanner {
     /// A planner for the Valve Steam Deck running SteamOS
     SteamDeck(steam_deck::SteamDeck),
     #[cfg_attr(not(target_os = "linux"), clap(hide = true))]
+    /// A planner for Synology NAS devices running DiskStation Manager (DSM)
+    Synology(synology::Synology),
+    #[cfg_attr(not(target_os = "linux"), clap(hide = true))]
     /// A planner suitable for immutable systems using ostree, such as Fedora Silverblue
     Ostree(ostree::Ostree),
     #[cfg_attr(not(target_os = "macos"), clap(hide = true))]
@@ -191,15 +199,28 @@ pub enum BuiltinPlanner {
 impl BuiltinPlanner {
     /// Heuristically determine the default planner for the target system
     pub async fn default() -> Result<Self, PlannerError> {
-        use target_lexicon::{Architecture, OperatingSystem};
+        use target_lexicon::{Architecture, ArmArchitecture, OperatingSystem};
         match (Architecture::host(), OperatingSystem::host()) {
             (Architecture::X86_64, OperatingSystem::Linux) => Self::detect_linux_distro().await,
+            (Architecture::X86_32(_), OperatingSystem::Linux)
+            | (Architecture::Aarch64(_), OperatingSystem::Linux)
+            | (
+                Architecture::Arm(ArmArchitecture::Armv7 | ArmArchitecture::Armv7a),
+                OperatingSystem::Linux,
+            ) if synology::is_synology() => {
+                Ok(Self::Synology(synology::Synology::default().await?))
+            },
             (Architecture::X86_32(_), OperatingSystem::Linux) => {
                 Ok(Self::Linux(linux::Linux::default().await?))
             },
             (Architecture::Aarch64(_), OperatingSystem::Linux) => {
                 Ok(Self::Linux(linux::Linux::default().await?))
             },
+            // Nix calls this `armv7l-linux`, the embedded tarball must have been built for it
+            (
+                Architecture::Arm(ArmArchitecture::Armv7 | ArmArchitecture::Armv7a),
+                OperatingSystem::Linux,
+            ) => Ok(Self::Linux(linux::Linux::default().await?)),
             (Architecture::X86_64, OperatingSystem::MacOSX { .. })
             | (Architecture::X86_64, OperatingSystem::Darwin) => {
                 Ok(Self::Macos(macos::Macos::default().await?))
@@ -213,6 +234,10 @@ impl BuiltinPlanner {
     }
 
     async fn detect_linux_distro() -> Result<Self, PlannerError> {
+        if synology::is_synology() {
+            return Ok(Self::Synology(synology::Synology::default().await?));
+        }
+
         let is_steam_deck =
             os_release::OsRelease::new().is_ok_and(|os_release| os_release.id == "steamos");
         if is_steam_deck {
@@ -236,6 +261,7 @@ impl BuiltinPlanner {
         match &mut built {
             BuiltinPlanner::Linux(inner) => inner.settings = settings,
             BuiltinPlanner::SteamDeck(inner) => inner.settings = settings,
+            BuiltinPlanner::Synology(inner) => inner.settings = settings,
             BuiltinPlanner::Ostree(inner) => inner.settings = settings,
             BuiltinPlanner::Macos(inner) => inner.settings = settings,
         }
@@ -248,6 +274,7 @@ impl BuiltinPlanner {
         match self {
             BuiltinPlanner::Linux(inner) => inner.configured_settings().await,
             BuiltinPlanner::SteamDeck(inner) => inner.configured_settings().await,
+            BuiltinPlanner::Synology(inner) => inner.configured_settings().await,
             BuiltinPlanner::Ostree(inner) => inner.configured_settings().await,
             BuiltinPlanner::Macos(inner) => inner.configured_settings().await,
         }
@@ -257,6 +284,7 @@ impl BuiltinPlanner {
         match self {
             BuiltinPlanner::Linux(planner) => InstallPlan::plan(planner).await,
             BuiltinPlanner::SteamDeck(planner) => InstallPlan::plan(planner).await,
+            BuiltinPlanner::Synology(planner) => InstallPlan::plan(planner).await,
             BuiltinPlanner::Ostree(planner) => InstallPlan::plan(planner).await,
             BuiltinPlanner::Macos(planner) => InstallPlan::plan(planner).await,
         }
@@ -265,6 +293,7 @@ impl BuiltinPlanner {
         match self {
             BuiltinPlanner::Linux(i) => i.boxed(),
             BuiltinPlanner::SteamDeck(i) => i.boxed(),
+            BuiltinPlanner::Synology(i) => i.boxed(),
             BuiltinPlanner::Ostree(i) => i.boxed(),
             BuiltinPlanner::Macos(i) => i.boxed(),
         }
@@ -274,6 +303,7 @@ impl BuiltinPlanner {
         match self {
             BuiltinPlanner::Linux(i) => i.typetag_name(),
             BuiltinPlanner::SteamDeck(i) => i.typetag_name(),
+            BuiltinPlanner::Synology(i) => i.typetag_name(),
             BuiltinPlanner::Ostree(i) => i.typetag_name(),
             BuiltinPlanner::Macos(i) => i.typetag_name(),
         }
@@ -283,6 +313,7 @@ impl BuiltinPlanner {
         match self {
             BuiltinPlanner::Linux(i) => i.settings(),
             BuiltinPlanner::SteamDeck(i) => i.settings(),
+            BuiltinPlanner::Synology(i) => i.settings(),
             BuiltinPlanner::Ostree(i) => i.settings(),
             BuiltinPlanner::Macos(i) => i.settings(),
         }
@@ -295,6 +326,7 @@ impl BuiltinPlanner {
         match self {
             BuiltinPlanner::Linux(i) => i.diagnostic_data().await,
             BuiltinPlanner::SteamDeck(i) => i.diagnostic_data().await,
+            BuiltinPlanner::Synology(i) => i.diagnostic_data().await,
             BuiltinPlanner::Ostree(i) => i.diagnostic_data().await,
             BuiltinPlanner::Macos(i) => i.diagnostic_data().await,
         }
@@ -326,6 +358,28 @@ impl Default for ShellProfileLocations {
     }
 }
 
+impl ShellProfileLocations {
+    /// The same locations inside `root`
+    pub fn rooted(self, root: &Path) -> Self {
+        let Self { fish, bash, zsh } = self;
+        let root_all = |paths: Vec<PathBuf>| {
+            paths
+                .into_iter()
+                .map(|path| rooted(Some(root), path))
+                .collect()
+        };
+        Self {
+            fish: FishShellProfileLocations {
+                confd_prefixes: root_all(fish.confd_prefixes),
+                vendor_confd_prefixes: root_all(fish.vendor_confd_prefixes),
+                ..fish
+            },
+            bash: root_all(bash),
+            zsh: root_all(zsh),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct FishShellProfileLocations {
     pub confd_suffix: PathBuf,
@@ -412,6 +466,11 @@ pub enum PlannerError {
     NixExists,
     #[error("WSL1 is not supported, please upgrade to WSL2: https://learn.microsoft.com/en-us/windows/wsl/install#upgrade-version-from-wsl-1-to-wsl-2")]
     Wsl1,
+    /// Only the `linux` planner can install into an alternate root
+    #[error("{0} does not support installing into an alternate root with `--root`")]
+    RootUnsupported(&'static str),
+    #[error("`{0}` is not a directory, `--root` must be where the filesystem to install into is mounted")]
+    RootNotDirectory(PathBuf),
     /// Failed to execute command
     #[error("Failed to execute command `{0}`")]
     Command(String, #[source] std::io::Error),
@@ -439,6 +498,10 @@ impl HasExpectedErrors for PlannerError {
                 if let Some(err) = _e.downcast_ref::<linux::LinuxErrorKind>() {
                     return err.expected();
                 }
+                #[cfg(target_os = "linux")]
+                if let Some(err) = _e.downcast_ref::<synology::SynologyError>() {
+                    return err.expected();
+                }
                 #[cfg(target_os = "macos")]
                 if let Some(err) = _e.downcast_ref::<macos::MacosError>() {
                     return err.expected();
@@ -448,6 +511,8 @@ impl HasExpectedErrors for PlannerError {
             this @ PlannerError::NixOs => Some(Box::new(this)),
             this @ PlannerError::NixExists => Some(Box::new(this)),
             this @ PlannerError::Wsl1 => Some(Box::new(this)),
+            this @ PlannerError::RootUnsupported(_) => Some(Box::new(this)),
+            this @ PlannerError::RootNotDirectory(_) => Some(Box::new(this)),
             PlannerError::Command(_, _) => None,
             #[cfg(feature = "diagnostics")]
             PlannerError::Diagnostic(diagnostic_error) => Some(Box::new(diagnostic_error)),
@@ -1,6 +1,6 @@
 use crate::{
     action::{
-        base::{CreateDirectory, CreateFile, RemoveDirectory},
+        base::{CreateBtrfsSubvolume, CreateDirectory, CreateFile, RemoveDirectory},
         common::{
             ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups,
             ProvisionDeterminateNixd, ProvisionNix,
@@ -46,6 +46,10 @@ impl Planner for Ostree {
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        if self.settings.root.is_some() {
+            return Err(PlannerError::RootUnsupported("The `ostree` planner"));
+        }
+
         let has_selinux = detect_selinux().await?;
         let mut plan = vec![
             // Primarily for uninstall
@@ -56,7 +60,7 @@ impl Planner for Ostree {
         ];
 
         plan.push(
-            CreateDirectory::plan(&self.persistence, None, None, 0o0755, true)
+            CreateBtrfsSubvolume::plan(&self.persistence, 0o0755, None, true)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
@@ -83,6 +87,7 @@ impl Planner for Ostree {
             0o0644,
             nix_directory_buf,
             false,
+            crate::action::base::file_backup::BACKUP_DIR,
         )
         .await
         .map_err(PlannerError::Action)?;
@@ -119,6 +124,7 @@ impl Planner for Ostree {
             0o0644,
             create_bind_mount_buf,
             false,
+            crate::action::base::file_backup::BACKUP_DIR,
         )
         .await
         .map_err(PlannerError::Action)?;
@@ -148,6 +154,7 @@ impl Planner for Ostree {
             0o0644,
             ensure_symlinked_units_resolve_buf,
             false,
+            crate::action::base::file_backup::BACKUP_DIR,
         )
         .await
         .map_err(PlannerError::Action)?;
@@ -223,7 +230,7 @@ impl Planner for Ostree {
         );
 
         plan.push(
-            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true)
+            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true, None)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
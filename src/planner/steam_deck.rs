@@ -102,7 +102,7 @@ use tokio::process::Command;
 
 use crate::{
     action::{
-        base::{CreateDirectory, CreateFile, RemoveDirectory},
+        base::{CreateBtrfsSubvolume, CreateFile, RemoveDirectory},
         common::{
             ConfigureNix, ConfigureUpstreamInitService, CreateUsersAndGroups,
             ProvisionDeterminateNixd, ProvisionNix,
@@ -149,6 +149,10 @@ impl Planner for SteamDeck {
     }
 
     async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        if self.settings.root.is_some() {
+            return Err(PlannerError::RootUnsupported("The `steam-deck` planner"));
+        }
+
         // Starting in roughly build ID `20230522.1000`, the Steam Deck has a `/home/.steamos/offload/nix` directory and `nix.mount` unit we can use instead of creating a mountpoint.
         let requires_nix_bind_mount = detect_requires_bind_mount().await?;
 
@@ -177,7 +181,7 @@ impl Planner for SteamDeck {
                 )));
             };
             actions.push(
-                CreateDirectory::plan(&persistence, None, None, 0o0755, true)
+                CreateBtrfsSubvolume::plan(persistence, 0o0755, None, true)
                     .await
                     .map_err(PlannerError::Action)?
                     .boxed(),
@@ -213,6 +217,7 @@ impl Planner for SteamDeck {
                 0o0644,
                 nix_directory_buf,
                 false,
+                crate::action::base::file_backup::BACKUP_DIR,
             )
             .await
             .map_err(PlannerError::Action)?;
@@ -249,6 +254,7 @@ impl Planner for SteamDeck {
                 0o0644,
                 create_bind_mount_buf,
                 false,
+                crate::action::base::file_backup::BACKUP_DIR,
             )
             .await
             .map_err(PlannerError::Action)?;
@@ -294,6 +300,7 @@ impl Planner for SteamDeck {
             0o0644,
             ensure_symlinked_units_resolve_buf,
             false,
+            crate::action::base::file_backup::BACKUP_DIR,
         )
         .await
         .map_err(PlannerError::Action)?;
@@ -349,7 +356,7 @@ impl Planner for SteamDeck {
             .map_err(PlannerError::Action)?
             .boxed(),
             // Init is required for the steam-deck archetype to make the `/nix` mount
-            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true)
+            ConfigureUpstreamInitService::plan(InitSystem::Systemd, true, None)
                 .await
                 .map_err(PlannerError::Action)?
                 .boxed(),
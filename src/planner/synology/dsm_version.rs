@@ -0,0 +1,149 @@
+/*! Detection of the DiskStation Manager (DSM) release the host is running */
+use std::path::PathBuf;
+
+use super::SynologyError;
+
+/// The file DSM describes its release in, `/etc/VERSION` is a copy which DSM updates may not refresh
+pub const DSM_VERSION_FILE: &str = "/etc.defaults/VERSION";
+
+/// The DSM major versions this installer has been tested on, DSM 6 with upstart and DSM 7 with systemd
+pub const TESTED_DSM_MAJORS: &[u32] = &[6, 7];
+
+/// A DSM release, as found in `/etc.defaults/VERSION`
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize,
+)]
+pub struct DsmVersion {
+    pub major: u32,
+    pub minor: u32,
+    /// The third part of the release, such as the `1` of DSM 7.2.1
+    #[serde(default)]
+    pub micro: u32,
+    pub build: u32,
+    /// The "Update N" applied on top of the build
+    pub smallfix: u32,
+}
+
+impl DsmVersion {
+    /// Read the DSM release of the host
+    pub async fn detect() -> Result<Self, SynologyError> {
+        let contents = tokio::fs::read_to_string(DSM_VERSION_FILE)
+            .await
+            .map_err(|e| SynologyError::ReadDsmVersion(PathBuf::from(DSM_VERSION_FILE), e))?;
+        Self::parse(&contents)
+    }
+
+    /// Parse the `key="value"` lines of `/etc.defaults/VERSION`
+    pub fn parse(contents: &str) -> Result<Self, SynologyError> {
+        let field = |key: &'static str| -> Result<Option<u32>, SynologyError> {
+            let Some(value) = contents.lines().find_map(|line| {
+                let (found_key, value) = line.split_once('=')?;
+                (found_key.trim() == key).then(|| value.trim().trim_matches('"'))
+            }) else {
+                return Ok(None);
+            };
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| SynologyError::ParseDsmVersion(key, value.to_string()))
+        };
+        let required = |key: &'static str| {
+            field(key)?.ok_or(SynologyError::ParseDsmVersion(key, String::new()))
+        };
+
+        Ok(Self {
+            major: required("majorversion")?,
+            minor: required("minorversion")?,
+            // Older releases do not record it
+            micro: field("micro")?.unwrap_or(0),
+            build: required("buildnumber")?,
+            // Only present once an update has been applied
+            smallfix: field("smallfixnumber")?.unwrap_or(0),
+        })
+    }
+
+    /// Whether this installer has been tested on this DSM major version
+    pub fn is_tested(&self) -> bool {
+        TESTED_DSM_MAJORS.contains(&self.major)
+    }
+
+    /// DSM switched from upstart to systemd in DSM 7
+    pub fn has_systemd(&self) -> bool {
+        self.major >= 7
+    }
+}
+
+impl std::fmt::Display for DsmVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.micro != 0 {
+            write!(f, ".{}", self.micro)?;
+        }
+        write!(f, "-{}", self.build)?;
+        if self.smallfix != 0 {
+            write!(f, " Update {}", self.smallfix)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DsmVersion;
+
+    #[test]
+    fn parses_dsm_version() -> eyre::Result<()> {
+        let version = DsmVersion::parse(
+            "\
+            majorversion=\"7\"\n\
+            minorversion=\"2\"\n\
+            major=\"7\"\n\
+            minor=\"2\"\n\
+            micro=\"1\"\n\
+            buildphase=\"GM\"\n\
+            buildnumber=\"69057\"\n\
+            smallfixnumber=\"5\"\n\
+            nano=\"5\"\n\
+            base=\"69057\"\n\
+            productversion=\"7.2.1\"\n\
+            os_name=\"DSM\"\n\
+            ",
+        )?;
+
+        assert_eq!(
+            version,
+            DsmVersion {
+                major: 7,
+                minor: 2,
+                micro: 1,
+                build: 69057,
+                smallfix: 5,
+            }
+        );
+        assert_eq!(version.to_string(), "7.2.1-69057 Update 5");
+        assert!(version.is_tested());
+        assert!(version.has_systemd());
+        Ok(())
+    }
+
+    #[test]
+    fn dsm_6_is_tested_without_systemd() -> eyre::Result<()> {
+        let version =
+            DsmVersion::parse("majorversion=\"6\"\nminorversion=\"2\"\nbuildnumber=\"25556\"\n")?;
+
+        assert_eq!(version.to_string(), "6.2-25556");
+        assert!(version.is_tested());
+        assert!(!version.has_systemd());
+        assert!(!DsmVersion {
+            major: 5,
+            ..version
+        }
+        .is_tested());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_incomplete_dsm_version() {
+        assert!(DsmVersion::parse("majorversion=\"6\"\nminorversion=\"2\"\n").is_err());
+    }
+}
@@ -0,0 +1,476 @@
+/*! A planner for Synology NAS devices running DiskStation Manager (DSM)
+
+DSM is a Linux distribution, but it differs from a traditional Linux in a few ways which matter to us:
+
+* It ships an older kernel (4.4 as of DSM 7.2) on which Nix's seccomp syscall filtering misbehaves.
+* Users and groups are managed with `synouser` and `synogroup`, not `useradd` and `groupadd`.
+* The root volume is small, so `/nix` is bind mounted from a data volume, and much of `/etc` is reset by DSM at boot and on updates.
+*/
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    linux::{check_nix_not_already_installed, check_systemd_active},
+    ShellProfileLocations,
+};
+use crate::{
+    action::{
+        base::{CreateDirectory, RemoveDirectory},
+        common::{
+            ConfigureDeterminateNixdInitService, ConfigureGarbageCollection, ConfigureNix,
+            ConfigureUpstreamInitService, CreateUsersAndGroups, ProvisionDeterminateNixd,
+            ProvisionNix,
+        },
+        linux::CreateSynologyNixBindMount,
+        StatefulAction,
+    },
+    error::HasExpectedErrors,
+    planner::{Planner, PlannerError},
+    settings::{
+        determinate_nix_settings, CommonSettings, InitSettings, InitSystem, InstallSettingsError,
+    },
+    Action, BuiltinPlanner,
+};
+
+#[cfg(feature = "cli")]
+use clap::ArgAction;
+
+pub mod dsm_version;
+pub mod volumes;
+
+use dsm_version::{DsmVersion, TESTED_DSM_MAJORS};
+
+/// Headroom required on the root filesystem, which only holds configuration files once `/nix` is bind mounted
+pub const MINIMUM_ROOT_FREE_BYTES: u64 = 64 * 1024 * 1024;
+/// Headroom required where `/nix` is bind mounted from, to unpack Nix and leave room for a few store paths
+pub const MINIMUM_PERSISTENCE_FREE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// The file DSM uses to store its system configuration, its presence identifies a Synology device
+pub const SYNOINFO_CONF: &str = "/etc/synoinfo.conf";
+
+/// A planner for Synology NAS devices running DSM
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::Parser))]
+pub struct Synology {
+    /// Where `/nix` will be bind mounted from [default: `nix` on the largest writable data volume]
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_SYNOLOGY_PERSISTENCE",
+            conflicts_with = "volume"
+        )
+    )]
+    pub persistence: Option<PathBuf>,
+    /// The data volume (eg `/volume2`) to hold `/nix`, it will be bind mounted from `nix` on that volume
+    #[cfg_attr(feature = "cli", clap(long, env = "NIX_INSTALLER_SYNOLOGY_VOLUME"))]
+    pub volume: Option<PathBuf>,
+    /// Limit the size of `/nix` with a btrfs qgroup (eg `500G`), if it is created as a btrfs subvolume
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_SYNOLOGY_PERSISTENCE_QUOTA",
+            value_parser = crate::action::base::create_btrfs_subvolume::parse_qgroup_limit
+        )
+    )]
+    #[serde(default)]
+    pub persistence_quota: Option<String>,
+    /// Keep Nix's seccomp syscall filtering enabled, even though the DSM 4.4 kernel does not support it
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            action(ArgAction::SetTrue),
+            default_value = "false",
+            env = "NIX_INSTALLER_SYNOLOGY_FILTER_SYSCALLS"
+        )
+    )]
+    pub filter_syscalls: bool,
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub settings: CommonSettings,
+    #[cfg_attr(feature = "cli", clap(flatten))]
+    pub init: InitSettings,
+}
+
+#[async_trait::async_trait]
+#[typetag::serde(name = "synology")]
+impl Planner for Synology {
+    async fn default() -> Result<Self, PlannerError> {
+        let mut init = InitSettings::default().await?;
+        // DSM 6 and older have no systemd, their boot scripts can still run the daemon when upstart
+        // was not detected either
+        if init.init != InitSystem::Upstart
+            && DsmVersion::detect()
+                .await
+                .is_ok_and(|version| !version.has_systemd())
+        {
+            init.init(InitSystem::SynologyRcD);
+        }
+
+        Ok(Self {
+            persistence: None,
+            volume: None,
+            persistence_quota: None,
+            filter_syscalls: false,
+            settings: CommonSettings::default().await?,
+            init,
+        })
+    }
+
+    async fn plan(&self) -> Result<Vec<StatefulAction<Box<dyn Action>>>, PlannerError> {
+        if self.settings.root.is_some() {
+            return Err(PlannerError::RootUnsupported("The `synology` planner"));
+        }
+
+        let mut plan = vec![];
+
+        let persistence = self.persistence().await?;
+
+        plan.push(
+            CreateDirectory::plan("/nix", None, None, 0o0755, true)
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+        plan.push(
+            CreateSynologyNixBindMount::plan(&persistence, self.persistence_quota.clone())
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+
+        if self.settings.determinate_nix {
+            plan.push(
+                ProvisionDeterminateNixd::plan(self.init.init)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+
+        plan.push(
+            ProvisionNix::plan(&self.settings.clone())
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+        plan.push(
+            CreateUsersAndGroups::plan(self.settings.clone())
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+        plan.push(
+            ConfigureNix::plan(
+                ShellProfileLocations::default(),
+                &self.settings,
+                self.extra_internal_conf(),
+            )
+            .await
+            .map_err(PlannerError::Action)?
+            .boxed(),
+        );
+
+        plan.push(
+            CreateDirectory::plan("/etc/tmpfiles.d", None, None, 0o0755, false)
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+
+        if self.settings.determinate_nix {
+            plan.push(
+                ConfigureDeterminateNixdInitService::plan(self.init.init, self.init.start_daemon)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        } else {
+            plan.push(
+                ConfigureUpstreamInitService::plan(self.init.init, self.init.start_daemon, None)
+                    .await
+                    .map_err(PlannerError::Action)?
+                    .boxed(),
+            );
+        }
+        if let Some(gc_schedule) = self.init.gc_schedule {
+            plan.push(
+                ConfigureGarbageCollection::plan(
+                    self.init.init,
+                    gc_schedule,
+                    self.init.gc_older_than.clone(),
+                    self.init.start_daemon,
+                    None,
+                )
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+            );
+        }
+        plan.push(
+            RemoveDirectory::plan(crate::settings::SCRATCH_DIR)
+                .await
+                .map_err(PlannerError::Action)?
+                .boxed(),
+        );
+
+        Ok(plan)
+    }
+
+    fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
+        let Self {
+            persistence,
+            volume,
+            persistence_quota,
+            filter_syscalls,
+            settings,
+            init,
+        } = self;
+        let mut map = HashMap::default();
+
+        map.extend(settings.settings()?);
+        map.extend(init.settings()?);
+        map.insert(
+            "persistence".to_string(),
+            serde_json::to_value(persistence)?,
+        );
+        map.insert("volume".to_string(), serde_json::to_value(volume)?);
+        map.insert(
+            "persistence_quota".to_string(),
+            serde_json::to_value(persistence_quota)?,
+        );
+        map.insert(
+            "filter_syscalls".to_string(),
+            serde_json::to_value(filter_syscalls)?,
+        );
+
+        Ok(map)
+    }
+
+    async fn configured_settings(
+        &self,
+    ) -> Result<HashMap<String, serde_json::Value>, PlannerError> {
+        let default = Self::default().await?.settings()?;
+        let configured = self.settings()?;
+
+        let mut settings: HashMap<String, serde_json::Value> = HashMap::new();
+        for (key, value) in configured.iter() {
+            if default.get(key) != Some(value) {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+
+        Ok(settings)
+    }
+
+    #[cfg(feature = "diagnostics")]
+    async fn diagnostic_data(&self) -> Result<crate::diagnostics::DiagnosticData, PlannerError> {
+        let diagnostic_data = crate::diagnostics::DiagnosticData::new(
+            self.settings.diagnostic_attribution.clone(),
+            self.settings.diagnostic_endpoint.clone(),
+            self.typetag_name().into(),
+            self.configured_settings()
+                .await?
+                .into_keys()
+                .collect::<Vec<_>>(),
+            self.settings.ssl_cert_file.clone(),
+        )?;
+
+        // `os-release` on DSM does not identify the DSM release
+        Ok(match DsmVersion::detect().await {
+            Ok(version) => diagnostic_data.os("DSM", version.to_string()),
+            Err(_) => diagnostic_data,
+        })
+    }
+
+    async fn platform_check(&self) -> Result<(), PlannerError> {
+        use target_lexicon::OperatingSystem;
+        match target_lexicon::OperatingSystem::host() {
+            OperatingSystem::Linux => (),
+            host_os => {
+                return Err(PlannerError::IncompatibleOperatingSystem {
+                    planner: self.typetag_name(),
+                    host_os,
+                })
+            },
+        }
+
+        if !is_synology() {
+            return Err(SynologyError::NotSynology.into());
+        }
+
+        Ok(())
+    }
+
+    async fn pre_uninstall_check(&self) -> Result<(), PlannerError> {
+        if self.init.init == InitSystem::Systemd && self.init.start_daemon {
+            check_systemd_active()?;
+        }
+
+        Ok(())
+    }
+
+    async fn pre_install_check(&self) -> Result<(), PlannerError> {
+        check_nix_not_already_installed().await?;
+
+        check_free_space(Path::new("/"), MINIMUM_ROOT_FREE_BYTES)?;
+        check_free_space(&self.persistence().await?, MINIMUM_PERSISTENCE_FREE_BYTES)?;
+
+        let version = DsmVersion::detect().await?;
+        tracing::debug!(%version, "Detected DSM version");
+        if !version.is_tested() {
+            if self.settings.force {
+                tracing::warn!(
+                    "DSM {version} has not been tested with this installer, continuing because `--force` was passed"
+                );
+            } else {
+                return Err(SynologyError::UntestedDsmVersion(version).into());
+            }
+        }
+        if self.init.init == InitSystem::Systemd && !version.has_systemd() {
+            return Err(SynologyError::SystemdUnavailable(version).into());
+        }
+
+        if self.init.init == InitSystem::Systemd && self.init.start_daemon {
+            check_systemd_active()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Synology {
+    /// The directory `/nix` will be bind mounted from, discovering a data volume if none was configured
+    async fn persistence(&self) -> Result<PathBuf, PlannerError> {
+        let persistence = match (&self.persistence, &self.volume) {
+            (Some(persistence), _) => persistence.clone(),
+            (None, Some(volume)) => volume.join("nix"),
+            (None, None) => {
+                let volumes = volumes::data_volumes().await?;
+                for volume in &volumes {
+                    tracing::debug!(
+                        mount_point = %volume.mount_point.display(),
+                        fs_type = %volume.fs_type,
+                        read_only = volume.read_only,
+                        available_bytes = volume.available_bytes,
+                        "Found data volume",
+                    );
+                }
+                let volume =
+                    volumes::largest_writable(&volumes).ok_or(SynologyError::NoDataVolume)?;
+                volume.mount_point.join("nix")
+            },
+        };
+
+        if !persistence.is_absolute() {
+            return Err(SynologyError::AbsolutePathRequired(persistence).into());
+        }
+
+        Ok(persistence)
+    }
+
+    /// Settings the planner adds to `/etc/nix/nix.conf` on top of the user's `extra_conf`
+    fn extra_internal_conf(&self) -> Option<nix_config_parser::NixConfig> {
+        let mut extra_internal_conf = self.settings.determinate_nix.then(determinate_nix_settings);
+
+        // https://github.com/DeterminateSystems/nix-installer/issues/324#issuecomment-1479536235
+        // When tuning `nix.conf` for the kernel, the probe disables syscall filtering on the DSM kernel itself
+        let filter_syscalls = match (self.filter_syscalls, self.settings.tune_nix_conf) {
+            (true, _) => Some("true"),
+            (false, true) => None,
+            (false, false) => Some("false"),
+        };
+        if let Some(filter_syscalls) = filter_syscalls {
+            extra_internal_conf
+                .get_or_insert_with(nix_config_parser::NixConfig::new)
+                .settings_mut()
+                .insert("filter-syscalls".into(), filter_syscalls.into());
+        }
+
+        extra_internal_conf
+    }
+}
+
+impl From<Synology> for BuiltinPlanner {
+    fn from(val: Synology) -> Self {
+        BuiltinPlanner::Synology(val)
+    }
+}
+
+/// Fail if the filesystem holding `path` has less than `required` bytes free
+fn check_free_space(path: &Path, required: u64) -> Result<(), PlannerError> {
+    let available = volumes::available_bytes(path)?;
+    if available < required {
+        return Err(SynologyError::InsufficientSpace {
+            path: path.to_path_buf(),
+            available,
+            required,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Whether the host is a Synology device running DSM
+pub(crate) fn is_synology() -> bool {
+    Path::new(SYNOINFO_CONF).is_file()
+}
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum SynologyError {
+    #[error("`{SYNOINFO_CONF}` was not found, this does not appear to be a Synology device running DSM. Use the `linux` planner instead.")]
+    NotSynology,
+    #[error("`{0}` is not a path that can be canonicalized into an absolute path, bind mounts require an absolute path")]
+    AbsolutePathRequired(PathBuf),
+    #[error("No writable data volume (eg `/volume1`) was found, pass `--volume` or `--persistence` to choose where `/nix` is stored")]
+    NoDataVolume,
+    #[error("`{}` has {} MiB free, at least {} MiB is required. Free up some space, or pass `--volume` or `--persistence` to store `/nix` elsewhere", path.display(), available / 1024 / 1024, required / 1024 / 1024)]
+    InsufficientSpace {
+        path: PathBuf,
+        available: u64,
+        required: u64,
+    },
+    #[error("Reading `{0}`")]
+    ReadMounts(PathBuf, #[source] std::io::Error),
+    #[error("Getting the filesystem statistics of `{0}`")]
+    Statvfs(PathBuf, #[source] nix::errno::Errno),
+    #[error("Reading the DSM version from `{0}`")]
+    ReadDsmVersion(PathBuf, #[source] std::io::Error),
+    #[error(
+        "Could not parse `{0}` from `{}`, found `{1}`",
+        dsm_version::DSM_VERSION_FILE
+    )]
+    ParseDsmVersion(&'static str, String),
+    #[error("DSM {0} has not been tested with this installer (tested major versions: {}), pass `--force` to install anyway", TESTED_DSM_MAJORS.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    UntestedDsmVersion(DsmVersion),
+    #[error("DSM {0} does not use systemd, pass `--init upstart` or `--init synology-rcd` to start the daemon without it")]
+    SystemdUnavailable(DsmVersion),
+}
+
+impl HasExpectedErrors for SynologyError {
+    fn expected<'a>(&'a self) -> Option<Box<dyn std::error::Error + 'a>> {
+        match self {
+            SynologyError::NotSynology => Some(Box::new(self)),
+            SynologyError::AbsolutePathRequired(_) => Some(Box::new(self)),
+            SynologyError::NoDataVolume => Some(Box::new(self)),
+            SynologyError::InsufficientSpace { .. } => Some(Box::new(self)),
+            SynologyError::ReadMounts(_, _) => None,
+            SynologyError::Statvfs(_, _) => None,
+            SynologyError::ReadDsmVersion(_, _) => None,
+            SynologyError::ParseDsmVersion(_, _) => None,
+            SynologyError::UntestedDsmVersion(_) => Some(Box::new(self)),
+            SynologyError::SystemdUnavailable(_) => Some(Box::new(self)),
+        }
+    }
+}
+
+impl From<SynologyError> for PlannerError {
+    fn from(v: SynologyError) -> PlannerError {
+        PlannerError::Custom(Box::new(v))
+    }
+}
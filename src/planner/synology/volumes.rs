@@ -0,0 +1,144 @@
+/*! Discovery of the DSM data volumes (`/volume1`, `/volume2`, ...) which can hold the Nix store */
+use std::path::{Path, PathBuf};
+
+use nix::sys::statvfs::{statvfs, FsFlags};
+
+use super::SynologyError;
+
+pub const PROC_MOUNTS: &str = "/proc/mounts";
+
+/// A mounted DSM data volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataVolume {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub read_only: bool,
+    pub available_bytes: u64,
+}
+
+/// List the data volumes mounted on the host, as found in `/proc/mounts`
+pub async fn data_volumes() -> Result<Vec<DataVolume>, SynologyError> {
+    let mounts = tokio::fs::read_to_string(PROC_MOUNTS)
+        .await
+        .map_err(|e| SynologyError::ReadMounts(PathBuf::from(PROC_MOUNTS), e))?;
+
+    let mut volumes = vec![];
+    for (mount_point, fs_type, read_only) in parse_data_volume_mounts(&mounts) {
+        let stat =
+            statvfs(&mount_point).map_err(|e| SynologyError::Statvfs(mount_point.clone(), e))?;
+        volumes.push(DataVolume {
+            read_only: read_only || stat.flags().contains(FsFlags::ST_RDONLY),
+            available_bytes: stat.blocks_available() as u64 * stat.fragment_size() as u64,
+            mount_point,
+            fs_type,
+        });
+    }
+
+    Ok(volumes)
+}
+
+/// The writable data volume with the most free space
+pub fn largest_writable(volumes: &[DataVolume]) -> Option<&DataVolume> {
+    volumes
+        .iter()
+        .filter(|volume| !volume.read_only)
+        .max_by_key(|volume| volume.available_bytes)
+}
+
+/// The free space available to unprivileged users on the filesystem holding `path`
+///
+/// If `path` does not exist yet, its closest existing ancestor is used.
+pub fn available_bytes(path: &Path) -> Result<u64, SynologyError> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or_else(|| Path::new("/"));
+    let stat = statvfs(existing).map_err(|e| SynologyError::Statvfs(existing.to_path_buf(), e))?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Pick the `/volumeN` mount points out of the contents of `/proc/mounts`, with their filesystem type and whether they are mounted read-only
+fn parse_data_volume_mounts(mounts: &str) -> Vec<(PathBuf, String, bool)> {
+    let mut found: Vec<(PathBuf, String, bool)> = vec![];
+
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(_device), Some(mount_point), Some(fs_type), Some(options)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        let is_data_volume = mount_point
+            .strip_prefix("/volume")
+            .is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()));
+        if !is_data_volume {
+            continue;
+        }
+
+        let read_only = options.split(',').any(|option| option == "ro");
+        let mount_point = PathBuf::from(mount_point);
+
+        // Later entries shadow earlier ones mounted on the same path
+        found.retain(|(existing, _, _)| *existing != mount_point);
+        found.push((mount_point, fs_type.to_string(), read_only));
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{largest_writable, parse_data_volume_mounts, DataVolume};
+
+    #[test]
+    fn parses_data_volume_mounts() {
+        let mounts = "\
+            /dev/md0 / ext4 rw,relatime,data=ordered 0 0\n\
+            none /dev devtmpfs rw,nosuid,noexec,relatime,size=3983376k,mode=755 0 0\n\
+            /dev/mapper/cachedev_0 /volume1 btrfs rw,nodev,relatime,ssd,synoacl,space_cache=v2 0 0\n\
+            /dev/mapper/cachedev_1 /volume2 ext4 ro,nodev,relatime,synoacl,data=ordered 0 0\n\
+            /dev/sdq1 /volumeUSB1/usbshare vfat rw,relatime 0 0\n\
+            /dev/mapper/cachedev_0 /volume1/@docker btrfs rw,nodev,relatime 0 0\n\
+        ";
+
+        assert_eq!(
+            parse_data_volume_mounts(mounts),
+            vec![
+                (PathBuf::from("/volume1"), "btrfs".to_string(), false),
+                (PathBuf::from("/volume2"), "ext4".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_largest_writable_volume() {
+        let volumes = vec![
+            DataVolume {
+                mount_point: PathBuf::from("/volume1"),
+                fs_type: "btrfs".into(),
+                read_only: false,
+                available_bytes: 10,
+            },
+            DataVolume {
+                mount_point: PathBuf::from("/volume2"),
+                fs_type: "ext4".into(),
+                read_only: true,
+                available_bytes: 1000,
+            },
+            DataVolume {
+                mount_point: PathBuf::from("/volume3"),
+                fs_type: "btrfs".into(),
+                read_only: false,
+                available_bytes: 100,
+            },
+        ];
+
+        assert_eq!(
+            largest_writable(&volumes).map(|volume| volume.mount_point.clone()),
+            Some(PathBuf::from("/volume3"))
+        );
+    }
+}
@@ -3,6 +3,20 @@ use std::{process::Output, time::SystemTime};
 use tokio::process::Command;
 use which::which;
 
+/// The Nix system of the host, as in `nix-2.23.3-armv7l-linux.tar.xz`
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub const SYSTEM: &str = "x86_64-linux";
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub const SYSTEM: &str = "i686-linux";
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+pub const SYSTEM: &str = "aarch64-linux";
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub const SYSTEM: &str = "armv7l-linux";
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+pub const SYSTEM: &str = "x86_64-darwin";
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+pub const SYSTEM: &str = "aarch64-darwin";
+
 #[non_exhaustive]
 #[derive(thiserror::Error, Debug, strum::IntoStaticStr)]
 pub enum SelfTestError {
@@ -91,15 +105,6 @@ impl Shell {
             },
         };
 
-        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-        const SYSTEM: &str = "x86_64-linux";
-        #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
-        const SYSTEM: &str = "aarch64-linux";
-        #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-        const SYSTEM: &str = "x86_64-darwin";
-        #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
-        const SYSTEM: &str = "aarch64-darwin";
-
         let timestamp_millis = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)?
             .as_millis();
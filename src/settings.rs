@@ -1,7 +1,13 @@
 /*! Configurable knobs and their related errors
 */
-use std::{collections::HashMap, fmt::Display, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 #[cfg(feature = "cli")]
 use clap::{
     error::{ContextKind, ContextValue},
@@ -15,6 +21,17 @@ use url::Url;
 
 pub const SCRATCH_DIR: &str = "/nix/temp-install-dir";
 
+/// `path` inside `root`, or `path` itself when installing into the running system
+///
+/// `path` is taken as absolute, so `rooted(Some("/mnt"), "/nix")` is `/mnt/nix`.
+pub fn rooted(root: Option<&Path>, path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    }
+}
+
 pub const NIX_TARBALL_PATH: &str = env!("NIX_INSTALLER_TARBALL_PATH");
 /// The NIX_INSTALLER_TARBALL_PATH environment variable should point to a target-appropriate
 /// Nix installation tarball, like nix-2.21.2-aarch64-darwin.tar.xz. The contents are embedded
@@ -40,6 +57,11 @@ pub enum InitSystem {
     None,
     Systemd,
     Launchd,
+    /// A job in `/etc/init/`, for hosts like DSM 6 which use upstart
+    Upstart,
+    /// A script in `/usr/local/etc/rc.d/`, which DSM runs at startup and shutdown
+    #[cfg_attr(feature = "cli", value(name = "synology-rcd"))]
+    SynologyRcD,
 }
 
 impl std::fmt::Display for InitSystem {
@@ -48,10 +70,39 @@ impl std::fmt::Display for InitSystem {
             InitSystem::None => write!(f, "none"),
             InitSystem::Systemd => write!(f, "systemd"),
             InitSystem::Launchd => write!(f, "launchd"),
+            InitSystem::Upstart => write!(f, "upstart"),
+            InitSystem::SynologyRcD => write!(f, "synology-rcd"),
         }
     }
 }
 
+/// How often scheduled garbage collection runs
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum GcSchedule {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl std::fmt::Display for GcSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GcSchedule::Daily => write!(f, "daily"),
+            GcSchedule::Weekly => write!(f, "weekly"),
+            GcSchedule::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+/// Parse a `nix-collect-garbage --delete-older-than` period, which Nix only accepts in days (eg. `30d`)
+pub fn parse_gc_older_than(input: &str) -> Result<String, String> {
+    match input.strip_suffix('d').map(str::parse::<u32>) {
+        Some(Ok(_)) => Ok(input.to_string()),
+        _ => Err(format!("`{input}` is not a number of days, such as `30d`")),
+    }
+}
+
 /** Common settings used by all [`BuiltinPlanner`](crate::planner::BuiltinPlanner)s
 
 Settings which only apply to certain [`Planner`](crate::planner::Planner)s should be located in the planner.
@@ -85,6 +136,22 @@ pub struct CommonSettings {
     )]
     pub modify_profile: bool,
 
+    /// Probe the host's kernel and add the `nix.conf` settings it needs, such as `filter-syscalls = false` on kernels older than 4.14
+    ///
+    /// Each of these relaxes a protection of Nix, such as its sandbox, and is logged as a warning when added.
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            action(ArgAction::SetFalse),
+            default_value = "true",
+            global = true,
+            env = "NIX_INSTALLER_NO_TUNE_NIX_CONF",
+            long = "no-tune-nix-conf"
+        )
+    )]
+    #[serde(default = "default_tune_nix_conf")]
+    pub tune_nix_conf: bool,
+
     /// The Nix build group name
     #[cfg_attr(
         feature = "cli",
@@ -160,6 +227,76 @@ pub struct CommonSettings {
     )]
     pub nix_package_url: Option<UrlOrPath>,
 
+    /// The SHA-256 the Nix package must have, in hex or as an SRI hash (`sha256-<base64>`), checked before it is unpacked
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_NIX_PACKAGE_SHA256", global = true)
+    )]
+    #[serde(default)]
+    pub nix_package_sha256: Option<NixPackageSha256>,
+
+    /// The URL or path of a minisign signature of the Nix package, checked against `--nix-package-public-key` before it is unpacked
+    ///
+    /// This is the `.minisig` file `minisign -S -m <tarball>` writes.
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_NIX_PACKAGE_SIGNATURE", global = true, value_parser = clap::value_parser!(UrlOrPath), requires = "nix_package_public_key")
+    )]
+    #[serde(default)]
+    pub nix_package_signature: Option<UrlOrPath>,
+
+    /// The minisign public key the Nix package must be signed with, as the base64 line of `minisign.pub` (eg. `RWQ...`)
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_NIX_PACKAGE_PUBLIC_KEY",
+            global = true,
+            requires = "nix_package_signature"
+        )
+    )]
+    #[serde(default)]
+    pub nix_package_public_key: Option<NixPackagePublicKey>,
+
+    /// Mirrors of `--nix-package-url`, tried in order if it cannot be fetched; they must serve the same tarball
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "nix-package-mirror",
+            action = ArgAction::Append,
+            value_delimiter = ',',
+            env = "NIX_INSTALLER_NIX_PACKAGE_MIRRORS",
+            global = true,
+            requires = "nix_package_url"
+        )
+    )]
+    #[serde(default)]
+    pub nix_package_mirrors: Vec<Url>,
+
+    /// How many times fetching the Nix package from each URL is retried, waiting twice as long before each retry
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_NIX_PACKAGE_RETRIES",
+            default_value_t = 3,
+            global = true
+        )
+    )]
+    #[serde(default = "default_nix_package_retries")]
+    pub nix_package_retries: u32,
+
+    /// A directory of Nix packages, named by their SHA-256, to use instead of fetching; fetched ones are added to it
+    ///
+    /// A package is reused when it matches `--nix-package-sha256`. Without one, `--nix-package-url` is fetched again, as it
+    /// may serve a newer package, and the package last fetched from it is only used if fetching fails, such as offline.
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_NIX_PACKAGE_CACHE", global = true)
+    )]
+    #[serde(default)]
+    pub nix_package_cache: Option<PathBuf>,
+
     /// The proxy to use (if any); valid proxy bases are `https://$URL`, `http://$URL` and `socks5://$URL`
     #[cfg_attr(feature = "cli", clap(long, env = "NIX_INSTALLER_PROXY"))]
     pub proxy: Option<Url>,
@@ -185,6 +322,17 @@ pub struct CommonSettings {
     )]
     pub force: bool,
 
+    /// Install into the filesystem mounted at this directory (such as a disk image or chroot) instead of the running system
+    ///
+    /// Users, groups and services are set up by editing files under the root rather than with the host's tools, and no
+    /// services are started.
+    #[cfg_attr(
+        feature = "cli",
+        clap(long, env = "NIX_INSTALLER_ROOT", global = true, default_value = None)
+    )]
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+
     #[cfg(feature = "diagnostics")]
     /// Relate the install diagnostic to a specific value
     #[cfg_attr(
@@ -284,6 +432,14 @@ pub fn is_macos_15_or_later() -> bool {
     maybe_major_version.is_some_and(|&v| v >= 15)
 }
 
+fn default_nix_package_retries() -> u32 {
+    3
+}
+
+fn default_tune_nix_conf() -> bool {
+    true
+}
+
 fn default_nix_build_user_id_base() -> u32 {
     use target_lexicon::OperatingSystem;
 
@@ -305,7 +461,7 @@ impl CommonSettings {
     pub async fn default() -> Result<Self, InstallSettingsError> {
         let nix_build_user_prefix;
 
-        use target_lexicon::{Architecture, OperatingSystem};
+        use target_lexicon::{Architecture, ArmArchitecture, OperatingSystem};
         match (Architecture::host(), OperatingSystem::host()) {
             (Architecture::X86_64, OperatingSystem::Linux) => {
                 nix_build_user_prefix = "nixbld";
@@ -316,6 +472,12 @@ impl CommonSettings {
             (Architecture::Aarch64(_), OperatingSystem::Linux) => {
                 nix_build_user_prefix = "nixbld";
             },
+            (
+                Architecture::Arm(ArmArchitecture::Armv7 | ArmArchitecture::Armv7a),
+                OperatingSystem::Linux,
+            ) => {
+                nix_build_user_prefix = "nixbld";
+            },
             (Architecture::X86_64, OperatingSystem::MacOSX { .. })
             | (Architecture::X86_64, OperatingSystem::Darwin) => {
                 nix_build_user_prefix = "_nixbld";
@@ -334,15 +496,23 @@ impl CommonSettings {
         Ok(Self {
             determinate_nix: false,
             modify_profile: true,
+            tune_nix_conf: true,
             nix_build_group_name: String::from("nixbld"),
             nix_build_group_id: 30_000,
             nix_build_user_id_base: default_nix_build_user_id_base(),
             nix_build_user_count: 32,
             nix_build_user_prefix: nix_build_user_prefix.to_string(),
             nix_package_url: None,
+            nix_package_sha256: None,
+            nix_package_signature: None,
+            nix_package_public_key: None,
+            nix_package_mirrors: Default::default(),
+            nix_package_retries: default_nix_package_retries(),
+            nix_package_cache: None,
             proxy: Default::default(),
             extra_conf: Default::default(),
             force: false,
+            root: None,
             ssl_cert_file: Default::default(),
             #[cfg(feature = "diagnostics")]
             diagnostic_attribution: None,
@@ -356,15 +526,23 @@ impl CommonSettings {
         let Self {
             determinate_nix,
             modify_profile,
+            tune_nix_conf,
             nix_build_group_name,
             nix_build_group_id,
             nix_build_user_prefix,
             nix_build_user_id_base,
             nix_build_user_count,
             nix_package_url,
+            nix_package_sha256,
+            nix_package_signature,
+            nix_package_public_key,
+            nix_package_mirrors,
+            nix_package_retries,
+            nix_package_cache,
             proxy,
             extra_conf,
             force,
+            root,
             ssl_cert_file,
             #[cfg(feature = "diagnostics")]
                 diagnostic_attribution: _,
@@ -381,6 +559,7 @@ impl CommonSettings {
             "modify_profile".into(),
             serde_json::to_value(modify_profile)?,
         );
+        map.insert("tune_nix_conf".into(), serde_json::to_value(tune_nix_conf)?);
         map.insert(
             "nix_build_group_name".into(),
             serde_json::to_value(nix_build_group_name)?,
@@ -405,10 +584,35 @@ impl CommonSettings {
             "nix_package_url".into(),
             serde_json::to_value(nix_package_url)?,
         );
+        map.insert(
+            "nix_package_sha256".into(),
+            serde_json::to_value(nix_package_sha256)?,
+        );
+        map.insert(
+            "nix_package_signature".into(),
+            serde_json::to_value(nix_package_signature)?,
+        );
+        map.insert(
+            "nix_package_public_key".into(),
+            serde_json::to_value(nix_package_public_key)?,
+        );
+        map.insert(
+            "nix_package_mirrors".into(),
+            serde_json::to_value(nix_package_mirrors)?,
+        );
+        map.insert(
+            "nix_package_retries".into(),
+            serde_json::to_value(nix_package_retries)?,
+        );
+        map.insert(
+            "nix_package_cache".into(),
+            serde_json::to_value(nix_package_cache)?,
+        );
         map.insert("proxy".into(), serde_json::to_value(proxy)?);
         map.insert("ssl_cert_file".into(), serde_json::to_value(ssl_cert_file)?);
         map.insert("extra_conf".into(), serde_json::to_value(extra_conf)?);
         map.insert("force".into(), serde_json::to_value(force)?);
+        map.insert("root".into(), serde_json::to_value(root)?);
 
         #[cfg(feature = "diagnostics")]
         map.insert(
@@ -441,6 +645,42 @@ async fn linux_detect_systemd_started() -> bool {
     started
 }
 
+/// Whether upstart is the running init, as on DSM 6
+fn linux_detect_upstart() -> bool {
+    use std::process::Stdio;
+
+    // `initctl` also ships with some systemd hosts, only upstart's mentions itself in the version
+    std::process::Command::new("initctl")
+        .arg("version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .is_some_and(|output| String::from_utf8_lossy(&output.stdout).contains("upstart"))
+}
+
+/// The init system to configure when none is passed, systemd unless the host boots with upstart
+#[cfg(all(target_os = "linux", feature = "cli"))]
+fn linux_default_init() -> InitSystem {
+    if !std::path::Path::new("/run/systemd/system").exists() && linux_detect_upstart() {
+        InitSystem::Upstart
+    } else {
+        InitSystem::Systemd
+    }
+}
+
+/// The init system running on this Linux host and whether the daemon can be started now
+async fn linux_detect_init() -> (InitSystem, bool) {
+    if linux_detect_systemd_started().await {
+        (InitSystem::Systemd, true)
+    } else if linux_detect_upstart() {
+        (InitSystem::Upstart, true)
+    } else {
+        (InitSystem::Systemd, false)
+    }
+}
+
 #[serde_with::serde_as]
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 #[cfg_attr(feature = "cli", derive(clap::Parser))]
@@ -453,7 +693,7 @@ pub struct InitSettings {
     )]
     #[cfg_attr(
         all(target_os = "linux", feature = "cli"),
-        clap(default_value_t = InitSystem::Systemd)
+        clap(default_value_t = linux_default_init())
     )]
     pub init: InitSystem,
 
@@ -470,22 +710,41 @@ pub struct InitSettings {
         )
     )]
     pub start_daemon: bool,
+
+    /// Periodically run `nix-collect-garbage`, with a systemd timer or, without systemd, an `/etc/crontab` entry
+    #[cfg_attr(
+        feature = "cli",
+        clap(value_enum, long, env = "NIX_INSTALLER_GC_SCHEDULE")
+    )]
+    #[serde(default)]
+    pub gc_schedule: Option<GcSchedule>,
+
+    /// Only delete store paths which have been unused for this many days, eg. `30d`
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            env = "NIX_INSTALLER_GC_OLDER_THAN",
+            requires = "gc_schedule",
+            value_parser = parse_gc_older_than
+        )
+    )]
+    #[serde(default)]
+    pub gc_older_than: Option<String>,
 }
 
 impl InitSettings {
     /// The default settings for the given Architecture & Operating System
     pub async fn default() -> Result<Self, InstallSettingsError> {
-        use target_lexicon::{Architecture, OperatingSystem};
+        use target_lexicon::{Architecture, ArmArchitecture, OperatingSystem};
         let (init, start_daemon) = match (Architecture::host(), OperatingSystem::host()) {
-            (Architecture::X86_64, OperatingSystem::Linux) => {
-                (InitSystem::Systemd, linux_detect_systemd_started().await)
-            },
-            (Architecture::X86_32(_), OperatingSystem::Linux) => {
-                (InitSystem::Systemd, linux_detect_systemd_started().await)
-            },
-            (Architecture::Aarch64(_), OperatingSystem::Linux) => {
-                (InitSystem::Systemd, linux_detect_systemd_started().await)
-            },
+            (Architecture::X86_64, OperatingSystem::Linux) => linux_detect_init().await,
+            (Architecture::X86_32(_), OperatingSystem::Linux) => linux_detect_init().await,
+            (Architecture::Aarch64(_), OperatingSystem::Linux) => linux_detect_init().await,
+            (
+                Architecture::Arm(ArmArchitecture::Armv7 | ArmArchitecture::Armv7a),
+                OperatingSystem::Linux,
+            ) => linux_detect_init().await,
             (Architecture::X86_64, OperatingSystem::MacOSX { .. })
             | (Architecture::X86_64, OperatingSystem::Darwin) => (InitSystem::Launchd, true),
             (Architecture::Aarch64(_), OperatingSystem::MacOSX { .. })
@@ -497,16 +756,28 @@ impl InitSettings {
             },
         };
 
-        Ok(Self { init, start_daemon })
+        Ok(Self {
+            init,
+            start_daemon,
+            gc_schedule: None,
+            gc_older_than: None,
+        })
     }
 
     /// A listing of the settings, suitable for [`Planner::settings`](crate::planner::Planner::settings)
     pub fn settings(&self) -> Result<HashMap<String, serde_json::Value>, InstallSettingsError> {
-        let Self { init, start_daemon } = self;
+        let Self {
+            init,
+            start_daemon,
+            gc_schedule,
+            gc_older_than,
+        } = self;
         let mut map = HashMap::default();
 
         map.insert("init".into(), serde_json::to_value(init)?);
         map.insert("start_daemon".into(), serde_json::to_value(start_daemon)?);
+        map.insert("gc_schedule".into(), serde_json::to_value(gc_schedule)?);
+        map.insert("gc_older_than".into(), serde_json::to_value(gc_older_than)?);
         Ok(map)
     }
 
@@ -521,6 +792,18 @@ impl InitSettings {
         self.start_daemon = toggle;
         self
     }
+
+    /// Periodically collect garbage (if an init is configured)
+    pub fn gc_schedule(&mut self, schedule: Option<GcSchedule>) -> &mut Self {
+        self.gc_schedule = schedule;
+        self
+    }
+
+    /// Only collect store paths unused for this long, eg. `30d`
+    pub fn gc_older_than(&mut self, older_than: Option<String>) -> &mut Self {
+        self.gc_older_than = older_than;
+        self
+    }
 }
 
 /// An error originating from a [`Planner::settings`](crate::planner::Planner::settings)
@@ -632,6 +915,95 @@ impl clap::builder::TypedValueParser for UrlOrPath {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("`{0}` is not a SHA-256, expected 64 hex digits or an SRI hash (`sha256-<base64>`)")]
+    InvalidSha256(String),
+    #[error("`{0}` is not a minisign public key, expected the base64 line of `minisign.pub`")]
+    InvalidPublicKey(String),
+}
+
+/// The SHA-256 of a Nix package, written as an SRI hash (`sha256-<base64>`)
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, serde_with::SerializeDisplay, serde_with::DeserializeFromStr,
+)]
+pub struct NixPackageSha256([u8; 32]);
+
+impl NixPackageSha256 {
+    /// The SHA-256 of `data`
+    pub fn of(data: &[u8]) -> Self {
+        Self::from_digest(ring::digest::digest(&ring::digest::SHA256, data))
+    }
+
+    /// The SHA-256 in hex, as `sha256sum` prints it
+    pub fn hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    pub(crate) fn from_digest(digest: ring::digest::Digest) -> Self {
+        let mut sha256 = [0; 32];
+        sha256.copy_from_slice(digest.as_ref());
+        Self(sha256)
+    }
+}
+
+impl Display for NixPackageSha256 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256-{}", BASE64.encode(self.0))
+    }
+}
+
+impl FromStr for NixPackageSha256 {
+    type Err = IntegrityError;
+
+    /// Parse hex, as `sha256sum` prints it, or an SRI hash
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || IntegrityError::InvalidSha256(s.to_string());
+        let bytes = match s.strip_prefix("sha256-") {
+            Some(encoded) => BASE64.decode(encoded).map_err(|_| invalid())?,
+            None if s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()) => (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid())?,
+            None => return Err(invalid()),
+        };
+        bytes.try_into().map(Self).map_err(|_| invalid())
+    }
+}
+
+/// A [minisign](https://jedisct1.github.io/minisign/) public key, written as the base64 line of `minisign.pub`
+#[derive(
+    Debug, PartialEq, Eq, Clone, serde_with::SerializeDisplay, serde_with::DeserializeFromStr,
+)]
+pub struct NixPackagePublicKey {
+    encoded: String,
+    key: minisign_verify::PublicKey,
+}
+
+impl NixPackagePublicKey {
+    pub(crate) fn minisign(&self) -> &minisign_verify::PublicKey {
+        &self.key
+    }
+}
+
+impl Display for NixPackagePublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.encoded)
+    }
+}
+
+impl FromStr for NixPackagePublicKey {
+    type Err = IntegrityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.trim().to_string();
+        let key = minisign_verify::PublicKey::from_base64(&encoded)
+            .map_err(|_| IntegrityError::InvalidPublicKey(s.to_string()))?;
+        Ok(Self { encoded, key })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize, Clone)]
 pub enum UrlOrPathOrString {
     Url(Url),
@@ -705,7 +1077,31 @@ impl crate::diagnostics::ErrorDiagnostic for InstallSettingsError {
 
 #[cfg(test)]
 mod tests {
-    use super::{FromStr, PathBuf, Url, UrlOrPath, UrlOrPathOrString};
+    use super::{
+        parse_gc_older_than, rooted, CommonSettings, FromStr, NixPackageSha256, Path, PathBuf, Url,
+        UrlOrPath, UrlOrPathOrString,
+    };
+
+    #[test]
+    fn rooted_paths() {
+        assert_eq!(rooted(None, "/nix"), PathBuf::from("/nix"));
+        assert_eq!(
+            rooted(Some(Path::new("/mnt/image")), "/etc/nix/nix.conf"),
+            PathBuf::from("/mnt/image/etc/nix/nix.conf")
+        );
+        assert_eq!(
+            rooted(Some(Path::new("/mnt/image")), "nix"),
+            PathBuf::from("/mnt/image/nix")
+        );
+    }
+
+    #[test]
+    fn gc_older_than_parses() {
+        assert_eq!(parse_gc_older_than("30d"), Ok("30d".to_string()));
+        assert!(parse_gc_older_than("30").is_err());
+        assert!(parse_gc_older_than("2w").is_err());
+        assert!(parse_gc_older_than("d").is_err());
+    }
 
     #[test]
     fn url_or_path_or_string_parses() -> Result<(), Box<dyn std::error::Error>> {
@@ -746,6 +1142,37 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn nix_package_sha256() -> eyre::Result<()> {
+        let hex = "8f434346648f6b96df89dda901c5176b10a6d83961dd3c1ac88b59b2dc327aa4";
+        let sri = "sha256-j0NDRmSPa5bfid2pAcUXaxCm2Dlh3TwayItZstwyeqQ=";
+        assert_eq!(
+            NixPackageSha256::from_str(hex)?,
+            NixPackageSha256::of(b"hi")
+        );
+        assert_eq!(
+            NixPackageSha256::from_str(sri)?,
+            NixPackageSha256::of(b"hi")
+        );
+        assert_eq!(NixPackageSha256::of(b"hi").to_string(), sri);
+        assert_eq!(NixPackageSha256::of(b"hi").hex(), hex);
+        assert!(NixPackageSha256::from_str(&hex[1..]).is_err());
+        assert!(NixPackageSha256::from_str("sha256-aGk=").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nix_package_retries_defaults_in_older_receipts() -> eyre::Result<()> {
+        let mut settings = serde_json::to_value(CommonSettings::default().await?)?;
+        settings
+            .as_object_mut()
+            .expect("Settings are an object")
+            .remove("nix_package_retries");
+        let settings: CommonSettings = serde_json::from_value(settings)?;
+        assert_eq!(settings.nix_package_retries, 3);
+        Ok(())
+    }
 }
 
 pub fn determinate_nix_settings() -> nix_config_parser::NixConfig {
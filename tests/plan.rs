@@ -1,29 +1,56 @@
-use nix_installer::InstallPlan;
+use nix_installer::{InstallPlan, RECEIPT_SCHEMA_VERSION};
 
+// Receipts written by the tagged `nix-installer` 0.20.2 release, from before `schema_version` was recorded
 const LINUX: &str = include_str!("./fixtures/linux/linux.json");
 const STEAM_DECK: &str = include_str!("./fixtures/linux/steam-deck.json");
 const MACOS: &str = include_str!("./fixtures/macos/macos.json");
+// No tagged release has the synology planner, so this receipt is at the current schema rather than a historical one
+const SYNOLOGY: &str = include_str!("./fixtures/linux/synology.json");
+
+/// Ensure a historical receipt still parses, is migrated to the current schema, and is accepted
+fn migrates(receipt: &str) -> eyre::Result<()> {
+    let plan = InstallPlan::from_receipt(receipt)?;
+    plan.check_compatible()?;
+
+    let written = serde_json::to_value(&plan)?;
+    assert_eq!(
+        written["schema_version"],
+        serde_json::json!(RECEIPT_SCHEMA_VERSION)
+    );
+    // Writing the migrated receipt again must not change it
+    let rewritten = serde_json::to_value(InstallPlan::from_receipt(&written.to_string())?)?;
+    assert_eq!(written, rewritten);
+    Ok(())
+}
 
 // Ensure existing plans still parse
-// If this breaks and you need to update the fixture, disable these tests, bump `nix_installer` to a new version, and update the plans.
+// If this breaks, do not update the fixture: add a migration (see `RECEIPT_SCHEMA_VERSION`) which upgrades it instead.
 #[test]
 fn plan_compat_linux() -> eyre::Result<()> {
-    let _: InstallPlan = serde_json::from_str(LINUX)?;
-    Ok(())
+    migrates(LINUX)
 }
 
 // Ensure existing plans still parse
-// If this breaks and you need to update the fixture, disable these tests, bump `nix_installer` to a new version, and update the plans.
+// If this breaks, do not update the fixture: add a migration (see `RECEIPT_SCHEMA_VERSION`) which upgrades it instead.
 #[test]
 fn plan_compat_steam_deck() -> eyre::Result<()> {
-    let _: InstallPlan = serde_json::from_str(STEAM_DECK)?;
-    Ok(())
+    migrates(STEAM_DECK)
+}
+
+// Ensure plans of the synology planner still parse
+// If this breaks after bumping `RECEIPT_SCHEMA_VERSION`, do not update the fixture: add a migration which upgrades it instead.
+#[test]
+fn plan_compat_synology() -> eyre::Result<()> {
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(SYNOLOGY)?["schema_version"],
+        serde_json::json!(1)
+    );
+    migrates(SYNOLOGY)
 }
 
 // Ensure existing plans still parse
-// If this breaks and you need to update the fixture, disable these tests, bump `nix_installer` to a new version, and update the plans.
+// If this breaks, do not update the fixture: add a migration (see `RECEIPT_SCHEMA_VERSION`) which upgrades it instead.
 #[test]
 fn plan_compat_macos() -> eyre::Result<()> {
-    let _: InstallPlan = serde_json::from_str(MACOS)?;
-    Ok(())
+    migrates(MACOS)
 }